@@ -7,33 +7,42 @@ extern crate lazy_static;
 extern crate log;
 extern crate simplelog;
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
 
 mod protocol;
 mod proxy;
 mod systems;
 
 use crate::proxy::{
-    config::SplinterConfig,
+    config::{SplinterConfig, CONFIG_FILENAME},
     logging as splinter_logging,
     SplinterProxy,
 };
 
-const CONFIG_FILENAME: &str = "./config.ron";
-
 fn main() -> anyhow::Result<()> {
-    splinter_logging::init()?;
-    let config = match SplinterConfig::from_file(CONFIG_FILENAME) {
-        Ok(config) => config,
-        Err(e) => {
-            warn!("Failed to read file at \"{}\": {}", CONFIG_FILENAME, e);
-            SplinterConfig::default()
-        }
+    let check = std::env::args().any(|arg| arg == "--check");
+    // the log format and level are in the config, so nothing is logged until it is read
+    let config_exists = Path::new(CONFIG_FILENAME).exists();
+    let config = if check && !config_exists {
+        // a check has no business writing the example config
+        SplinterConfig::default()
+    } else {
+        SplinterConfig::load(CONFIG_FILENAME)?
     };
-    if let Err(e) = config.to_file(CONFIG_FILENAME) {
-        warn!("Failed to write config to \"{}\": {}", CONFIG_FILENAME, e);
+    splinter_logging::init(&config.logging)?;
+    if !check && !config_exists {
+        info!("Wrote an example config to \"{}\"", CONFIG_FILENAME);
     }
     info!("Loaded configuration");
+    protocol::v_cur::load_data(&config.data_paths)
+        .with_context(|| "Failed to load the block, item and entity data")?;
+    if check {
+        protocol::v_cur::self_test().with_context(|| "Self-test failed")?;
+        info!("Self-test passed");
+        return Ok(());
+    }
     let proxy = SplinterProxy::new(config)?;
     let proxy_arc = Arc::new(proxy);
     info!("Starting Splinter Proxy");
@@ -47,11 +47,24 @@ impl<'a> LazyDeserializedPacket<'a> {
         let res = self.de_packet.as_mut().unwrap();
         res.as_mut()
     }
+    /// Gets the deserialized packet without deserializing it, if it has been deserialized
+    /// successfully
+    pub fn deserialized(&self) -> Option<&PacketLatest> {
+        self.de_packet.as_ref().and_then(|res| res.as_ref().ok())
+    }
     /// Returns ownership to the deserialized packet. Packet may be deserialized during this call
     pub fn into_packet(mut self) -> Result<PacketLatest, PacketErr> {
         self.de();
         self.de_packet.unwrap()
     }
+    /// Gets the raw packet, if this packet hasn't been deserialized
+    pub fn raw_packet(&self) -> Option<&RawPacketLatest<'a>> {
+        if self.is_deserialized() {
+            None
+        } else {
+            self.raw_packet.as_ref()
+        }
+    }
     pub fn into_raw_packet(self) -> Option<RawPacketLatest<'a>> {
         if self.is_deserialized() {
             None
@@ -59,6 +72,20 @@ impl<'a> LazyDeserializedPacket<'a> {
             self.raw_packet
         }
     }
+    /// Gets the error from deserializing this packet, if deserialization was attempted and failed
+    pub fn error(&self) -> Option<&PacketErr> {
+        self.de_packet.as_ref().and_then(|res| res.as_ref().err())
+    }
+    /// Discards a failed deserialization so that the packet is treated as its raw form again.
+    /// Returns whether there was a raw packet to fall back on
+    pub fn discard_error(&mut self) -> bool {
+        if self.raw_packet.is_some() && self.error().is_some() {
+            self.de_packet = None;
+            true
+        } else {
+            false
+        }
+    }
     /// Checks if this packet has an already deserialized version
     pub fn is_deserialized(&self) -> bool {
         self.de_packet.is_some()
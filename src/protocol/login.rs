@@ -1,31 +1,43 @@
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     net::SocketAddr,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use craftio_rs::CraftIo;
+use craftio_rs::{CraftAsyncReader, CraftIo};
 use futures_lite::future;
-use smol::lock::Mutex;
 
-use super::{v_cur::send_position_set, AsyncCraftConnection, AsyncCraftWriter, Tags};
+use super::{
+    state::ConnectionState, v_cur::send_position_set, AsyncCraftConnection, AsyncCraftReader,
+    AsyncCraftWriter, Tags,
+};
 use crate::{
     protocol::{
         current::{
+            proto::PlayJoinGameSpec,
             protocol::{PacketDirection, State},
             types::Vec3,
             uuid::UUID4,
+            RawPacketLatest,
         },
         v_cur,
     },
     proxy::{
         client::{ClientSettings, SplinterClient},
+        config::JoinTarget,
+        logging::Instrument,
         mapping::uuid_from_name,
-        server::SplinterServerConnection,
+        server::{SplinterServer, SplinterServerConnection},
         SplinterProxy,
     },
-    systems::{playersave::DEFAULT_SPAWN_POSITION, zoning::world_to_chunk_position},
+    systems::{
+        commands::CommandSender,
+        keepalive::unix_time_millis,
+        playersave::DEFAULT_SPAWN_POSITION,
+        zoning::world_to_chunk_position,
+    },
 };
 
 pub struct ClientBuilder<'a> {
@@ -33,16 +45,34 @@ pub struct ClientBuilder<'a> {
     pub name: Option<String>,
     pub uuid: Option<UUID4>,
     pub client_addr: SocketAddr,
+    /// Protocol version the client gave in its handshake
+    pub protocol_version: i32,
     pub client_writer: AsyncCraftWriter,
     pub server_conn: Option<SplinterServerConnection>,
     pub settings: Option<ClientSettings>,
     pub position: Option<Vec3<f64>>,
+    pub state: ConnectionState,
+    /// Id of the server the client should have joined, if it no longer exists and the client was
+    /// sent to another server
+    pub redirected_from: Option<u64>,
+    /// Hotbar slot the client had selected when its player data was last saved
+    pub saved_held_slot: Option<i8>,
+    /// Serialized item stacks in the client's inventory when its player data was last saved. They
+    /// stand in for the tracked inventory until the server sends one, so that saving before then
+    /// keeps them
+    pub saved_inventory: HashMap<i16, Vec<u8>>,
+    /// Server the client was held in limbo for because it couldn't be reached during login. The
+    /// client is already in play once this is set
+    pub limbo_server: Option<u64>,
+    /// Join game packet the client was sent, as the client got it
+    pub join_game: Option<PlayJoinGameSpec>,
 }
 
 impl<'a> ClientBuilder<'a> {
     pub fn new(
         proxy: &'a Arc<SplinterProxy>,
         client_addr: SocketAddr,
+        protocol_version: i32,
         client_writer: AsyncCraftWriter,
     ) -> Self {
         Self {
@@ -50,59 +80,196 @@ impl<'a> ClientBuilder<'a> {
             name: None,
             uuid: None,
             client_addr,
+            protocol_version,
             server_conn: None,
             client_writer,
             settings: None,
             position: None,
+            state: ConnectionState::Login,
+            redirected_from: None,
+            saved_held_slot: None,
+            saved_inventory: HashMap::new(),
+            limbo_server: None,
+            join_game: None,
         }
     }
     pub async fn login_start(&mut self, name: impl AsRef<str>) -> anyhow::Result<()> {
+        let config = self.proxy.config();
+        if self.proxy.in_maintenance() && !config.maintenance.is_exempt(name.as_ref()) {
+            v_cur::send_login_disconnect(
+                &mut self.client_writer,
+                &config.kick_messages.maintenance,
+            )
+            .await?;
+            bail!(
+                "Refused login from \"{}\", {}: in maintenance mode",
+                name.as_ref(),
+                self.client_addr
+            );
+        }
+        // logins are in offline mode: the name isn't verified with the session server, so the
+        // uuid is derived from it the same way offline servers do
+        let uuid = uuid_from_name(name.as_ref());
+        let ban_reason = self
+            .proxy
+            .banlist
+            .read()
+            .await
+            .find(name.as_ref(), uuid)
+            .map(|entry| entry.reason.clone().unwrap_or_default());
+        if let Some(reason) = ban_reason {
+            let message = config.kick_messages.banned.replace("{reason}", &reason);
+            v_cur::send_login_disconnect(&mut self.client_writer, &message).await?;
+            bail!(
+                "Refused login from \"{}\", {}: banned",
+                name.as_ref(),
+                self.client_addr
+            );
+        }
+        if config.whitelist_enabled
+            && self
+                .proxy
+                .whitelist
+                .read()
+                .await
+                .find(name.as_ref(), uuid)
+                .is_none()
+        {
+            v_cur::send_login_disconnect(
+                &mut self.client_writer,
+                &config.kick_messages.not_whitelisted,
+            )
+            .await?;
+            bail!(
+                "Refused login from \"{}\", {}: not whitelisted",
+                name.as_ref(),
+                self.client_addr
+            );
+        }
         self.name = Some(name.as_ref().to_owned());
-        self.uuid = Some(uuid_from_name(name));
+        self.uuid = Some(uuid);
         info!(
-            "\"{}\" logging in from {}",
+            "\"{}\" logging in from {} with protocol version {}",
             self.name.as_ref().unwrap(),
-            self.client_addr
+            self.client_addr,
+            self.protocol_version,
         );
         let player_data_lock = self.proxy.player_data.lock().await;
         let plinfo = player_data_lock.players.get(self.uuid.as_ref().unwrap());
         let spawn_pos = if let Some(plinfo) = plinfo {
             self.position = Some((plinfo.x, plinfo.y, plinfo.z).into());
+            self.saved_held_slot = Some(plinfo.held_slot);
+            self.saved_inventory = plinfo
+                .inventory
+                .iter()
+                .map(|item| (item.slot, item.data.clone()))
+                .collect();
             (plinfo.x, plinfo.z)
         } else {
             self.position = Some(DEFAULT_SPAWN_POSITION.into());
             (DEFAULT_SPAWN_POSITION.0, DEFAULT_SPAWN_POSITION.2)
         };
         debug!("spawn position is {:?}", self.position.as_ref().unwrap());
-        let active_server_id = *self
-            .proxy
-            .zoner
-            .zones_in_point(world_to_chunk_position(spawn_pos))
-            .get(0)
-            .unwrap_or(&0);
-        debug!("player should join server {}", active_server_id);
-        let server = Arc::clone(
+        let (chunk_x, chunk_z) = world_to_chunk_position(spawn_pos);
+        let zone_server_id = self.proxy.zoner.server_for(chunk_x, chunk_z);
+        debug!("player should join server {}", zone_server_id);
+        let target = {
+            let servers = self.proxy.servers.read().await;
             self.proxy
-                .servers
-                .read()
-                .await
-                .get(&active_server_id)
-                .unwrap(),
-        );
-        let server_craft_conn = server
-            .connect()
+                .config()
+                .missing_server
+                .policy
+                .join_target(zone_server_id, |id| servers.contains_key(&id))?
+        };
+        let active_server_id = match target {
+            JoinTarget::Server(server_id) => server_id,
+            JoinTarget::Redirect { missing, to } => {
+                warn!(
+                    "Server {} for \"{}\" does not exist, redirecting to server {}",
+                    missing,
+                    self.name.as_ref().unwrap(),
+                    to
+                );
+                self.redirected_from = Some(missing);
+                to
+            }
+            JoinTarget::Reject(message) => {
+                v_cur::send_login_disconnect(&mut self.client_writer, &message).await?;
+                bail!(
+                    "Refused login from \"{}\": server {} does not exist",
+                    self.name.as_ref().unwrap(),
+                    zone_server_id
+                );
+            }
+        };
+        let server = self
+            .proxy
+            .servers
+            .read()
             .await
-            .with_context(|| "Failed to connect client to server")?;
-        let (server_reader, server_writer) = server_craft_conn.into_split();
-        let mut server_conn = SplinterServerConnection {
-            writer: Mutex::new(server_writer),
-            reader: Mutex::new(server_reader),
-            server: (*server).clone(),
-            alive: AtomicBool::new(true),
-            eid: -1,
-            uuid: UUID4::from(0u128),
-            known_chunks: Mutex::new(HashSet::new()),
+            .get(&active_server_id)
+            .map(Arc::clone)
+            .ok_or_else(|| anyhow!("No server with id {}", active_server_id))?;
+        let (active_server_id, server) = if self
+            .proxy
+            .config()
+            .server_allows_version(active_server_id, self.protocol_version)
+        {
+            (active_server_id, server)
+        } else {
+            // fall back to the lowest id server that takes the client's version
+            let compatible = {
+                let servers = self.proxy.servers.read().await;
+                self.proxy
+                    .config()
+                    .compatible_server(servers.keys().copied(), self.protocol_version)
+                    .map(|id| (id, Arc::clone(servers.get(&id).unwrap())))
+            };
+            match compatible {
+                Some((compatible_id, compatible_server)) => {
+                    info!(
+                        "Server {} does not allow protocol version {} of \"{}\", sending them to server {}",
+                        active_server_id,
+                        self.protocol_version,
+                        self.name.as_ref().unwrap(),
+                        compatible_id
+                    );
+                    (compatible_id, compatible_server)
+                }
+                None => {
+                    v_cur::send_login_disconnect(
+                        &mut self.client_writer,
+                        &config.improper_version_disconnect_message,
+                    )
+                    .await?;
+                    bail!(
+                        "Refused login from \"{}\": no server allows protocol version {}",
+                        self.name.as_ref().unwrap(),
+                        self.protocol_version
+                    );
+                }
+            }
         };
+        if let Err(e) = self.connect_server(&server).await {
+            if !config.limbo.enabled {
+                return Err(e);
+            }
+            warn!(
+                "Failed to connect \"{}\" to server {}, holding them in limbo: {:?}",
+                self.name.as_ref().unwrap(),
+                active_server_id,
+                e
+            );
+            self.limbo_server = Some(active_server_id);
+        }
+        Ok(())
+    }
+    /// Opens a connection to the server for the client and starts logging in to it
+    async fn connect_server(&mut self, server: &Arc<SplinterServer>) -> anyhow::Result<()> {
+        let mut server_conn = server
+            .open_connection(self.client_addr)
+            .await
+            .with_context(|| "Failed to connect client to server")?;
         info!(
             "Connection for client \"{}\" initiated with {}",
             self.name.as_ref().unwrap(),
@@ -123,14 +290,100 @@ impl<'a> ClientBuilder<'a> {
             .with_context(|| {
                 format!(
                     "Failed to write login start packet to server {}, {}",
-                    active_server_id, server.address
+                    server.id, server.address
                 )
             })?;
         self.server_conn = Some(server_conn);
         Ok(())
     }
+    /// Keeps the client in an empty world made by the proxy until its server can be connected
+    /// to, then starts logging in to that server
+    ///
+    /// The server's join game packet is relayed to the client as usual afterwards, which replaces
+    /// the limbo world
+    pub async fn hold_in_limbo(
+        &mut self,
+        client_conn_reader: &mut AsyncCraftReader,
+    ) -> anyhow::Result<()> {
+        let server_id = self.limbo_server.unwrap();
+        self.client_login_success(client_conn_reader).await?;
+        v_cur::send_limbo_world(&mut self.client_writer, self.position.as_ref().unwrap())
+            .await
+            .with_context(|| "Sending limbo world")?;
+        self.play_join_game().await?;
+        v_cur::send_system_message(
+            &mut self.client_writer,
+            self.proxy
+                .config()
+                .limbo
+                .message
+                .replace("{server}", &server_id.to_string()),
+        )
+        .await
+        .with_context(|| "Sending limbo message")?;
+
+        let retry_interval = Duration::from_secs(self.proxy.config().limbo.retry_interval);
+        let keep_alive_interval = Duration::from_secs(self.proxy.config().keep_alive_interval);
+        let mut next_retry = Instant::now() + retry_interval;
+        let mut next_keep_alive = Instant::now();
+        loop {
+            if !self.proxy.is_alive() {
+                bail!(
+                    "Proxy shut down while \"{}\" was in limbo",
+                    self.name.as_ref().unwrap()
+                );
+            }
+            let now = Instant::now();
+            if now >= next_keep_alive {
+                v_cur::send_keep_alive(&mut self.client_writer, unix_time_millis() as i64)
+                    .await
+                    .with_context(|| "Sending limbo keep alive")?;
+                next_keep_alive = now + keep_alive_interval;
+            }
+            if now >= next_retry {
+                let server = self
+                    .proxy
+                    .servers
+                    .read()
+                    .await
+                    .get(&server_id)
+                    .map(Arc::clone)
+                    .ok_or_else(|| anyhow!("Server {} no longer exists", server_id))?;
+                match self.connect_server(&server).await {
+                    Ok(()) => {
+                        info!(
+                            "Moving \"{}\" out of limbo to server {}",
+                            self.name.as_ref().unwrap(),
+                            server_id
+                        );
+                        return Ok(());
+                    }
+                    Err(e) => debug!(
+                        "Server {} still unavailable for \"{}\": {:?}",
+                        server_id,
+                        self.name.as_ref().unwrap(),
+                        e
+                    ),
+                }
+                next_retry = Instant::now() + retry_interval;
+            }
+            // nothing the client sends matters in limbo. it sends its position at least every
+            // second, so waiting on it doesn't hold up keep alives or retries for long
+            if client_conn_reader
+                .read_raw_packet_async::<RawPacketLatest>()
+                .await?
+                .is_none()
+            {
+                bail!(
+                    "Client \"{}\" connection closed in limbo",
+                    self.name.as_ref().unwrap()
+                );
+            }
+        }
+    }
     pub fn login_set_compression(&mut self, threshold: i32) {
-        let threshold = if threshold > 0 { Some(threshold) } else { None };
+        // zero compresses every packet; only a negative threshold turns it off
+        let threshold = if threshold >= 0 { Some(threshold) } else { None };
         let conn = self.server_conn.as_mut().unwrap();
         conn.writer.get_mut().set_compression_threshold(threshold);
         conn.reader.get_mut().set_compression_threshold(threshold);
@@ -139,7 +392,26 @@ impl<'a> ClientBuilder<'a> {
         &mut self,
         client_conn_reader: &mut impl CraftIo,
     ) -> anyhow::Result<()> {
-        if let Some(threshold) = self.proxy.config.compression_threshold {
+        // a client coming out of limbo was logged in when it went in
+        if self.limbo_server.is_none() {
+            self.client_login_success(client_conn_reader).await?;
+        }
+        let conn = self.server_conn.as_mut().unwrap();
+        self.state.apply(conn.writer.get_mut());
+        self.state.apply(conn.reader.get_mut());
+        Ok(())
+    }
+    /// Finishes logging in the client to the proxy, moving it to play
+    async fn client_login_success(
+        &mut self,
+        client_conn_reader: &mut impl CraftIo,
+    ) -> anyhow::Result<()> {
+        if let Some(threshold) = self
+            .proxy
+            .config()
+            .compression_threshold
+            .filter(|threshold| *threshold >= 0)
+        {
             v_cur::send_set_compression(&mut self.client_writer, threshold)
                 .await
                 .with_context(|| {
@@ -164,19 +436,18 @@ impl<'a> ClientBuilder<'a> {
                 self.name.as_ref().unwrap(),
             )
         })?;
-        client_conn_reader.set_state(State::Play);
-        self.client_writer.set_state(State::Play);
-        let conn = self.server_conn.as_mut().unwrap();
-        conn.writer.get_mut().set_state(State::Play);
-        conn.reader.get_mut().set_state(State::Play);
+        self.state.transition(ConnectionState::Play)?;
+        self.state.apply(client_conn_reader);
+        self.state.apply(&mut self.client_writer);
         Ok(())
     }
     pub async fn play_join_game(&mut self) -> anyhow::Result<()> {
         const MAX_BRAND_SIZE: usize = 128;
-        let brand = if self.proxy.config.brand.len() >= MAX_BRAND_SIZE {
-            &self.proxy.config.brand[0..MAX_BRAND_SIZE]
+        let config = self.proxy.config();
+        let brand = if config.brand.len() >= MAX_BRAND_SIZE {
+            &config.brand[0..MAX_BRAND_SIZE]
         } else {
-            self.proxy.config.brand.as_str()
+            config.brand.as_str()
         };
         v_cur::send_brand(&mut self.client_writer, brand)
             .await
@@ -200,9 +471,8 @@ impl<'a> ClientBuilder<'a> {
                     self.server_conn.as_ref().unwrap().server.id,
                 )
             })?;
-        let tags_opt = self.proxy.tags.lock().await.as_ref().cloned();
-        if let Some(tags) = tags_opt {
-            v_cur::send_tags(&mut self.client_writer, &tags)
+        if let Some(tags_spec) = self.proxy.tags_spec().await {
+            v_cur::send_tags(&mut self.client_writer, tags_spec)
                 .await
                 .with_context(|| {
                     format!(
@@ -215,15 +485,17 @@ impl<'a> ClientBuilder<'a> {
     }
     pub async fn play_tags(&mut self, tags: Tags) -> anyhow::Result<()> {
         if self.proxy.tags.lock().await.is_none() {
-            v_cur::send_tags(&mut self.client_writer, &tags)
-                .await
-                .with_context(|| {
-                    format!(
-                        "Failed to send tags packet to client {}",
-                        self.name.as_ref().unwrap(),
-                    )
-                })?;
-            *self.proxy.tags.lock().await = Some(tags);
+            self.proxy.set_tags(tags).await;
+            if let Some(tags_spec) = self.proxy.tags_spec().await {
+                v_cur::send_tags(&mut self.client_writer, tags_spec)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to send tags packet to client {}",
+                            self.name.as_ref().unwrap(),
+                        )
+                    })?;
+            }
         }
         Ok(())
     }
@@ -231,25 +503,40 @@ impl<'a> ClientBuilder<'a> {
         let cl = SplinterClient::new(
             Arc::clone(self.proxy),
             self.name.unwrap(),
+            self.client_addr,
+            self.protocol_version,
             self.client_writer,
             Arc::new(self.server_conn.unwrap()),
             self.position.unwrap(),
         );
         cl.settings.store(Arc::new(self.settings.unwrap()));
+        *cl.inventory.lock().await = self.saved_inventory;
+        *cl.join_game.lock().await = self.join_game;
         cl
     }
 }
 
 pub async fn handle_client_login(
-    mut conn: AsyncCraftConnection,
+    conn: AsyncCraftConnection,
     addr: SocketAddr,
     proxy: Arc<SplinterProxy>,
+    protocol_version: i32,
 ) -> anyhow::Result<()> {
-    conn.set_state(State::Login);
     let (mut client_conn_reader, client_conn_writer) = conn.into_split();
-    let mut client_builder = ClientBuilder::new(&proxy, addr, client_conn_writer);
+    let mut client_builder = ClientBuilder::new(&proxy, addr, protocol_version, client_conn_writer);
     let mut next_sender = PacketDirection::ServerBound;
+    let mut packets_received = 0;
     loop {
+        if next_sender == PacketDirection::ServerBound {
+            packets_received += 1;
+            if packets_received > proxy.config().max_pre_play_packets {
+                bail!(
+                    "Client \"{}\", {} sent too many packets during login",
+                    client_builder.name.unwrap_or_else(String::new),
+                    addr,
+                );
+            }
+        }
         if let Some(val) = v_cur::handle_client_login_packet(
             &mut next_sender,
             &mut client_builder,
@@ -261,6 +548,14 @@ pub async fn handle_client_login(
             if val {
                 break;
             }
+            if client_builder.limbo_server.is_some() && client_builder.server_conn.is_none() {
+                client_builder
+                    .hold_in_limbo(&mut client_conn_reader)
+                    .await
+                    .with_context(|| "Holding client in limbo")?;
+                // the client's packets from limbo don't count towards its login
+                packets_received = 0;
+            }
         } else {
             bail!(
                 "Client \"{}\", {} connection closed during login",
@@ -269,6 +564,8 @@ pub async fn handle_client_login(
             );
         }
     }
+    let redirected_from = client_builder.redirected_from;
+    let saved_held_slot = client_builder.saved_held_slot;
     let client = client_builder.build().await;
     let cl_pos = &**client.position.load();
     send_position_set(
@@ -279,18 +576,46 @@ pub async fn handle_client_login(
     )
     .await
     .with_context(|| "Sending position set")?;
+    if let Some(slot) = saved_held_slot {
+        client
+            .set_held_slot(slot)
+            .await
+            .with_context(|| "Restoring held slot")?;
+    }
     let client_arc = Arc::new(client);
     proxy
         .players
         .write()
         .await
         .insert(client_arc.name.clone(), Arc::clone(&client_arc));
+    proxy.metrics.logins.fetch_add(1, Ordering::Relaxed);
+    client_arc
+        .send_tab_list()
+        .await
+        .with_context(|| "Sending tab list")?;
+    client_arc
+        .send_routing_message()
+        .await
+        .with_context(|| "Sending routing message")?;
+    if let Some(missing_id) = redirected_from {
+        let message = proxy
+            .config()
+            .missing_server
+            .redirect_message
+            .replace("{missing}", &missing_id.to_string())
+            .replace("{server}", &client_arc.server_id().to_string());
+        client_arc
+            .send_message(message, &CommandSender::Console)
+            .await
+            .with_context(|| "Sending redirect message")?;
+    }
 
     // move on to relay loop
     let (res_a, res_b) = future::zip(
         client_arc.handle_client_relay(Arc::clone(&proxy), client_conn_reader),
         client_arc.handle_server_relay(proxy, Arc::clone(&client_arc)),
     )
+    .instrument(client_arc.log_span.clone())
     .await;
     res_a?;
     res_b?;
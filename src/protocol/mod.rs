@@ -3,16 +3,18 @@ use std::{
     fmt::Debug,
     net::{SocketAddr, TcpStream},
     sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
+use anyhow::Context;
 use async_compat::Compat;
 use async_dup::Arc as AsyncArc;
-use craftio_rs::{CraftAsyncReader, CraftConnection, CraftReader, CraftWriter};
-use smol::Async;
+use craftio_rs::{CraftAsyncReader, CraftConnection, CraftReader, CraftWriter, ReadError};
+use smol::{Async, Timer};
 
-use crate::{
-    proxy::{client::SplinterClient, server::SplinterServerConnection, SplinterProxy},
-    systems::playersave::PlInfoPlayer,
+use crate::proxy::{
+    client::SplinterClient, logging::Instrument, server::SplinterServerConnection,
+    ClientKickReason, SplinterProxy,
 };
 
 pub mod current;
@@ -21,11 +23,13 @@ mod login;
 pub mod v_cur;
 pub use login::*;
 pub mod plugin;
+pub mod state;
 
 use current::{
     proto::{HandshakeNextState, Packet756 as PacketLatest, RawPacket756 as RawPacketLatest},
     protocol::PacketDirection,
 };
+use state::ConnectionState;
 
 pub type AsyncCraftConnection =
     CraftConnection<Compat<AsyncArc<Async<TcpStream>>>, Compat<AsyncArc<Async<TcpStream>>>>;
@@ -46,27 +50,24 @@ pub struct Tags {
 ///
 /// Expects the JSON file to be in the format of a list of objects, and each object has a `name`
 /// string and an `id` number.
-fn load_json_id_name_pairs(data: impl AsRef<str>) -> Vec<(i32, String)> {
-    let parsed = match json::parse(data.as_ref()) {
-        Ok(parsed) => parsed,
-        Err(e) => {
-            error!("Failed to parse json: {}", e);
-            panic!("File parse error");
-        }
-    };
+fn load_json_id_name_pairs(data: impl AsRef<str>) -> anyhow::Result<Vec<(i32, String)>> {
+    let parsed = json::parse(data.as_ref()).with_context(|| "Invalid JSON")?;
+    if !parsed.is_array() {
+        bail!("Expected a list of objects");
+    }
     let mut list = vec![];
-    for block_data in parsed.members() {
+    for (i, block_data) in parsed.members().enumerate() {
         list.push((
             block_data["id"]
                 .as_i32()
-                .expect("Failed to convert JSON id to i32"),
+                .ok_or_else(|| anyhow!("Entry {} has no integer `id`", i))?,
             block_data["name"]
                 .as_str()
-                .expect("Failed to convert JSON name to str")
+                .ok_or_else(|| anyhow!("Entry {} has no string `name`", i))?
                 .into(),
         ));
     }
-    list
+    Ok(list)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -82,12 +83,19 @@ pub async fn handle_handshake(
     addr: SocketAddr,
     proxy: Arc<SplinterProxy>,
 ) -> anyhow::Result<()> {
+    let mut state = ConnectionState::Handshake;
     let packet = conn.read_packet_async::<RawPacketLatest>().await?;
     match packet {
         Some(PacketLatest::Handshake(body)) => match body.next_state {
-            HandshakeNextState::Status => v_cur::handle_client_status(conn, addr, proxy).await?,
+            HandshakeNextState::Status => {
+                state.transition(ConnectionState::Status)?;
+                state.apply(&mut conn);
+                v_cur::handle_client_status(conn, addr, proxy).await?
+            }
             HandshakeNextState::Login => {
-                handle_client_login(conn, addr, proxy).await?;
+                state.transition(ConnectionState::Login)?;
+                state.apply(&mut conn);
+                handle_client_login(conn, addr, proxy, *body.version).await?;
             }
         },
         Some(other_packet) => bail!(
@@ -99,6 +107,22 @@ pub async fn handle_handshake(
     Ok(())
 }
 
+/// Whether an error from reading a packet leaves the stream unusable. Once a packet's length
+/// prefix has been read the next packet can still be found, even if the packet itself is bad, but
+/// errors reading the prefix (or the socket) lose track of where packets start
+fn is_fatal_read_error(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<ReadError>() {
+        Some(ReadError::PacketErr { .. }) | Some(ReadError::DecompressFailed { .. }) => false,
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// How long a relay waits before reading again after this many packet errors in a row
+fn packet_error_backoff(consecutive_errors: u32) -> Duration {
+    Duration::from_millis(10 << consecutive_errors.min(7))
+}
+
 impl SplinterClient {
     pub async fn handle_server_relay(
         self: &Arc<Self>,
@@ -107,9 +131,43 @@ impl SplinterClient {
     ) -> anyhow::Result<()> {
         let sender = PacketDirection::ClientBound;
         let mut active_server;
+        let mut consecutive_errors = 0;
         loop {
             // server->proxy->client
             active_server = client.active_server.load();
+            if self.alive.load(Ordering::Relaxed) && !active_server.alive.load(Ordering::Relaxed) {
+                if self.reconnecting.load(Ordering::Relaxed)
+                    || self.in_limbo.load(Ordering::Relaxed)
+                {
+                    // the active connection is being replaced, or the client is in limbo; wait
+                    // for the new one
+                    Timer::after(Duration::from_millis(50)).await;
+                    continue;
+                }
+                if active_server.disconnect_relayed.load(Ordering::Relaxed) {
+                    // the server kicked the client, so they aren't moved to another server
+                    debug!(
+                        "Server {} disconnected {}, not failing over",
+                        active_server.server.id, &self.name
+                    );
+                    self.set_alive(false).await;
+                    break;
+                }
+                // the server closed the active connection on its own
+                match self.fail_over().await {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        error!("Failed to move {} to another server: {:?}", &self.name, e);
+                        if let Err(e) = self.send_kick(ClientKickReason::ServerDown).await {
+                            error!("Failed to send kick to {}: {}", &self.name, e);
+                        }
+                        // the client relay ends once the client is dead, and removes the player
+                        // from the player list then
+                        self.set_alive(false).await;
+                        break;
+                    }
+                }
+            }
             if !self.alive.load(Ordering::Relaxed) || !active_server.alive.load(Ordering::Relaxed) {
                 // debug!(
                 //     "active connection for {}, {} no longer alive (client state: {:?})",
@@ -119,26 +177,45 @@ impl SplinterClient {
                 // );
                 break;
             }
-            let server_reader: &mut AsyncCraftReader = &mut *active_server.reader.lock().await;
-            match v_cur::handle_server_packet(
-                &proxy,
-                self,
-                server_reader,
-                &active_server.server,
-                &sender,
-            )
-            .await
-            {
-                Ok(Some(())) => {}
+            let result = {
+                let server_reader: &mut AsyncCraftReader = &mut *active_server.reader.lock().await;
+                v_cur::handle_server_packet(&proxy, self, server_reader, &active_server, &sender)
+                    .instrument(active_server.server.log_span.clone())
+                    .await
+            };
+            match result {
+                Ok(Some(())) => consecutive_errors = 0,
                 Ok(None) => {
+                    if self.reconnecting.load(Ordering::Relaxed)
+                        || self.in_limbo.load(Ordering::Relaxed)
+                        || !Arc::ptr_eq(&*active_server, &*client.active_server.load())
+                    {
+                        // the connection we were reading from has been replaced
+                        continue;
+                    }
                     // debug!(
                     //     "server {} closed connection with {}!",
                     //     active_server.server.id, &client.name
                     // );
-                    break;
+                    active_server.alive.store(false, Ordering::Relaxed);
+                    continue;
                 }
                 Err(e) => {
                     error!("Failed to handle packet from server: {:?}", e);
+                    consecutive_errors += 1;
+                    if is_fatal_read_error(&e)
+                        || consecutive_errors >= proxy.config().max_consecutive_packet_errors
+                    {
+                        error!(
+                            "Closing connection between {} and server {} after {} packet errors",
+                            &self.name, active_server.server.id, consecutive_errors
+                        );
+                        // handled like the server closing the connection
+                        active_server.alive.store(false, Ordering::Relaxed);
+                        consecutive_errors = 0;
+                        continue;
+                    }
+                    Timer::after(packet_error_backoff(consecutive_errors)).await;
                 }
             }
         }
@@ -155,35 +232,128 @@ impl SplinterClient {
         mut client_reader: AsyncCraftReader,
     ) -> anyhow::Result<()> {
         let sender = PacketDirection::ServerBound;
+        let mut consecutive_errors = 0;
         loop {
             // client->proxy->server
             if !self.alive.load(Ordering::Relaxed) {
                 break;
             }
             match v_cur::handle_client_packet(&proxy, self, &mut client_reader, &sender).await {
-                Ok(Some(())) => {}
+                Ok(Some(())) => consecutive_errors = 0,
                 Ok(None) => break,
                 Err(e) => {
                     error!(
                         "Failed to handle packet from client \"{}\": {}",
                         &self.name, e
                     );
+                    consecutive_errors += 1;
+                    if is_fatal_read_error(&e)
+                        || consecutive_errors >= proxy.config().max_consecutive_packet_errors
+                    {
+                        error!(
+                            "Closing connection with client \"{}\" after {} packet errors",
+                            &self.name, consecutive_errors
+                        );
+                        break;
+                    }
+                    Timer::after(packet_error_backoff(consecutive_errors)).await;
                 }
             }
         }
-        proxy.players.write().await.remove(&self.name);
-        self.alive.store(false, Ordering::Relaxed);
-        let pos = &**self.position.load();
-        self.proxy.player_data.lock().await.players.insert(
-            self.uuid,
-            PlInfoPlayer {
-                x: pos.x,
-                y: pos.y,
-                z: pos.z,
-                name: self.name.clone(),
-            },
-        );
+        proxy.remove_player(self).await;
+        proxy.metrics.disconnects.fetch_add(1, Ordering::Relaxed);
+        self.set_alive(false).await;
+        self.store_player_info().await;
         info!("Client \"{}\" connection closed", &self.name);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use craftio_rs::{CraftIo, CraftSyncReader};
+
+    use super::*;
+    use crate::protocol::current::protocol::{RawPacket, State};
+
+    /// Largest packet the test readers take
+    const TEST_MAX_PACKET_SIZE: usize = 64;
+
+    fn status_reader(data: &[u8]) -> CraftReader<&[u8]> {
+        let mut reader = CraftReader::wrap(data, PacketDirection::ServerBound);
+        reader.set_state(State::Status);
+        reader.set_max_packet_size(TEST_MAX_PACKET_SIZE);
+        reader
+    }
+
+    /// Reads a packet the way the relays do, expecting it to fail
+    fn read_error(reader: &mut CraftReader<&[u8]>) -> anyhow::Error {
+        match reader
+            .read_raw_packet::<RawPacketLatest>()
+            .with_context(|| "Failed to read packet")
+        {
+            Ok(_) => panic!("Read should have failed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn packet_within_max_size_is_read() {
+        let mut data = vec![TEST_MAX_PACKET_SIZE as u8, 0x01];
+        data.extend([0u8; TEST_MAX_PACKET_SIZE - 1]);
+        assert!(status_reader(&data)
+            .read_raw_packet::<RawPacketLatest>()
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn oversized_length_prefix_is_fatal() {
+        // a length of 300, past the cap, followed by far less data
+        let data = [0xac, 0x02, 0x00, 0x00];
+        assert!(is_fatal_read_error(&read_error(&mut status_reader(&data))));
+    }
+
+    #[test]
+    fn truncated_packet_is_fatal() {
+        let data = [0x05, 0x00];
+        assert!(is_fatal_read_error(&read_error(&mut status_reader(&data))));
+    }
+
+    #[test]
+    fn unknown_packet_is_not_fatal() {
+        // an unknown id inside a well formed length prefix, then a status request
+        let data = [0x01, 0x7f, 0x01, 0x00];
+        let mut reader = status_reader(&data);
+        assert!(!is_fatal_read_error(&read_error(&mut reader)));
+        let raw = reader
+            .read_raw_packet::<RawPacketLatest>()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            raw.deserialize().unwrap(),
+            PacketLatest::StatusRequest(_)
+        ));
+    }
+
+    #[test]
+    fn other_errors_are_not_fatal() {
+        assert!(!is_fatal_read_error(&anyhow!("Bad packet")));
+    }
+
+    #[test]
+    fn loads_id_name_pairs() {
+        let pairs =
+            load_json_id_name_pairs(r#"[{"id": 0, "name": "air"}, {"id": 1, "name": "stone"}]"#)
+                .unwrap();
+        assert_eq!(pairs, vec![(0, "air".to_owned()), (1, "stone".to_owned())]);
+    }
+
+    #[test]
+    fn malformed_id_name_pairs_are_errors() {
+        assert!(load_json_id_name_pairs("[{").is_err());
+        assert!(load_json_id_name_pairs(r#"{"id": 0, "name": "air"}"#).is_err());
+        assert!(load_json_id_name_pairs(r#"[{"id": "0", "name": "air"}]"#).is_err());
+        assert!(load_json_id_name_pairs(r#"[{"id": 0}]"#).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use craftio_rs::CraftIo;
+
+use crate::protocol::current::protocol::State;
+
+/// Where a client connection is in its lifecycle
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectionState {
+    /// Waiting for the client's handshake
+    Handshake,
+    /// Client is querying the server status
+    Status,
+    /// Client is logging in
+    Login,
+    /// Client is in game
+    Play,
+    /// Connection is closed or being closed
+    Dead,
+}
+
+impl ConnectionState {
+    /// Checks if a connection in this state may move to the next state
+    pub fn can_transition_to(&self, next: ConnectionState) -> bool {
+        matches!(
+            (self, next),
+            (ConnectionState::Handshake, ConnectionState::Status)
+                | (ConnectionState::Handshake, ConnectionState::Login)
+                | (ConnectionState::Login, ConnectionState::Play)
+                | (_, ConnectionState::Dead)
+        )
+    }
+    /// Moves to the next state, failing if the transition is not allowed
+    pub fn transition(&mut self, next: ConnectionState) -> anyhow::Result<()> {
+        if !self.can_transition_to(next) {
+            bail!(
+                "Illegal connection state transition from {:?} to {:?}",
+                self,
+                next
+            );
+        }
+        *self = next;
+        Ok(())
+    }
+    /// Gets the protocol state that packets are read in while in this state
+    pub fn protocol_state(&self) -> Option<State> {
+        match self {
+            ConnectionState::Handshake => Some(State::Handshaking),
+            ConnectionState::Status => Some(State::Status),
+            ConnectionState::Login => Some(State::Login),
+            ConnectionState::Play => Some(State::Play),
+            ConnectionState::Dead => None,
+        }
+    }
+    /// Sets a craftio reader, writer, or connection to the protocol state of this state, so that
+    /// packets written afterwards get the ids of this state
+    pub fn apply(&self, io: &mut impl CraftIo) {
+        if let Some(state) = self.protocol_state() {
+            io.set_state(state);
+        }
+    }
+    /// Checks if a packet from the given protocol state may be handled in this state
+    pub fn accepts(&self, state: &State) -> bool {
+        self.protocol_state().as_ref() == Some(state)
+    }
+    /// Fails if a packet from the given protocol state may not be handled in this state
+    pub fn expect(&self, state: &State) -> anyhow::Result<()> {
+        if !self.accepts(state) {
+            bail!(
+                "Received a packet for state {:?} while in connection state {:?}",
+                state,
+                self
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use craftio_rs::{
+        CraftReader,
+        CraftSyncReader,
+        CraftSyncWriter,
+        CraftWriter,
+    };
+
+    use super::*;
+    use crate::protocol::current::{
+        proto::{
+            LoginDisconnectSpec,
+            Packet756 as PacketLatest,
+            RawPacket756 as RawPacketLatest,
+        },
+        protocol::{
+            PacketDirection,
+            RawPacket,
+        },
+        types::Chat,
+    };
+
+    fn read_in_state(state: ConnectionState, data: &[u8]) -> PacketLatest {
+        let mut reader = CraftReader::wrap(data, PacketDirection::ServerBound);
+        state.apply(&mut reader);
+        let raw = reader
+            .read_raw_packet::<RawPacketLatest>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(raw.id().state), state.protocol_state());
+        raw.deserialize().unwrap()
+    }
+
+    fn assert_rejected(from: ConnectionState, to: ConnectionState) {
+        let mut state = from;
+        assert!(state.transition(to).is_err());
+        assert_eq!(state, from);
+    }
+
+    macro_rules! rejected_transitions {
+        ($($name:ident: $from:ident -> $to:ident,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    assert_rejected(ConnectionState::$from, ConnectionState::$to);
+                }
+            )*
+        };
+    }
+
+    rejected_transitions! {
+        handshake_to_handshake: Handshake -> Handshake,
+        handshake_to_play: Handshake -> Play,
+        status_to_handshake: Status -> Handshake,
+        status_to_status: Status -> Status,
+        status_to_login: Status -> Login,
+        status_to_play: Status -> Play,
+        login_to_handshake: Login -> Handshake,
+        login_to_status: Login -> Status,
+        login_to_login: Login -> Login,
+        play_to_handshake: Play -> Handshake,
+        play_to_status: Play -> Status,
+        play_to_login: Play -> Login,
+        play_to_play: Play -> Play,
+        dead_to_handshake: Dead -> Handshake,
+        dead_to_status: Dead -> Status,
+        dead_to_login: Dead -> Login,
+        dead_to_play: Dead -> Play,
+    }
+
+    #[test]
+    fn allowed_transitions() {
+        let mut state = ConnectionState::Handshake;
+        state.transition(ConnectionState::Login).unwrap();
+        state.transition(ConnectionState::Play).unwrap();
+        state.transition(ConnectionState::Dead).unwrap();
+        let mut state = ConnectionState::Handshake;
+        state.transition(ConnectionState::Status).unwrap();
+        state.transition(ConnectionState::Dead).unwrap();
+    }
+
+    #[test]
+    fn rejects_packets_of_other_states() {
+        assert!(ConnectionState::Login.expect(&State::Play).is_err());
+        assert!(ConnectionState::Play.expect(&State::Login).is_err());
+        assert!(ConnectionState::Dead.expect(&State::Play).is_err());
+        assert!(ConnectionState::Play.expect(&State::Play).is_ok());
+    }
+
+    #[test]
+    fn applies_state_to_reads() {
+        // id 0 with an empty body is a status request once in the status state
+        match read_in_state(ConnectionState::Status, &[0x01, 0x00]) {
+            PacketLatest::StatusRequest(_) => {}
+            other => panic!("Read {:?} in the status state", other),
+        }
+    }
+
+    #[test]
+    fn applies_state_to_writes() {
+        let mut data = Vec::new();
+        let mut writer = CraftWriter::wrap(&mut data, PacketDirection::ClientBound);
+        ConnectionState::Login.apply(&mut writer);
+        writer
+            .write_packet(PacketLatest::LoginDisconnect(LoginDisconnectSpec {
+                message: Chat::from_text("bye"),
+            }))
+            .unwrap();
+        drop(writer);
+        let mut reader = CraftReader::wrap(&data[..], PacketDirection::ClientBound);
+        ConnectionState::Login.apply(&mut reader);
+        let raw = reader
+            .read_raw_packet::<RawPacketLatest>()
+            .unwrap()
+            .unwrap();
+        match raw.deserialize().unwrap() {
+            PacketLatest::LoginDisconnect(body) => {
+                assert_eq!(body.message, Chat::from_text("bye"))
+            }
+            other => panic!("Wrote {:?} in the login state", other),
+        }
+    }
+
+    #[test]
+    fn dead_keeps_previous_state() {
+        let mut reader = CraftReader::wrap(&[0x01u8, 0x00][..], PacketDirection::ServerBound);
+        ConnectionState::Status.apply(&mut reader);
+        ConnectionState::Dead.apply(&mut reader);
+        let raw = reader
+            .read_raw_packet::<RawPacketLatest>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(raw.id().state, State::Status);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use super::{RelayPass, BIOME_MAP};
+use crate::{
+    protocol::current::{
+        nbt::{NamedTag, Tag},
+        protocol::PacketDirection,
+        types::{NamedNbtTag, VarInt},
+        PacketLatest, PacketLatestKind,
+    },
+    proxy::mapping::SplinterMapping,
+};
+
+const BIOME_REGISTRY: &str = "minecraft:worldgen/biome";
+
+// in protocol 756 (1.17.1), chunk data carries the biomes of a chunk column as an array of
+// biome registry ids, one for every 4x4x4 block cell
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, _client, sender, lazy_packet, _destination| {
+            if *sender != PacketDirection::ClientBound
+                || !matches!(lazy_packet.kind(), PacketLatestKind::PlayChunkData)
+            {
+                return;
+            }
+            let server_id = connection.server.id;
+            let map = &*smol::block_on(proxy.mapping.lock());
+            if !map.biomes.contains_key(&server_id) {
+                // the server numbers its biomes the same as the proxy
+                return;
+            }
+            match lazy_packet.packet() {
+                Ok(PacketLatest::PlayChunkData(body)) => {
+                    for biome in body.biomes.iter_mut() {
+                        *biome = VarInt::from(map.map_biome(server_id, **biome));
+                    }
+                }
+                Ok(_) => unreachable!(),
+                Err(e) => error!("Failed to deserialize chunk data: {:?}", e),
+            }
+        }),
+    }
+}
+
+/// Records how a server numbers its biomes from its dimension codec, and rewrites the codec's
+/// biome ids to the proxy-side ids
+///
+/// Biome ids are indices into the `minecraft:worldgen/biome` registry of the dimension codec in
+/// each server's join game packet. Servers with different datapacks can number the same biome
+/// differently, so each server's ids are mapped by name to the ids in `BIOME_MAP`. Biomes the
+/// proxy doesn't know keep the server's id
+pub fn map_dimension_codec(map: &mut SplinterMapping, server_id: u64, codec: &mut NamedNbtTag) {
+    let entries = match biome_entries(&mut codec.root) {
+        Some(entries) => entries,
+        None => return warn!("Server {} sent a dimension codec without biomes", server_id),
+    };
+    let mut ids = HashMap::new();
+    for entry in entries.iter_mut() {
+        let fields = match entry {
+            Tag::Compound(fields) => fields,
+            _ => continue,
+        };
+        let name = match field(fields, "name") {
+            Some(Tag::String(name)) => name.trim_start_matches("minecraft:").to_owned(),
+            _ => continue,
+        };
+        let proxy_id = match BIOME_MAP.get_by_right(&name) {
+            Some(proxy_id) => *proxy_id,
+            None => {
+                debug!("Server {} has unknown biome \"{}\"", server_id, name);
+                continue;
+            }
+        };
+        if let Some(Tag::Int(id)) = field(fields, "id") {
+            if *id != proxy_id {
+                ids.insert(*id, proxy_id);
+                *id = proxy_id;
+            }
+        }
+    }
+    if ids.is_empty() {
+        map.biomes.remove(&server_id);
+    } else {
+        debug!("Server {} has {} remapped biome ids", server_id, ids.len());
+        map.biomes.insert(server_id, ids);
+    }
+}
+
+/// Gets the entries of the biome registry in a dimension codec
+fn biome_entries(root: &mut NamedTag) -> Option<&mut Vec<Tag>> {
+    let registry = match &mut root.payload {
+        Tag::Compound(registries) => field(registries, BIOME_REGISTRY)?,
+        _ => return None,
+    };
+    match registry {
+        Tag::Compound(fields) => match field(fields, "value")? {
+            Tag::List(entries) => Some(entries),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn field<'a>(fields: &'a mut [NamedTag], name: &str) -> Option<&'a mut Tag> {
+    fields
+        .iter_mut()
+        .find(|tag| tag.name == name)
+        .map(|tag| &mut tag.payload)
+}
@@ -0,0 +1,129 @@
+use std::{collections::HashMap, mem};
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::{
+        current::{
+            proto::{BossBarAction, PlayBossBarSpec},
+            protocol::PacketDirection,
+            uuid::UUID4,
+            PacketLatest,
+            PacketLatestKind,
+        },
+        events::LazyDeserializedPacket,
+    },
+    proxy::{
+        client::SplinterClient,
+        config::BossBarVisibility,
+        mapping::map_boss_bar_uuid,
+        server::SplinterServerConnection,
+    },
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, _client, sender, lazy_packet, destination| {
+            if *sender == PacketDirection::ClientBound
+                && lazy_packet.kind() == PacketLatestKind::PlayBossBar
+            {
+                match lazy_packet.packet() {
+                    Ok(PacketLatest::PlayBossBar(body)) => {
+                        // kept with the connection the packet was read from, so a boss bar read
+                        // from the old server around a switch stays that server's
+                        smol::block_on(connection.record_boss_bar(body));
+                        body.uuid = map_boss_bar_uuid(connection.server.id, body.uuid);
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => {
+                        error!("Failed to deserialize boss bar: {}", e);
+                        // an update under the server's uuid could hit another server's boss bar
+                        *destination = PacketDestination::None;
+                    }
+                }
+            }
+        }),
+    }
+}
+
+/// Boss bars a server has shown, by server-side uuid. Each is kept as the action that added it
+/// followed by the latest update of each kind since, which together recreate it
+pub type BossBars = HashMap<UUID4, Vec<BossBarAction>>;
+
+impl SplinterServerConnection {
+    /// Keeps track of the boss bars the server shows the player
+    pub async fn record_boss_bar(&self, body: &PlayBossBarSpec) {
+        let boss_bars = &mut *self.boss_bars.lock().await;
+        match &body.action {
+            BossBarAction::Add(_) => {
+                boss_bars.insert(body.uuid, vec![body.action.clone()]);
+            }
+            BossBarAction::Remove => {
+                boss_bars.remove(&body.uuid);
+            }
+            update => {
+                if let Some(actions) = boss_bars.get_mut(&body.uuid) {
+                    let kind = mem::discriminant(update);
+                    actions.retain(|action| mem::discriminant(action) != kind);
+                    actions.push(update.clone());
+                }
+            }
+        }
+    }
+}
+
+impl SplinterClient {
+    /// Shows the client every boss bar of the connection's server, under their proxy-side uuids
+    pub async fn show_boss_bars(
+        &self,
+        connection: &SplinterServerConnection,
+    ) -> anyhow::Result<()> {
+        let packets = connection
+            .boss_bars
+            .lock()
+            .await
+            .iter()
+            .flat_map(|(uuid, actions)| {
+                let uuid = map_boss_bar_uuid(connection.server.id, *uuid);
+                actions.iter().map(move |action| PlayBossBarSpec {
+                    uuid,
+                    action: action.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        for packet in packets {
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayBossBar(packet),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+    /// Takes every boss bar of the connection's server away from the client
+    pub async fn hide_boss_bars(
+        &self,
+        connection: &SplinterServerConnection,
+    ) -> anyhow::Result<()> {
+        let uuids = connection
+            .boss_bars
+            .lock()
+            .await
+            .keys()
+            .map(|uuid| map_boss_bar_uuid(connection.server.id, *uuid))
+            .collect::<Vec<_>>();
+        for uuid in uuids {
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayBossBar(PlayBossBarSpec {
+                    uuid,
+                    action: BossBarAction::Remove,
+                }),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+    /// Whether the client is shown the boss bars of servers other than its active one
+    pub fn shows_background_boss_bars(&self) -> bool {
+        self.proxy.config().boss_bars == BossBarVisibility::AllServers
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use craftio_rs::CraftAsyncWriter;
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::current::{
+        proto::{LoginPluginRequestSpec, LoginPluginResponseSpec},
+        protocol::PacketDirection,
+        BytesSerializer, Deserialize, Deserialized, PacketLatest, PacketLatestKind, Serialize,
+    },
+    proxy::{client::SplinterClient, server::SplinterServerConnection, SplinterProxy},
+};
+
+type PluginChannelFn = Box<
+    dyn Send
+        + Sync
+        + Fn(
+            &Arc<SplinterProxy>,
+            &Arc<SplinterServerConnection>,
+            &Arc<SplinterClient>,
+            &PacketDirection,
+            &mut Vec<u8>,
+        ) -> bool,
+>;
+/// Handles the plugin messages on a channel during play, in both directions. The handler may
+/// change the message's data, and returns whether the message should still be relayed
+///
+/// Messages on channels without a handler are relayed unchanged
+pub struct PluginChannel {
+    pub channel: &'static str,
+    pub handler: PluginChannelFn,
+}
+
+inventory::collect!(PluginChannel);
+
+type LoginPluginChannelFn =
+    Box<dyn Send + Sync + Fn(&Arc<SplinterProxy>, u64, &[u8]) -> Option<Vec<u8>>>;
+/// Answers the login plugin requests on a channel from servers, given the server id and the
+/// request's data. Returns the response data, or `None` if the request isn't understood
+///
+/// Clients have already logged in to the proxy by the time a server is logged in to, so the proxy
+/// answers these itself. Requests on channels without a handler aren't understood, as with a
+/// vanilla client
+pub struct LoginPluginChannel {
+    pub channel: &'static str,
+    pub handler: LoginPluginChannelFn,
+}
+
+inventory::collect!(LoginPluginChannel);
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, client, sender, lazy_packet, destination| {
+            if !matches!(
+                lazy_packet.kind(),
+                PacketLatestKind::PlayServerPluginMessage | PacketLatestKind::PlayClientPluginMessage
+            ) {
+                return;
+            }
+            let (channel, data) = match lazy_packet.packet() {
+                Ok(PacketLatest::PlayServerPluginMessage(body)) => (&body.channel, &mut body.data.data),
+                Ok(PacketLatest::PlayClientPluginMessage(body)) => (&body.channel, &mut body.data.data),
+                Ok(_) => unreachable!(),
+                Err(e) => return error!("Failed to deserialize plugin message: {:?}", e),
+            };
+            if let Some(plugin_channel) = inventory::iter::<PluginChannel>
+                .into_iter()
+                .find(|plugin_channel| plugin_channel.channel == channel.as_str())
+            {
+                if !(plugin_channel.handler)(proxy, connection, client, sender, data) {
+                    *destination = PacketDestination::None;
+                }
+            }
+        }),
+    }
+}
+
+// tell the client which server software is behind the proxy, as well as that it is behind the
+// proxy
+inventory::submit! {
+    PluginChannel {
+        channel: "minecraft:brand",
+        handler: Box::new(|proxy, _connection, _client, sender, data| {
+            if *sender != PacketDirection::ClientBound {
+                return true;
+            }
+            let server_brand = match String::mc_deserialize(data) {
+                Ok(Deserialized { value, .. }) => value,
+                Err(e) => {
+                    warn!("Failed to deserialize server brand: {:?}", e);
+                    return true;
+                }
+            };
+            let brand = format!("{} ({})", server_brand, &proxy.config().brand);
+            let mut serializer = BytesSerializer::default();
+            match brand.mc_serialize(&mut serializer) {
+                Ok(()) => *data = serializer.into_bytes(),
+                Err(e) => error!("Failed to serialize brand: {:?}", e),
+            }
+            true
+        }),
+    }
+}
+
+/// Answers a server's login plugin request with the handler for its channel
+pub async fn answer_login_plugin_request(
+    proxy: &Arc<SplinterProxy>,
+    server_conn: &mut SplinterServerConnection,
+    request: LoginPluginRequestSpec,
+) -> anyhow::Result<()> {
+    let response = inventory::iter::<LoginPluginChannel>
+        .into_iter()
+        .find(|plugin_channel| plugin_channel.channel == request.channel)
+        .and_then(|plugin_channel| {
+            (plugin_channel.handler)(proxy, server_conn.server.id, &request.data.data)
+        });
+    if response.is_none() {
+        debug!(
+            "Not understood login plugin request from server {} on channel \"{}\"",
+            server_conn.server.id, &request.channel
+        );
+    }
+    server_conn
+        .writer
+        .get_mut()
+        .write_packet_async(PacketLatest::LoginPluginResponse(LoginPluginResponseSpec {
+            message_id: request.message_id,
+            successful: response.is_some(),
+            data: response.unwrap_or_default().into(),
+        }))
+        .await
+        .map_err(|e| e.into())
+}
@@ -6,12 +6,16 @@ use super::{
 };
 use crate::{
     protocol::{
-        current::proto::{
-            ChatPosition,
-            Packet756 as PacketLatest,
-            Packet756Kind as PacketLatestKind,
-            PlayClientChatMessageSpec,
-            PlayServerChatMessageSpec,
+        current::{
+            proto::{
+                ChatPosition,
+                Packet756 as PacketLatest,
+                Packet756Kind as PacketLatestKind,
+                PlayClientChatMessageSpec,
+                PlayServerChatMessageSpec,
+            },
+            types::Chat,
+            uuid::UUID4,
         },
         events::LazyDeserializedPacket,
     },
@@ -21,23 +25,27 @@ use crate::{
             ToChat,
         },
         client::SplinterClient,
+        config::MessageChannel,
     },
     systems::commands::CommandSender,
 };
 
 inventory::submit! {
-    RelayPass(Box::new(|proxy, _connection, client, sender, lazy_packet, destination| {
-        if lazy_packet.kind() == PacketLatestKind::PlayClientChatMessage {
-            match lazy_packet.packet() {
-                Ok(PacketLatest::PlayClientChatMessage(body)) => smol::block_on(receive_chat_message(proxy, client, sender, &body.message)),
-                Ok(_) => unreachable!(),
-                Err(e) => {
-                    error!("Failed to deserialize chat message: {}", e);
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, _connection, client, sender, lazy_packet, destination| {
+            if lazy_packet.kind() == PacketLatestKind::PlayClientChatMessage {
+                match lazy_packet.packet() {
+                    Ok(PacketLatest::PlayClientChatMessage(body)) => smol::block_on(receive_chat_message(proxy, client, sender, &body.message)),
+                    Ok(_) => unreachable!(),
+                    Err(e) => {
+                        error!("Failed to deserialize chat message: {}", e);
+                    }
                 }
+                *destination = PacketDestination::None;
             }
-            *destination = PacketDestination::None;
-        }
-    }))
+        }),
+    }
 }
 
 impl SplinterClient {
@@ -45,15 +53,51 @@ impl SplinterClient {
         &self,
         msg: impl ToChat,
         sender: &CommandSender,
+    ) -> anyhow::Result<()> {
+        let position = match sender {
+            CommandSender::Player(_) => ChatPosition::ChatBox,
+            CommandSender::Console | CommandSender::Rcon(..) => ChatPosition::SystemMessage,
+        };
+        self.write_chat(msg.to_chat(), position, sender.uuid())
+            .await
+    }
+    /// Tells the client which server it is now on, if a routing message is configured for it
+    pub async fn send_routing_message(&self) -> anyhow::Result<()> {
+        let server_id = self.server_id();
+        let config = self.proxy.config();
+        let message = config.routing_messages.message_for(server_id);
+        let position = match message.channel {
+            MessageChannel::None => return Ok(()),
+            MessageChannel::Chat => ChatPosition::SystemMessage,
+            MessageChannel::ActionBar => ChatPosition::GameInfo,
+        };
+        self.write_chat(
+            message
+                .text
+                .replace("{server}", &server_id.to_string())
+                .to_chat(),
+            position,
+            CommandSender::Console.uuid(),
+        )
+        .await
+    }
+    /// Writes a chat message in the packet form of the client's protocol version. Every chat
+    /// sending helper goes through this, so supporting another version only changes this function
+    ///
+    /// 1.16 and 1.17 (protocols 735 to 756) send `PlayServerChatMessage`, unsigned, with the
+    /// position and sender given here. 1.19 and later split it into signed player chat and
+    /// unsigned system chat; messages from the proxy itself would be sent as system chat there
+    pub async fn write_chat(
+        &self,
+        message: Chat,
+        position: ChatPosition,
+        sender: UUID4,
     ) -> anyhow::Result<()> {
         self.write_packet(LazyDeserializedPacket::from_packet(
             PacketLatest::PlayServerChatMessage(PlayServerChatMessageSpec {
-                message: msg.to_chat(),
-                position: match sender {
-                    CommandSender::Player(_) => ChatPosition::ChatBox,
-                    CommandSender::Console => ChatPosition::SystemMessage,
-                },
-                sender: sender.uuid(),
+                message,
+                position,
+                sender,
             }),
         ))
         .await
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use super::{
     PacketDestination,
     RelayPass,
@@ -12,44 +14,86 @@ use crate::{
             ChunkLoadData,
             SplinterClient,
         },
+        lru::LruMap,
         server::SplinterServerConnection,
     },
+    systems::zoning::world_to_chunk_position,
 };
 
 inventory::submit! {
-    RelayPass(Box::new(|_proxy, connection, client, _sender, lazy_packet, destination| {
-        if matches!(lazy_packet.kind(),
-            PacketLatestKind::PlayChunkData
-            | PacketLatestKind::PlayUpdateLight
-            | PacketLatestKind::PlayUnloadChunk
-        ) {
-            if let Ok(packet) = lazy_packet.packet() {
-                let pass_through = smol::block_on(async {
-                    match packet {
-                        PacketLatest::PlayChunkData(body) => {
-                            let chunk = (body.x, body.z);
-                            connection.update_chunk(&*client, true, chunk).await
-                        },
-                        PacketLatest::PlayUpdateLight(body) => {
-                            let chunk = (*body.chunk.x, *body.chunk.z);
-                            connection.update_chunk(&*client, false, chunk).await
-                        },
-                        PacketLatest::PlayUnloadChunk(body) => {
-                            let chunk = (body.position.x, body.position.z);
-                            connection.remove_chunk(&*client, chunk).await
-                        },
-                        _ => unreachable!(),
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, _sender, lazy_packet, destination| {
+            if matches!(lazy_packet.kind(),
+                PacketLatestKind::PlayChunkData
+                | PacketLatestKind::PlayUpdateLight
+                | PacketLatestKind::PlayUnloadChunk
+            ) {
+                if let Ok(packet) = lazy_packet.packet() {
+                    let pass_through = smol::block_on(async {
+                        match packet {
+                            PacketLatest::PlayChunkData(body) => {
+                                let chunk = (body.x, body.z);
+                                connection.update_chunk(&*client, true, chunk).await
+                            },
+                            PacketLatest::PlayUpdateLight(body) => {
+                                let chunk = (*body.chunk.x, *body.chunk.z);
+                                connection.update_chunk(&*client, false, chunk).await
+                            },
+                            PacketLatest::PlayUnloadChunk(body) => {
+                                let chunk = (body.position.x, body.position.z);
+                                connection.remove_chunk(&*client, chunk).await
+                            },
+                            _ => unreachable!(),
+                        }
+                    });
+                    if !pass_through {
+                        *destination = PacketDestination::None;
                     }
-                });
-                if !pass_through {
-                    *destination = PacketDestination::None;
                 }
             }
+        }),
+    }
+}
+
+impl SplinterClient {
+    /// Whether a chunk that a background connection hasn't sent before may be relayed, per the
+    /// `background_chunks` config
+    pub fn wants_background_chunk(&self, (chunk_x, chunk_z): (i32, i32)) -> bool {
+        let config = &self.proxy.config().background_chunks;
+        if config.cull {
+            return false;
+        }
+        match config.radius {
+            Some(radius) => {
+                let position = self.position.load();
+                let (x, z) = world_to_chunk_position((position.x, position.z));
+                let distance = (chunk_x as i64 - x as i64)
+                    .abs()
+                    .max((chunk_z as i64 - z as i64).abs());
+                distance <= radius as i64
+            }
+            None => true,
         }
-    }))
+    }
 }
 
 impl SplinterServerConnection {
+    /// `update_chunk` for a connection that isn't the client's active one. A chunk the connection
+    /// isn't tracking yet is left untracked if the client doesn't want it, so that unloading it
+    /// later isn't passed on either
+    pub async fn update_background_chunk(
+        &self,
+        client: &SplinterClient,
+        is_chunkdata: bool,
+        chunk: (i32, i32),
+    ) -> bool {
+        if !self.known_chunks.lock().await.contains(&chunk) && !client.wants_background_chunk(chunk)
+        {
+            return false;
+        }
+        self.update_chunk(client, is_chunkdata, chunk).await
+    }
     /// Returns whether we pass the packet on
     pub async fn update_chunk(
         &self,
@@ -57,51 +101,130 @@ impl SplinterServerConnection {
         is_chunkdata: bool,
         chunk: (i32, i32),
     ) -> bool {
-        let newly_added_to_self = self.known_chunks.lock().await.insert(chunk);
-        let client_known_chunks = &mut *client.known_chunks.lock().await;
-        if let Some(load_data) = client_known_chunks.get_mut(&chunk) {
-            if newly_added_to_self {
-                load_data.refcount += 1;
-            }
-            if is_chunkdata {
-                if !load_data.received_chunkdata {
-                    load_data.received_chunkdata = true;
-                    true
-                } else {
-                    false
-                }
-            } else {
-                if !load_data.received_updatelight {
-                    load_data.received_updatelight = true;
-                    true
-                } else {
-                    false
-                }
-            }
+        track_chunk(
+            &mut *self.known_chunks.lock().await,
+            &mut *client.known_chunks.lock().await,
+            is_chunkdata,
+            chunk,
+        )
+    }
+    /// Returns whether we pass the unload on, which is once no connection holds the chunk
+    pub async fn remove_chunk(&self, client: &SplinterClient, chunk: (i32, i32)) -> bool {
+        release_chunk(
+            &mut *self.known_chunks.lock().await,
+            &mut *client.known_chunks.lock().await,
+            chunk,
+        )
+    }
+}
+
+/// Records that a connection sent a chunk's data or light, returning whether the client should
+/// be sent it. Each connection holding the chunk counts once towards its refcount, and the
+/// client is only sent the first data and light of a chunk it doesn't have yet
+pub fn track_chunk(
+    connection_chunks: &mut HashSet<(i32, i32)>,
+    client_chunks: &mut LruMap<(i32, i32), ChunkLoadData>,
+    is_chunkdata: bool,
+    chunk: (i32, i32),
+) -> bool {
+    let newly_added_to_connection = connection_chunks.insert(chunk);
+    if let Some(load_data) = client_chunks.get_mut(&chunk) {
+        if newly_added_to_connection {
+            load_data.refcount += 1;
+        }
+        if is_chunkdata {
+            !std::mem::replace(&mut load_data.received_chunkdata, true)
         } else {
-            client_known_chunks.insert(
-                chunk,
-                ChunkLoadData {
-                    received_chunkdata: is_chunkdata,
-                    received_updatelight: !is_chunkdata,
-                    refcount: 1,
-                },
-            );
-            true
+            !std::mem::replace(&mut load_data.received_updatelight, true)
         }
+    } else {
+        client_chunks.insert(
+            chunk,
+            ChunkLoadData {
+                received_chunkdata: is_chunkdata,
+                received_updatelight: !is_chunkdata,
+                refcount: 1,
+            },
+        );
+        true
     }
-    pub async fn remove_chunk(&self, client: &SplinterClient, chunk: (i32, i32)) -> bool {
-        if self.known_chunks.lock().await.remove(&chunk) {
-            let client_known_chunks = &mut *client.known_chunks.lock().await;
-            if let Some(load_data) = client_known_chunks.get_mut(&chunk) {
-                if load_data.refcount > 1 {
-                    load_data.refcount -= 1;
-                } else {
-                    client_known_chunks.remove(&chunk);
-                    return true;
-                }
+}
+
+/// Records that a connection unloaded a chunk, returning whether the client should unload it
+/// too, which is once no other connection holds it
+pub fn release_chunk(
+    connection_chunks: &mut HashSet<(i32, i32)>,
+    client_chunks: &mut LruMap<(i32, i32), ChunkLoadData>,
+    chunk: (i32, i32),
+) -> bool {
+    if connection_chunks.remove(&chunk) {
+        if let Some(load_data) = client_chunks.get_mut(&chunk) {
+            if load_data.refcount > 1 {
+                load_data.refcount -= 1;
+            } else {
+                client_chunks.remove(&chunk);
+                return true;
             }
         }
-        return false;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refcount(client_chunks: &mut LruMap<(i32, i32), ChunkLoadData>, chunk: (i32, i32)) -> usize {
+        client_chunks
+            .get_mut(&chunk)
+            .map(|load_data| load_data.refcount)
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn tracked_chunk_is_removed_on_unload() {
+        let mut connection = HashSet::new();
+        let mut client = LruMap::new(16);
+        assert!(track_chunk(&mut connection, &mut client, true, (1, 2)));
+        assert!(track_chunk(&mut connection, &mut client, false, (1, 2)));
+        // resent data for a chunk the client has isn't passed on
+        assert!(!track_chunk(&mut connection, &mut client, true, (1, 2)));
+        assert_eq!(refcount(&mut client, (1, 2)), 1);
+        assert!(release_chunk(&mut connection, &mut client, (1, 2)));
+        assert!(!connection.contains(&(1, 2)));
+        assert_eq!(refcount(&mut client, (1, 2)), 0);
+        // a second unload of the same chunk isn't passed on
+        assert!(!release_chunk(&mut connection, &mut client, (1, 2)));
+    }
+
+    #[test]
+    fn chunk_held_by_two_connections_unloads_after_both() {
+        let (mut first, mut second) = (HashSet::new(), HashSet::new());
+        let mut client = LruMap::new(16);
+        assert!(track_chunk(&mut first, &mut client, true, (0, 0)));
+        assert!(!track_chunk(&mut second, &mut client, true, (0, 0)));
+        assert_eq!(refcount(&mut client, (0, 0)), 2);
+        assert!(!release_chunk(&mut first, &mut client, (0, 0)));
+        assert_eq!(refcount(&mut client, (0, 0)), 1);
+        assert!(release_chunk(&mut second, &mut client, (0, 0)));
+    }
+
+    #[test]
+    fn switch_releases_old_connection_chunks() {
+        let (mut old, mut new) = (HashSet::new(), HashSet::new());
+        let mut client = LruMap::new(16);
+        track_chunk(&mut old, &mut client, true, (0, 0));
+        track_chunk(&mut old, &mut client, true, (5, 5));
+        track_chunk(&mut new, &mut client, true, (5, 5));
+        // what a server switch does with the old connection's chunks
+        for chunk in old.clone() {
+            release_chunk(&mut old, &mut client, chunk);
+        }
+        assert!(old.is_empty());
+        assert_eq!(refcount(&mut client, (0, 0)), 0);
+        assert_eq!(refcount(&mut client, (5, 5)), 1);
+        // the new connection can send the released chunk again
+        assert!(track_chunk(&mut new, &mut client, true, (0, 0)));
+        assert!(!track_chunk(&mut new, &mut client, true, (5, 5)));
     }
 }
@@ -0,0 +1,137 @@
+use super::RelayPass;
+use crate::{
+    protocol::{
+        current::{
+            proto::{
+                CommandLiteralNodeSpec,
+                CommandNode,
+                CommandNodeSpec,
+                PlayDeclareCommandsSpec,
+            },
+            protocol::PacketDirection,
+            types::VarInt,
+            PacketLatest,
+            PacketLatestKind,
+        },
+        events::LazyDeserializedPacket,
+    },
+    proxy::client::SplinterClient,
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, _client, sender, lazy_packet, _destination| {
+            if *sender == PacketDirection::ClientBound
+                && lazy_packet.kind() == PacketLatestKind::PlayDeclareCommands
+            {
+                match lazy_packet.packet() {
+                    Ok(PacketLatest::PlayDeclareCommands(body)) => {
+                        // kept as the server sent it, so a reload can add a different set of
+                        // proxy commands
+                        *smol::block_on(connection.declared_commands.lock()) = Some(body.clone());
+                        add_proxy_commands(body, &proxy.config().player_commands);
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => error!("Failed to deserialize declare commands: {}", e),
+                }
+            }
+        }),
+    }
+}
+
+impl SplinterClient {
+    /// Sends the client the command tree its active server last declared, with the proxy's
+    /// current commands added. Does nothing if the server has not declared one
+    pub async fn resend_commands(&self) -> anyhow::Result<()> {
+        let declared = self
+            .active_server
+            .load()
+            .declared_commands
+            .lock()
+            .await
+            .clone();
+        if let Some(mut body) = declared {
+            add_proxy_commands(&mut body, &self.proxy.config().player_commands);
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayDeclareCommands(body),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds each command as an executable literal under the root of the server's command tree, so
+/// that the client can complete them. Commands the server already declares are left alone
+pub fn add_proxy_commands(body: &mut PlayDeclareCommandsSpec, names: &[String]) {
+    let root_index = *body.root_index as usize;
+    let mut nodes = body.nodes.iter().cloned().collect::<Vec<CommandNodeSpec>>();
+    if root_index >= nodes.len() {
+        return warn!("Declare commands packet has no root node");
+    }
+    for name in names {
+        let exists = nodes[root_index].children_indices.iter().any(|index| {
+            matches!(
+                nodes.get(**index as usize).map(|node| &node.node),
+                Some(CommandNode::Literal(literal)) if literal.name == *name
+            )
+        });
+        if exists {
+            continue;
+        }
+        let new_index = nodes.len() as i32;
+        nodes.push(CommandNodeSpec {
+            children_indices: vec![].into(),
+            redirect_node: None,
+            is_executable: true,
+            node: CommandNode::Literal(CommandLiteralNodeSpec {
+                name: name.clone(),
+            }),
+        });
+        let mut children = nodes[root_index]
+            .children_indices
+            .iter()
+            .copied()
+            .collect::<Vec<VarInt>>();
+        children.push(new_index.into());
+        nodes[root_index].children_indices = children.into();
+    }
+    body.nodes = nodes.into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal_names(body: &PlayDeclareCommandsSpec) -> Vec<String> {
+        body.nodes[*body.root_index as usize]
+            .children_indices
+            .iter()
+            .filter_map(|index| match &body.nodes[**index as usize].node {
+                CommandNode::Literal(literal) => Some(literal.name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn declared_tree_takes_the_current_commands() {
+        let declared = PlayDeclareCommandsSpec {
+            nodes: vec![CommandNodeSpec {
+                children_indices: vec![].into(),
+                redirect_node: None,
+                is_executable: false,
+                node: CommandNode::Root,
+            }]
+            .into(),
+            root_index: 0.into(),
+        };
+        let mut before = declared.clone();
+        add_proxy_commands(&mut before, &["hub".into()]);
+        assert_eq!(literal_names(&before), vec!["hub"]);
+        let mut after = declared;
+        add_proxy_commands(&mut after, &["lobby".into()]);
+        assert_eq!(literal_names(&after), vec!["lobby"]);
+    }
+}
@@ -0,0 +1,104 @@
+use super::RelayPass;
+use crate::protocol::current::{
+    proto::{GameMode, PlayJoinGameSpec, PlayRespawnSpec, PreviousGameMode},
+    protocol::PacketDirection,
+    types::NamedNbtTag,
+    PacketLatest, PacketLatestKind,
+};
+
+/// World a client is moved to for a moment so that respawning into the world it was already in
+/// reloads it. The client only starts the world over if the world name changes
+const RELOAD_WORLD: &str = "splinter:reload";
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, _client, sender, lazy_packet, _destination| {
+            if *sender == PacketDirection::ClientBound
+                && lazy_packet.kind() == PacketLatestKind::PlayRespawn
+            {
+                match lazy_packet.packet() {
+                    Ok(PacketLatest::PlayRespawn(body)) => {
+                        *smol::block_on(connection.dimension.lock()) = Some(ServerDimension::from(&*body));
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => error!("Failed to deserialize respawn: {}", e),
+                }
+            }
+        }),
+    }
+}
+
+/// The world a server has placed a player in, from the server's join game packet or its latest
+/// respawn packet
+#[derive(Clone)]
+pub struct ServerDimension {
+    pub dimension: NamedNbtTag,
+    pub world_name: String,
+    pub hashed_seed: i64,
+    pub gamemode: GameMode,
+    pub previous_gamemode: PreviousGameMode,
+    pub is_debug: bool,
+    pub is_flat: bool,
+}
+
+impl From<&PlayJoinGameSpec> for ServerDimension {
+    fn from(body: &PlayJoinGameSpec) -> Self {
+        Self {
+            dimension: body.dimension.clone(),
+            world_name: body.world_name.clone(),
+            hashed_seed: body.hashed_seed,
+            gamemode: body.gamemode.clone(),
+            previous_gamemode: body.previous_gamemode.clone(),
+            is_debug: body.is_debug,
+            is_flat: body.is_flat,
+        }
+    }
+}
+
+impl From<&PlayRespawnSpec> for ServerDimension {
+    fn from(body: &PlayRespawnSpec) -> Self {
+        Self {
+            dimension: body.dimension.clone(),
+            world_name: body.world_name.clone(),
+            hashed_seed: body.hashed_seed,
+            gamemode: body.gamemode.clone(),
+            previous_gamemode: body.previous_gamemode.clone(),
+            is_debug: body.is_debug,
+            is_flat: body.is_flat,
+        }
+    }
+}
+
+impl ServerDimension {
+    /// Whether both servers simulate the same world, so that the client can be handed from one to
+    /// the other without reloading it
+    pub fn same_world(&self, other: &ServerDimension) -> bool {
+        self.world_name == other.world_name && self.hashed_seed == other.hashed_seed
+    }
+    fn respawn_packet(&self, world_name: String) -> PacketLatest {
+        PacketLatest::PlayRespawn(PlayRespawnSpec {
+            dimension: self.dimension.clone(),
+            world_name,
+            hashed_seed: self.hashed_seed,
+            gamemode: self.gamemode.clone(),
+            previous_gamemode: self.previous_gamemode.clone(),
+            is_debug: self.is_debug,
+            is_flat: self.is_flat,
+            copy_metadata: true,
+        })
+    }
+    /// Packets moving a client shown the previous world into this one, which are none if it is
+    /// the same world. A different world under the same name, ex. another server's overworld, is
+    /// reloaded by passing through `RELOAD_WORLD` on the way
+    pub fn change_packets(&self, previous: Option<&ServerDimension>) -> Vec<PacketLatest> {
+        match previous {
+            Some(previous) if previous.same_world(self) => vec![],
+            Some(previous) if previous.world_name == self.world_name => vec![
+                self.respawn_packet(RELOAD_WORLD.into()),
+                self.respawn_packet(self.world_name.clone()),
+            ],
+            _ => vec![self.respawn_packet(self.world_name.clone())],
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::current::{
+        protocol::PacketDirection,
+        types::Vec3,
+        PacketLatest,
+        PacketLatestKind,
+    },
+    proxy::{
+        client::SplinterClient,
+        config::BackgroundEffects,
+    },
+};
+
+/// Kinds of the packets that play a sound or show particles at a position in the world
+pub const EFFECT_KINDS: &[PacketLatestKind] = &[
+    PacketLatestKind::PlayNamedSoundEffect,
+    PacketLatestKind::PlaySoundEffect,
+    PacketLatestKind::PlayParticle,
+];
+
+// effects read from a connection just before the client switched away from it. Connections
+// are compared rather than server ids, since the client may have reconnected to the same server
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, sender, lazy_packet, destination| {
+            if *sender == PacketDirection::ClientBound
+                && EFFECT_KINDS.contains(&lazy_packet.kind())
+                && !Arc::ptr_eq(connection, &client.active_server.load())
+            {
+                if let Ok(packet) = lazy_packet.packet() {
+                    if !client.wants_background_effect(packet) {
+                        *destination = PacketDestination::None;
+                    }
+                }
+            }
+        }),
+    }
+}
+
+/// Where in the world an effect packet happens
+pub fn effect_position(packet: &PacketLatest) -> Option<Vec3<f64>> {
+    // sound positions are fixed-point, in eighths of a block
+    let sound_position = |x: i32, y: i32, z: i32| Vec3 {
+        x: x as f64 / 8.,
+        y: y as f64 / 8.,
+        z: z as f64 / 8.,
+    };
+    match packet {
+        PacketLatest::PlayNamedSoundEffect(body) => Some(sound_position(
+            body.position_x,
+            body.position_y,
+            body.position_z,
+        )),
+        PacketLatest::PlaySoundEffect(body) => Some(sound_position(
+            body.position_x,
+            body.position_y,
+            body.position_z,
+        )),
+        PacketLatest::PlayParticle(body) => Some(body.position.clone()),
+        _ => None,
+    }
+}
+
+impl SplinterClient {
+    /// Whether an effect packet from a connection that isn't the client's active one may be
+    /// relayed, per the `background_effects` config
+    pub fn wants_background_effect(&self, packet: &PacketLatest) -> bool {
+        match self.proxy.config().background_effects {
+            BackgroundEffects::Suppress => false,
+            BackgroundEffects::Pass => true,
+            BackgroundEffects::WithinDistance(distance) => match effect_position(packet) {
+                Some(effect) => {
+                    let position = self.position.load();
+                    let (dx, dy, dz) = (
+                        effect.x - position.x,
+                        effect.y - position.y,
+                        effect.z - position.z,
+                    );
+                    dx * dx + dy * dy + dz * dz <= distance * distance
+                }
+                None => false,
+            },
+        }
+    }
+}
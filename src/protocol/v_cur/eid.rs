@@ -1,17 +1,33 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use anyhow::Context;
+
 use super::{
     PacketDestination,
     RelayPass,
+    ENTITY_MAP,
 };
 use crate::{
-    protocol::current::{
-        proto::{
-            EntityMetadataFieldData,
-            SculkDestinationIdentifier,
+    protocol::{
+        current::{
+            proto::{
+                EntityMetadataFieldData,
+                SculkDestinationIdentifier,
+            },
+            protocol::PacketDirection,
+            types::VarInt,
+            PacketLatest,
+            PacketLatestKind,
         },
-        protocol::PacketDirection,
-        types::VarInt,
-        PacketLatest,
-        PacketLatestKind,
+        load_json_id_name_pairs,
     },
     proxy::{
         client::SplinterClient,
@@ -21,28 +37,96 @@ use crate::{
             SplinterMappingResult,
         },
         server::SplinterServer,
+        SplinterProxy,
     },
 };
 
 inventory::submit! {
-    RelayPass(Box::new(|proxy, connection, client, sender, lazy_packet, destination| {
-        if has_eids(lazy_packet.kind()) {
-            if let Ok(packet) = lazy_packet.packet() {
-                let map = &mut *smol::block_on(proxy.mapping.lock());
-                match map_eid(&*client, map, packet, sender, &connection.server) {
-                    SplinterMappingResult::Server(server_id) => {
-                        *destination = PacketDestination::Server(server_id);
-                        //debug!("mapping packet {:?} to server {}", lazy_packet.kind(), server_id);
-                    }
-                    SplinterMappingResult::None => {
-                        *destination = PacketDestination::None;
-                        //debug!("refusing to send packet of kind {:?} (no eid mapping)", packet);
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, client, sender, lazy_packet, destination| {
+            if has_eids(lazy_packet.kind()) {
+                if let Ok(packet) = lazy_packet.packet() {
+                    // server-side ids of destroyed entities, taken before they're mapped
+                    let destroyed = match packet {
+                        PacketLatest::PlayDestroyEntities(body) if *sender == PacketDirection::ClientBound => {
+                            body.entity_ids.iter().map(|eid| **eid).collect::<Vec<i32>>()
+                        }
+                        _ => vec![],
+                    };
+                    let result = {
+                        let map = &mut *smol::block_on(proxy.mapping.lock());
+                        map_eid(&*client, map, packet, sender, &connection.server)
+                    };
+                    match result {
+                        SplinterMappingResult::Server(server_id) => {
+                            *destination = PacketDestination::Server(server_id);
+                            //debug!("mapping packet {:?} to server {}", lazy_packet.kind(), server_id);
+                        }
+                        SplinterMappingResult::None => {
+                            *destination = PacketDestination::None;
+                            //debug!("refusing to send packet of kind {:?} (no eid mapping)", packet);
+                        }
+                        SplinterMappingResult::Client => {
+                            if !destroyed.is_empty() {
+                                smol::block_on(free_destroyed_eids(proxy, connection.server.id, destroyed));
+                            }
+                        }
                     }
-                    _ => {}
                 }
             }
+        }),
+    }
+}
+
+/// Frees the mappings of a server's destroyed entities that no client knows of anymore, so that
+/// their proxy-side ids can be reused
+///
+/// The mapping is unlocked while the clients are checked, so that the player list is never
+/// waited on while holding it
+async fn free_destroyed_eids(proxy: &SplinterProxy, server_id: u64, server_eids: Vec<i32>) {
+    let destroyed = {
+        let map = proxy.mapping.lock().await;
+        server_eids
+            .into_iter()
+            .filter_map(|server_eid| {
+                map.eids
+                    .get_by_right(&(server_id, server_eid))
+                    .map(|proxy_eid| (*proxy_eid, server_eid))
+            })
+            .collect::<Vec<(i32, i32)>>()
+    };
+    let clients = proxy
+        .players
+        .read()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut unused = Vec::with_capacity(destroyed.len());
+    for (proxy_eid, server_eid) in destroyed {
+        let mut in_use = false;
+        for client in clients.iter() {
+            // a player's own entity is never in its own known eids, but is still in use
+            let active = client.active_server.load();
+            if (active.server.id == server_id && active.eid == server_eid)
+                || client.known_eids.lock().await.contains(&proxy_eid)
+            {
+                in_use = true;
+                break;
+            }
+        }
+        if !in_use {
+            unused.push((proxy_eid, server_eid));
+        }
+    }
+    let map = &mut *proxy.mapping.lock().await;
+    for (proxy_eid, server_eid) in unused {
+        // the entity may have been spawned again under a new mapping in the meantime
+        if map.eids.get_by_left(&proxy_eid) == Some(&(server_id, server_eid)) {
+            map.free_eid(proxy_eid);
         }
-    }))
+    }
 }
 
 pub fn has_eids(kind: PacketLatestKind) -> bool {
@@ -85,12 +169,130 @@ pub fn has_eids(kind: PacketLatestKind) -> bool {
     )
 }
 
+/// Reads a server's entity type ids from a file in the format of minecraft-data's
+/// `entities.json`, mapped by name to the proxy-side ids. Types the proxy doesn't know are left
+/// out
+pub fn load_entity_types(path: impl AsRef<Path>) -> anyhow::Result<HashMap<i32, i32>> {
+    let path = path.as_ref();
+    let data = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read entity types from {}", path.display()))?;
+    let mut types = HashMap::new();
+    let pairs = load_json_id_name_pairs(data)
+        .with_context(|| format!("Failed to parse entity types from {}", path.display()))?;
+    for (server_type, name) in pairs {
+        match ENTITY_MAP.get_by_right(&name) {
+            Some(proxy_type) => {
+                types.insert(server_type, *proxy_type);
+            }
+            None => warn!("Unknown entity type \"{}\" in {}", name, path.display()),
+        }
+    }
+    Ok(types)
+}
+
+/// Gets the server-side eid of the entity a packet spawns, if it spawns one
+fn spawned_eid(packet: &PacketLatest) -> Option<i32> {
+    match packet {
+        PacketLatest::PlaySpawnEntity(body) => Some(*body.entity_id),
+        PacketLatest::PlaySpawnExperienceOrb(body) => Some(*body.entity_id),
+        PacketLatest::PlaySpawnLivingEntity(body) => Some(*body.entity_id),
+        PacketLatest::PlaySpawnPainting(body) => Some(*body.entity_id),
+        PacketLatest::PlaySpawnPlayer(body) => Some(*body.entity_id),
+        _ => None,
+    }
+}
+
+/// Gets the eids in a combat event. The attacker is -1 when there is none, ex. fall damage, and
+/// is left out
+fn combat_event_eids(packet: &mut PacketLatest) -> (Vec<&mut i32>, Vec<&mut VarInt>) {
+    match packet {
+        PacketLatest::PlayEndCombatEvent(body) => (
+            if body.entity_id < 0 {
+                vec![]
+            } else {
+                vec![&mut body.entity_id]
+            },
+            vec![],
+        ),
+        PacketLatest::PlayDeathCombatEvent(body) => (
+            if body.entity_id < 0 {
+                vec![]
+            } else {
+                vec![&mut body.entity_id]
+            },
+            vec![&mut body.player_id],
+        ),
+        _ => (vec![], vec![]),
+    }
+}
+
+/// Replaces server-side eids of the server with their proxy-side ids. Fails if any of them has
+/// no mapping, in which case the ids may be partly replaced
+fn map_server_eids(
+    map: &SplinterMapping,
+    server_id: u64,
+    nums: Vec<&mut i32>,
+    varnums: Vec<&mut VarInt>,
+) -> bool {
+    for id in nums {
+        *id = if let Some(mapped_id) = map.eids.get_by_right(&(server_id, *id)) {
+            *mapped_id
+        } else {
+            return false;
+        };
+    }
+    for id in varnums {
+        *id = if let Some(mapped_id) = map.eids.get_by_right(&(server_id, **id)) {
+            (*mapped_id).into()
+        } else {
+            return false;
+        };
+    }
+    true
+}
+
 pub fn map_eid(
     client: &SplinterClient,
     map: &mut SplinterMapping,
     packet: &mut PacketLatest,
     sender: &PacketDirection,
     server: &SplinterServer,
+) -> SplinterMappingResult {
+    let known_eids = &mut *smol::block_on(client.known_eids.lock());
+    if *sender == PacketDirection::ClientBound {
+        if let Some(server_eid) = spawned_eid(packet) {
+            // a new entity past the cap is never shown to the client, so a server spawning
+            // endless entities can't exhaust the proxy's memory or ids
+            if known_eids.len() >= client.proxy.config().max_mapped_entities
+                && map.eids.get_by_right(&(server.id, server_eid)).is_none()
+            {
+                client
+                    .proxy
+                    .metrics
+                    .entity_cap_hits
+                    .fetch_add(1, Ordering::Relaxed);
+                if client.entity_cap_hits.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!(
+                        "\"{}\" reached the cap of {} mapped entities; dropping new entities from server {}",
+                        &client.name, client.proxy.config().max_mapped_entities, server.id
+                    );
+                }
+                return SplinterMappingResult::None;
+            }
+        }
+    }
+    remap_eids(map, known_eids, packet, sender, server.id)
+}
+
+/// Rewrites the entity ids in a packet from the server's ids to the proxy's, or from the proxy's
+/// to the server's for packets from the client. Spawned entities get new proxy-side ids, and the
+/// proxy-side ids the client is shown are kept in `known_eids`
+pub fn remap_eids(
+    map: &mut SplinterMapping,
+    known_eids: &mut HashSet<i32>,
+    packet: &mut PacketLatest,
+    sender: &PacketDirection,
+    server_id: u64,
 ) -> SplinterMappingResult {
     match sender {
         PacketDirection::ClientBound => {
@@ -116,8 +318,6 @@ pub fn map_eid(
                 PacketLatest::PlayEntityTeleport(body) => (vec![], vec![&mut body.entity_id]),
                 PacketLatest::PlayEntityProperties(body) => (vec![], vec![&mut body.entity_id]),
                 PacketLatest::PlayEntityEffect(body) => (vec![], vec![&mut body.entity_id]),
-                PacketLatest::PlayEndCombatEvent(body) => (vec![&mut body.entity_id], vec![]),
-                PacketLatest::PlayDeathCombatEvent(body) => (vec![&mut body.entity_id], vec![]),
 
                 // slightly more complex
                 PacketLatest::PlaySculkVibrationSignal(body) => {
@@ -142,6 +342,9 @@ pub fn map_eid(
                     },
                     vec![],
                 ),
+                PacketLatest::PlayEndCombatEvent(_) | PacketLatest::PlayDeathCombatEvent(_) => {
+                    combat_event_eids(packet)
+                }
                 PacketLatest::PlayCollectItem(body) => (
                     vec![],
                     vec![&mut body.collected_entity_id, &mut body.collector_entity_id],
@@ -162,12 +365,15 @@ pub fn map_eid(
 
                 // entity spawning
                 PacketLatest::PlaySpawnEntity(body) => {
+                    body.entity_type = map.map_entity_type(server_id, *body.entity_type).into();
                     let entity_type = *body.entity_type;
                     entity_data = Some(EntityData {
                         id: *body.entity_id,
                         entity_type,
                     });
-                    body.entity_id = map.register_eid_mapping(server.id, *body.entity_id).into();
+                    let new_eid = map.register_eid_mapping(server_id, *body.entity_id);
+                    known_eids.insert(new_eid);
+                    body.entity_id = new_eid.into();
                     // debug!("entity spawn type: {}", entity_type);
                     (
                         match entity_type {
@@ -180,10 +386,10 @@ pub fn map_eid(
                                 if body.data > 0 {
                                     // body.data is option varint. we need to specially handle this
                                     if let Some(mapped_id) =
-                                        map.eids.get_by_right(&(server.id, body.data - 1))
+                                        map.eids.get_by_right(&(server_id, body.data - 1))
                                     {
                                         body.data = mapped_id + 1;
-                                        smol::block_on(client.known_eids.lock()).insert(*mapped_id);
+                                        known_eids.insert(*mapped_id);
                                     } else {
                                         return SplinterMappingResult::None;
                                     }
@@ -203,18 +409,19 @@ pub fn map_eid(
                         id: *body.entity_id,
                         entity_type: 25,
                     });
-                    let new_eid = map.register_eid_mapping(server.id, *body.entity_id);
-                    smol::block_on(client.known_eids.lock()).insert(new_eid);
+                    let new_eid = map.register_eid_mapping(server_id, *body.entity_id);
+                    known_eids.insert(new_eid);
                     body.entity_id = new_eid.into();
                     (vec![], vec![])
                 }
                 PacketLatest::PlaySpawnLivingEntity(body) => {
+                    body.entity_type = map.map_entity_type(server_id, *body.entity_type).into();
                     entity_data = Some(EntityData {
                         id: *body.entity_id,
                         entity_type: *body.entity_type,
                     });
-                    let new_eid = map.register_eid_mapping(server.id, *body.entity_id);
-                    smol::block_on(client.known_eids.lock()).insert(new_eid);
+                    let new_eid = map.register_eid_mapping(server_id, *body.entity_id);
+                    known_eids.insert(new_eid);
                     body.entity_id = new_eid.into();
                     (vec![], vec![])
                 }
@@ -223,8 +430,8 @@ pub fn map_eid(
                         id: *body.entity_id,
                         entity_type: 60,
                     });
-                    let new_eid = map.register_eid_mapping(server.id, *body.entity_id);
-                    smol::block_on(client.known_eids.lock()).insert(new_eid);
+                    let new_eid = map.register_eid_mapping(server_id, *body.entity_id);
+                    known_eids.insert(new_eid);
                     body.entity_id = new_eid.into();
                     (vec![], vec![])
                 }
@@ -234,29 +441,31 @@ pub fn map_eid(
                         entity_type: 111,
                     });
                     let new_eid = if let Some(mapped_id) =
-                        map.eids.get_by_right(&(server.id, *body.entity_id))
+                        map.eids.get_by_right(&(server_id, *body.entity_id))
                     {
                         *mapped_id
                     } else {
-                        map.register_eid_mapping(server.id, *body.entity_id)
+                        map.register_eid_mapping(server_id, *body.entity_id)
                         // for whatever reason, server has two eids per player or something. im
                         // not sure. this fixes it though
                     };
-                    smol::block_on(client.known_eids.lock()).insert(new_eid);
+                    known_eids.insert(new_eid);
                     body.entity_id = new_eid.into();
+                    // must match the uuid the client got in the player info
+                    body.uuid = map.map_uuid(server_id, body.uuid);
                     (vec![], vec![])
                 }
                 // complex
                 PacketLatest::PlayEntityMetadata(body) => {
                     // we specially need to handle mapping here for the proxy side eid
                     let proxy_eid = if let Some(proxy_eid) =
-                        map.eids.get_by_right(&(server.id, *body.entity_id))
+                        map.eids.get_by_right(&(server_id, *body.entity_id))
                     {
                         *proxy_eid
                     } else {
                         return SplinterMappingResult::None;
                     };
-                    smol::block_on(client.known_eids.lock()).insert(proxy_eid);
+                    known_eids.insert(proxy_eid);
                     body.entity_id = proxy_eid.into();
                     if let Some(data) = map.entity_data.get(&proxy_eid) {
                         match data.entity_type {
@@ -268,10 +477,9 @@ pub fn map_eid(
                                     let found_id: i32 = **id;
                                     if found_id > 0 {
                                         if let Some(mapped_id) =
-                                            map.eids.get_by_right(&(server.id, found_id - 1))
+                                            map.eids.get_by_right(&(server_id, found_id - 1))
                                         {
-                                            smol::block_on(client.known_eids.lock())
-                                                .insert(*mapped_id);
+                                            known_eids.insert(*mapped_id);
                                             *id = (mapped_id + 1).into();
                                         } else {
                                             return SplinterMappingResult::None;
@@ -287,10 +495,9 @@ pub fn map_eid(
                                     let found_id: i32 = **id;
                                     if found_id > 0 {
                                         if let Some(mapped_id) =
-                                            map.eids.get_by_right(&(server.id, found_id - 1))
+                                            map.eids.get_by_right(&(server_id, found_id - 1))
                                         {
-                                            smol::block_on(client.known_eids.lock())
-                                                .insert(*mapped_id);
+                                            known_eids.insert(*mapped_id);
                                             *id = (mapped_id + 1).into();
                                         } else {
                                             return SplinterMappingResult::None;
@@ -307,10 +514,9 @@ pub fn map_eid(
                                         let found_id: i32 = **id;
                                         if found_id > 0 {
                                             if let Some(mapped_id) =
-                                                map.eids.get_by_right(&(server.id, found_id - 1))
+                                                map.eids.get_by_right(&(server_id, found_id - 1))
                                             {
-                                                smol::block_on(client.known_eids.lock())
-                                                    .insert(*mapped_id);
+                                                known_eids.insert(*mapped_id);
                                                 *id = (mapped_id + 1).into(); // docs dont say + 1, but Im assuming that is the case here
                                             } else {
                                                 return SplinterMappingResult::None;
@@ -327,10 +533,9 @@ pub fn map_eid(
                                     let found_id: i32 = **id;
                                     if found_id > 0 {
                                         if let Some(mapped_id) =
-                                            map.eids.get_by_right(&(server.id, found_id - 1))
+                                            map.eids.get_by_right(&(server_id, found_id - 1))
                                         {
-                                            smol::block_on(client.known_eids.lock())
-                                                .insert(*mapped_id);
+                                            known_eids.insert(*mapped_id);
                                             *id = (mapped_id + 1).into(); // docs dont say +1, same as above
                                         } else {
                                             return SplinterMappingResult::None;
@@ -344,36 +549,29 @@ pub fn map_eid(
                     (vec![], vec![])
                 }
                 PacketLatest::PlayDestroyEntities(ref mut body) => {
-                    for eid in body.entity_ids.iter_mut() {
-                        // since we're removing the id from the mapping table here, we have to map them here as well
-                        *eid = if let Some(mapped_id) = map.eids.get_by_right(&(server.id, **eid)) {
-                            smol::block_on(client.known_eids.lock()).remove(mapped_id);
-                            (*mapped_id).into()
-                        } else {
-                            return SplinterMappingResult::None;
-                        };
+                    let mut mapped_eids = Vec::with_capacity(body.entity_ids.len());
+                    for eid in body.entity_ids.iter() {
+                        // entities without a mapping were never shown to the client (ex. ones
+                        // past the entity cap), so there is nothing to destroy for them
+                        if let Some(mapped_id) = map.eids.get_by_right(&(server_id, **eid)) {
+                            known_eids.remove(mapped_id);
+                            mapped_eids.push(VarInt::from(*mapped_id));
+                        }
+                    }
+                    if mapped_eids.is_empty() {
+                        return SplinterMappingResult::None;
                     }
+                    body.entity_ids = mapped_eids.into();
                     (vec![], vec![])
                 }
                 _ => unreachable!(),
             };
-            for id in nums {
-                *id = if let Some(mapped_id) = map.eids.get_by_right(&(server.id, *id)) {
-                    *mapped_id
-                } else {
-                    return SplinterMappingResult::None;
-                };
-            }
-            for id in varnums {
-                *id = if let Some(mapped_id) = map.eids.get_by_right(&(server.id, **id)) {
-                    (*mapped_id).into()
-                } else {
-                    return SplinterMappingResult::None;
-                };
+            if !map_server_eids(map, server_id, nums, varnums) {
+                return SplinterMappingResult::None;
             }
             if let Some(mut data) = entity_data {
                 let proxy_eid =
-                    if let Some(mapped_id) = map.eids.get_by_right(&(server.id, data.id)) {
+                    if let Some(mapped_id) = map.eids.get_by_right(&(server_id, data.id)) {
                         *mapped_id
                     } else {
                         return SplinterMappingResult::None;
@@ -399,3 +597,250 @@ pub fn map_eid(
     };
     return SplinterMappingResult::None;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::current::{
+        proto::{
+            CardinalDirection,
+            PlayCameraSpec,
+            PlayDeathCombatEventSpec,
+            PlayDestroyEntitiesSpec,
+            PlayEndCombatEventSpec,
+            PlayQueryEntityNbtSpec,
+            PlaySpawnExperienceOrbSpec,
+            PlaySpawnPaintingSpec,
+        },
+        types::{
+            Chat,
+            IntPosition,
+            Vec3,
+        },
+        uuid::UUID4,
+    };
+
+    fn camera(eid: i32) -> PacketLatest {
+        PacketLatest::PlayCamera(PlayCameraSpec {
+            camera_id: eid.into(),
+        })
+    }
+
+    fn camera_id(packet: &PacketLatest) -> i32 {
+        match packet {
+            PacketLatest::PlayCamera(body) => *body.camera_id,
+            _ => unreachable!(),
+        }
+    }
+
+    fn query_entity(eid: i32) -> PacketLatest {
+        PacketLatest::PlayQueryEntityNbt(PlayQueryEntityNbtSpec {
+            transaction_id: 1.into(),
+            entity_id: eid.into(),
+        })
+    }
+
+    fn queried_eid(packet: &PacketLatest) -> i32 {
+        match packet {
+            PacketLatest::PlayQueryEntityNbt(body) => *body.entity_id,
+            _ => unreachable!(),
+        }
+    }
+
+    fn map_combat_event(map: &SplinterMapping, server_id: u64, packet: &mut PacketLatest) -> bool {
+        let (nums, varnums) = combat_event_eids(packet);
+        map_server_eids(map, server_id, nums, varnums)
+    }
+
+    #[test]
+    fn death_event_gets_proxy_eids() {
+        let mut map = SplinterMapping::new();
+        let player_eid = map.register_eid_mapping(1, 10);
+        let attacker_eid = map.register_eid_mapping(1, 20);
+        let mut packet = PacketLatest::PlayDeathCombatEvent(PlayDeathCombatEventSpec {
+            player_id: 10.into(),
+            entity_id: 20,
+            message: Chat::from_text("slain"),
+        });
+        assert!(map_combat_event(&map, 1, &mut packet));
+        match packet {
+            PacketLatest::PlayDeathCombatEvent(body) => {
+                assert_eq!(*body.player_id, player_eid);
+                assert_eq!(body.entity_id, attacker_eid);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn death_event_without_attacker_keeps_it_absent() {
+        let mut map = SplinterMapping::new();
+        let player_eid = map.register_eid_mapping(1, 10);
+        let mut packet = PacketLatest::PlayDeathCombatEvent(PlayDeathCombatEventSpec {
+            player_id: 10.into(),
+            entity_id: -1,
+            message: Chat::from_text("fell"),
+        });
+        assert!(map_combat_event(&map, 1, &mut packet));
+        match packet {
+            PacketLatest::PlayDeathCombatEvent(body) => {
+                assert_eq!(*body.player_id, player_eid);
+                assert_eq!(body.entity_id, -1);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn end_event_uses_the_attackers_server() {
+        let mut map = SplinterMapping::new();
+        map.register_eid_mapping(1, 20);
+        let mut packet = PacketLatest::PlayEndCombatEvent(PlayEndCombatEventSpec {
+            duration: 5.into(),
+            entity_id: 20,
+        });
+        // the same server-side eid on another server is another entity
+        assert!(!map_combat_event(&map, 2, &mut packet));
+    }
+
+    #[test]
+    fn server_eids_become_proxy_eids() {
+        let mut map = SplinterMapping::new();
+        let mut known_eids = HashSet::new();
+        let proxy_eid = map.register_eid_mapping(1, 10);
+        let mut packet = camera(10);
+        assert_eq!(
+            remap_eids(
+                &mut map,
+                &mut known_eids,
+                &mut packet,
+                &PacketDirection::ClientBound,
+                1
+            ),
+            SplinterMappingResult::Client
+        );
+        assert_eq!(camera_id(&packet), proxy_eid);
+    }
+
+    #[test]
+    fn unknown_server_eid_is_not_sent() {
+        let mut map = SplinterMapping::new();
+        let mut known_eids = HashSet::new();
+        map.register_eid_mapping(1, 10);
+        // the same server-side eid on another server is another entity
+        for (server_id, eid) in [(1, 11), (2, 10)] {
+            let mut packet = camera(eid);
+            assert_eq!(
+                remap_eids(
+                    &mut map,
+                    &mut known_eids,
+                    &mut packet,
+                    &PacketDirection::ClientBound,
+                    server_id
+                ),
+                SplinterMappingResult::None
+            );
+        }
+    }
+
+    #[test]
+    fn proxy_eids_go_to_their_server() {
+        let mut map = SplinterMapping::new();
+        let mut known_eids = HashSet::new();
+        map.register_eid_mapping(1, 10);
+        let proxy_eid = map.register_eid_mapping(2, 10);
+        let mut packet = query_entity(proxy_eid);
+        assert_eq!(
+            remap_eids(
+                &mut map,
+                &mut known_eids,
+                &mut packet,
+                &PacketDirection::ServerBound,
+                1
+            ),
+            SplinterMappingResult::Server(2)
+        );
+        assert_eq!(queried_eid(&packet), 10);
+    }
+
+    #[test]
+    fn unknown_proxy_eid_is_not_sent() {
+        let mut map = SplinterMapping::new();
+        let mut known_eids = HashSet::new();
+        let proxy_eid = map.register_eid_mapping(1, 10);
+        let mut packet = query_entity(proxy_eid + 1);
+        assert_eq!(
+            remap_eids(
+                &mut map,
+                &mut known_eids,
+                &mut packet,
+                &PacketDirection::ServerBound,
+                1
+            ),
+            SplinterMappingResult::None
+        );
+        assert_eq!(queried_eid(&packet), proxy_eid + 1);
+    }
+
+    /// Spawns the entity from server 1 and destroys it again, checking that the client only ever
+    /// sees its proxy-side eid
+    fn spawn_and_destroy(mut spawn: PacketLatest, server_eid: i32, entity_type: i32) {
+        let mut map = SplinterMapping::new();
+        let mut known_eids = HashSet::new();
+        // the same server-side eid on another server, which must be left alone
+        let other_eid = map.register_eid_mapping(2, server_eid);
+        let clientbound = PacketDirection::ClientBound;
+        assert_eq!(
+            remap_eids(&mut map, &mut known_eids, &mut spawn, &clientbound, 1),
+            SplinterMappingResult::Client
+        );
+        let proxy_eid = spawned_eid(&spawn).unwrap();
+        assert_ne!(proxy_eid, other_eid);
+        assert_eq!(map.eids.get_by_left(&proxy_eid), Some(&(1, server_eid)));
+        assert!(known_eids.contains(&proxy_eid));
+        assert_eq!(map.entity_data[&proxy_eid].entity_type, entity_type);
+
+        let mut destroy = PacketLatest::PlayDestroyEntities(PlayDestroyEntitiesSpec {
+            entity_ids: vec![VarInt::from(server_eid)].into(),
+        });
+        assert_eq!(
+            remap_eids(&mut map, &mut known_eids, &mut destroy, &clientbound, 1),
+            SplinterMappingResult::Client
+        );
+        match destroy {
+            PacketLatest::PlayDestroyEntities(body) => {
+                assert_eq!(
+                    body.entity_ids
+                        .iter()
+                        .map(|eid| **eid)
+                        .collect::<Vec<i32>>(),
+                    vec![proxy_eid]
+                );
+            }
+            _ => unreachable!(),
+        }
+        assert!(!known_eids.contains(&proxy_eid));
+    }
+
+    #[test]
+    fn experience_orb_spawn_is_remapped() {
+        let spawn = PacketLatest::PlaySpawnExperienceOrb(PlaySpawnExperienceOrbSpec {
+            entity_id: 30.into(),
+            position: Vec3::from((0.5, 64., 0.5)),
+            count: 7,
+        });
+        spawn_and_destroy(spawn, 30, 25);
+    }
+
+    #[test]
+    fn painting_spawn_is_remapped() {
+        let spawn = PacketLatest::PlaySpawnPainting(PlaySpawnPaintingSpec {
+            entity_id: 40.into(),
+            entity_uuid: UUID4::from(40u128),
+            motive: 3.into(),
+            location: IntPosition { x: 1, y: 64, z: -3 },
+            direction: CardinalDirection::North,
+        });
+        spawn_and_destroy(spawn, 40, 60);
+    }
+}
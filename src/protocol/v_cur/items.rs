@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use super::RelayPass;
+use crate::protocol::current::{
+    protocol::PacketDirection, BytesSerializer, PacketLatest, PacketLatestKind, Serialize,
+};
+
+/// Window id of the player's own inventory
+const PLAYER_WINDOW: u8 = 0;
+
+// keeps each client's inventory current so that it can be saved with its player data
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, sender, lazy_packet, _destination| {
+            if !matches!(
+                lazy_packet.kind(),
+                PacketLatestKind::PlayWindowItems
+                    | PacketLatestKind::PlaySetSlot
+                    | PacketLatestKind::PlayCreativeInventoryAction
+            ) {
+                return;
+            }
+            // only the active server's inventory is the one the client sees
+            if *sender == PacketDirection::ClientBound && connection.server.id != client.server_id() {
+                return;
+            }
+            let packet = match lazy_packet.packet() {
+                Ok(packet) => packet,
+                Err(e) => return error!("Failed to deserialize inventory packet: {:?}", e),
+            };
+            let inventory = &mut *smol::block_on(client.inventory.lock());
+            match packet {
+                PacketLatest::PlayWindowItems(body) => {
+                    if body.window_id as u8 == PLAYER_WINDOW {
+                        inventory.clear();
+                        for (slot, item) in body.slots.iter().enumerate() {
+                            set_item(inventory, slot as i16, item);
+                        }
+                    }
+                }
+                PacketLatest::PlaySetSlot(body) => {
+                    if body.window_id as u8 == PLAYER_WINDOW {
+                        set_item(inventory, body.slot, &body.slot_data);
+                    }
+                }
+                PacketLatest::PlayCreativeInventoryAction(body) => {
+                    set_item(inventory, body.slot, &body.clicked_item);
+                }
+                _ => unreachable!(),
+            }
+        }),
+    }
+}
+
+fn set_item(inventory: &mut HashMap<i16, Vec<u8>>, slot: i16, item: &impl Serialize) {
+    let mut serializer = BytesSerializer::default();
+    if let Err(e) = item.mc_serialize(&mut serializer) {
+        return error!("Failed to serialize item in slot {}: {:?}", slot, e);
+    }
+    let data = serializer.into_bytes();
+    // an empty slot serializes to a single false byte
+    if data == [0] {
+        inventory.remove(&slot);
+    } else {
+        inventory.insert(slot, data);
+    }
+}
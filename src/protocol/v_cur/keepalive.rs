@@ -1,3 +1,8 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::Ordering,
+};
+
 use craftio_rs::CraftAsyncWriter;
 
 use crate::{
@@ -16,28 +21,138 @@ use crate::{
 };
 
 inventory::submit! {
-    v_cur::RelayPass(Box::new(|_proxy, _connection, client, direction, lazy_packet, destination| {
-        match direction {
-            PacketDirection::ServerBound => {
-                if lazy_packet.kind() == PacketLatestKind::PlayClientKeepAlive { // TODO: may want to do something with the keep alive IDs
-                    *smol::block_on(client.last_keep_alive.lock()) = unix_time_millis();
-                    *destination = v_cur::PacketDestination::None;
+    v_cur::RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, client, direction, lazy_packet, destination| {
+            match direction {
+                PacketDirection::ServerBound => {
+                    if lazy_packet.kind() == PacketLatestKind::PlayClientKeepAlive {
+                        if let Ok(PacketLatest::PlayClientKeepAlive(body)) = lazy_packet.packet() {
+                            // only an answer to a keep alive we are waiting on counts, so a stale
+                            // or repeated answer can't keep a dead connection around
+                            let now = unix_time_millis();
+                            let timeout = proxy.config().keep_alive_timeout as u128 * 1000;
+                            let round_trip = smol::block_on(client.pending_keep_alives.lock())
+                                .answer(body.id, now, timeout);
+                            match round_trip {
+                                Some(round_trip) => {
+                                    *smol::block_on(client.last_keep_alive.lock()) = now;
+                                    client.ping.store(round_trip as u64, Ordering::Relaxed);
+                                }
+                                None => debug!("Ignoring keep alive from \"{}\" with unknown or expired id {}", &client.name, body.id),
+                            }
+                        }
+                        *destination = v_cur::PacketDestination::None;
+                    }
                 }
-            }
-            PacketDirection::ClientBound => {
-                if lazy_packet.kind() == PacketLatestKind::PlayServerKeepAlive {
-                    if let Ok(PacketLatest::PlayServerKeepAlive(body)) = lazy_packet.packet() {
-                        // respond to server
-
-                        let server_conn = client.active_server.load();
-                        if let Err(e) = smol::block_on(async { server_conn.writer.lock().await.write_packet_async(PacketLatest::PlayClientKeepAlive(PlayClientKeepAliveSpec {
-                            id: body.id,
-                        })).await }) {
-                            error!("Failed to send keep alive from \"{}\" to server id {}: {}", &client.name, server_conn.server.id, e);
+                PacketDirection::ClientBound => {
+                    if lazy_packet.kind() == PacketLatestKind::PlayServerKeepAlive {
+                        if let Ok(PacketLatest::PlayServerKeepAlive(body)) = lazy_packet.packet() {
+                            // respond to server ourselves. the client has its own keep alives from
+                            // the proxy, so the two don't depend on each other's timing
+                            if let Err(e) = smol::block_on(async { connection.writer.lock().await.write_packet_async(PacketLatest::PlayClientKeepAlive(PlayClientKeepAliveSpec {
+                                id: body.id,
+                            })).await }) {
+                                error!("Failed to send keep alive from \"{}\" to server id {}: {}", &client.name, connection.server.id, e);
+                            }
                         }
+                        *destination = v_cur::PacketDestination::None;
                     }
                 }
             }
+        }),
+    }
+}
+
+/// Keep alives sent to a client that it hasn't answered yet, by id, with the unix time in
+/// milliseconds each was sent at
+#[derive(Default)]
+pub struct PendingKeepAlives {
+    sent: HashMap<i64, u128>,
+}
+
+impl PendingKeepAlives {
+    /// Picks the id for a keep alive sent now and starts waiting on it. Keep alives older than
+    /// the timeout are given up on
+    pub fn send(&mut self, time: u128, timeout: u128) -> i64 {
+        self.sent
+            .retain(|_, sent| time.saturating_sub(*sent) <= timeout);
+        // ids are the send time, bumped past any id still awaiting an answer so that every
+        // pending keep alive has a distinct id
+        let mut id = time as i64;
+        while self.sent.contains_key(&id) {
+            id += 1;
         }
-    }))
+        self.sent.insert(id, time);
+        id
+    }
+    /// Stops waiting on the keep alive with the given id, returning how long the answer took, or
+    /// `None` if no keep alive with that id is pending or it was sent longer than the timeout ago
+    pub fn answer(&mut self, id: i64, time: u128, timeout: u128) -> Option<u128> {
+        self.sent
+            .remove(&id)
+            .map(|sent| time.saturating_sub(sent))
+            .filter(|round_trip| *round_trip <= timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: u128 = 30_000;
+
+    #[test]
+    fn only_the_matching_answer_clears_a_keep_alive() {
+        let mut pending = PendingKeepAlives::default();
+        let first = pending.send(1000, TIMEOUT);
+        let second = pending.send(1000, TIMEOUT);
+        assert_ne!(first, second);
+        assert_eq!(pending.answer(second, 1050, TIMEOUT), Some(50));
+        // answered already
+        assert_eq!(pending.answer(second, 1050, TIMEOUT), None);
+        assert_eq!(pending.answer(first, 1100, TIMEOUT), Some(100));
+    }
+
+    #[test]
+    fn ignores_unknown_ids() {
+        let mut pending = PendingKeepAlives::default();
+        let id = pending.send(1000, TIMEOUT);
+        assert_eq!(pending.answer(id + 1, 1000, TIMEOUT), None);
+        assert_eq!(pending.answer(id, 1000, TIMEOUT), Some(0));
+    }
+
+    #[test]
+    fn keeps_ids_wider_than_i32() {
+        let mut pending = PendingKeepAlives::default();
+        let time = 1_700_000_000_000u128;
+        let id = pending.send(time, TIMEOUT);
+        assert_eq!(id, time as i64);
+        assert_eq!(pending.answer(id as i32 as i64, time, TIMEOUT), None);
+        assert_eq!(pending.answer(id, time + 20, TIMEOUT), Some(20));
+    }
+
+    #[test]
+    fn forgets_timed_out_keep_alives() {
+        let mut pending = PendingKeepAlives::default();
+        let old = pending.send(1000, TIMEOUT);
+        pending.send(1000 + TIMEOUT + 1, TIMEOUT);
+        assert_eq!(pending.answer(old, 1000, TIMEOUT), None);
+    }
+
+    #[test]
+    fn late_answer_is_expired() {
+        let mut pending = PendingKeepAlives::default();
+        let id = pending.send(1000, TIMEOUT);
+        assert_eq!(pending.answer(id, 1000 + TIMEOUT + 1, TIMEOUT), None);
+        // and isn't waited on anymore
+        assert_eq!(pending.answer(id, 1000, TIMEOUT), None);
+    }
+
+    #[test]
+    fn answer_at_the_timeout_still_counts() {
+        let mut pending = PendingKeepAlives::default();
+        let id = pending.send(1000, TIMEOUT);
+        assert_eq!(pending.answer(id, 1000 + TIMEOUT, TIMEOUT), Some(TIMEOUT));
+    }
 }
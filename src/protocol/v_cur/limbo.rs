@@ -0,0 +1,263 @@
+use craftio_rs::CraftAsyncWriter;
+
+use super::BIOME_MAP;
+use crate::{
+    protocol::{
+        current::{
+            nbt::{NamedTag, Tag},
+            proto::{
+                ChatPosition, GameMode, PlayChunkDataSpec, PlayJoinGameSpec,
+                PlayServerChatMessageSpec, PlayServerKeepAliveSpec,
+                PlayServerPlayerPositionAndLookSpec, PositionAndLookFlags, PreviousGameMode,
+            },
+            types::{Chat, EntityLocation, EntityRotation, NamedNbtTag, VarInt, Vec3},
+            uuid::UUID4,
+            PacketLatest,
+        },
+        AsyncCraftWriter,
+    },
+    systems::zoning::world_to_chunk_position,
+};
+
+/// Name of the world clients are shown while in limbo. It differs from any server's world so
+/// that the client starts over when a server's join game packet follows it
+const LIMBO_WORLD: &str = "splinter:limbo";
+const LIMBO_DIMENSION: &str = "splinter:limbo";
+/// Height of the limbo dimension, the same as the overworld's
+const LIMBO_HEIGHT: i32 = 256;
+
+/// Shows the client an empty world of its own, with the client as a spectator at the given
+/// position, without any server behind it
+///
+/// The world is one empty chunk column under the client. Nothing else is sent, so the client
+/// needs only keep alives until it is sent to a server
+pub async fn send_limbo_world(
+    writer: &mut impl CraftAsyncWriter,
+    position: &Vec3<f64>,
+) -> anyhow::Result<()> {
+    let dimension = limbo_dimension_type();
+    writer
+        .write_packet_async(PacketLatest::PlayJoinGame(PlayJoinGameSpec {
+            entity_id: 0,
+            is_hardcore: false,
+            gamemode: GameMode::Spectator,
+            previous_gamemode: PreviousGameMode::NoPrevious,
+            worlds: vec![LIMBO_WORLD.to_owned()].into(),
+            dimension_codec: limbo_dimension_codec(&dimension),
+            dimension: NamedNbtTag {
+                root: NamedTag {
+                    name: String::new(),
+                    payload: dimension,
+                },
+            },
+            world_name: LIMBO_WORLD.into(),
+            hashed_seed: 0,
+            max_players: 1.into(),
+            view_distance: 2.into(),
+            reduced_debug_info: false,
+            enable_respawn_screen: false,
+            is_debug: false,
+            is_flat: true,
+        }))
+        .await?;
+
+    // every 4x4x4 cell of the column is plains
+    let plains = *BIOME_MAP.get_by_right("plains").unwrap_or(&1);
+    let (chunk_x, chunk_z) = world_to_chunk_position((position.x, position.z));
+    writer
+        .write_packet_async(PacketLatest::PlayChunkData(PlayChunkDataSpec {
+            x: chunk_x,
+            z: chunk_z,
+            primary_bit_mask: vec![].into(),
+            heightmaps: NamedNbtTag {
+                root: NamedTag {
+                    name: String::new(),
+                    payload: Tag::Compound(vec![]),
+                },
+            },
+            biomes: vec![VarInt::from(plains); (LIMBO_HEIGHT / 4 * 16) as usize].into(),
+            data: vec![].into(),
+            block_entities: vec![].into(),
+        }))
+        .await?;
+
+    writer
+        .write_packet_async(PacketLatest::PlayServerPlayerPositionAndLook(
+            PlayServerPlayerPositionAndLookSpec {
+                location: EntityLocation {
+                    position: position.clone(),
+                    rotation: EntityRotation { yaw: 0., pitch: 0. },
+                },
+                flags: PositionAndLookFlags(0),
+                teleport_id: 0.into(),
+                dismount_vehicle: false,
+            },
+        ))
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Sends a system message to a client that has no `SplinterClient` yet
+pub async fn send_system_message(
+    writer: &mut AsyncCraftWriter,
+    message: impl AsRef<str>,
+) -> anyhow::Result<()> {
+    writer
+        .write_packet_async(PacketLatest::PlayServerChatMessage(
+            PlayServerChatMessageSpec {
+                message: Chat::from_text(message.as_ref()),
+                position: ChatPosition::SystemMessage,
+                sender: UUID4::from(0u128),
+            },
+        ))
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Sends a keep alive to a client that has no `SplinterClient` yet. Its answer isn't checked
+pub async fn send_keep_alive(writer: &mut AsyncCraftWriter, id: i64) -> anyhow::Result<()> {
+    writer
+        .write_packet_async(PacketLatest::PlayServerKeepAlive(PlayServerKeepAliveSpec {
+            id,
+        }))
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Dimension codec holding only the limbo dimension type and the plains biome, which is all the
+/// client needs to show the limbo world
+fn limbo_dimension_codec(dimension: &Tag) -> NamedNbtTag {
+    let plains_id = *BIOME_MAP.get_by_right("plains").unwrap_or(&1);
+    let registry = |name: &str, entry_name: &str, id: i32, element: Tag| {
+        named(
+            name,
+            Tag::Compound(vec![
+                named("type", Tag::String(name.into())),
+                named(
+                    "value",
+                    Tag::List(vec![Tag::Compound(vec![
+                        named("name", Tag::String(entry_name.into())),
+                        named("id", Tag::Int(id)),
+                        named("element", element),
+                    ])]),
+                ),
+            ]),
+        )
+    };
+    NamedNbtTag {
+        root: named(
+            "",
+            Tag::Compound(vec![
+                registry(
+                    "minecraft:dimension_type",
+                    LIMBO_DIMENSION,
+                    0,
+                    dimension.clone(),
+                ),
+                registry(
+                    "minecraft:worldgen/biome",
+                    "minecraft:plains",
+                    plains_id,
+                    plains_biome(),
+                ),
+            ]),
+        ),
+    }
+}
+
+/// Dimension type of the limbo world: always daylit, with nothing special about it
+fn limbo_dimension_type() -> Tag {
+    Tag::Compound(vec![
+        named("piglin_safe", Tag::Byte(0)),
+        named("natural", Tag::Byte(0)),
+        named("ambient_light", Tag::Float(0.)),
+        named("fixed_time", Tag::Long(6000)),
+        named(
+            "infiniburn",
+            Tag::String("minecraft:infiniburn_overworld".into()),
+        ),
+        named("respawn_anchor_works", Tag::Byte(0)),
+        named("has_skylight", Tag::Byte(1)),
+        named("bed_works", Tag::Byte(0)),
+        named("effects", Tag::String("minecraft:overworld".into())),
+        named("has_raids", Tag::Byte(0)),
+        named("min_y", Tag::Int(0)),
+        named("height", Tag::Int(LIMBO_HEIGHT)),
+        named("logical_height", Tag::Int(LIMBO_HEIGHT)),
+        named("coordinate_scale", Tag::Double(1.)),
+        named("ultrawarm", Tag::Byte(0)),
+        named("has_ceiling", Tag::Byte(0)),
+    ])
+}
+
+fn plains_biome() -> Tag {
+    Tag::Compound(vec![
+        named("precipitation", Tag::String("none".into())),
+        named("depth", Tag::Float(0.125)),
+        named("temperature", Tag::Float(0.8)),
+        named("scale", Tag::Float(0.05)),
+        named("downfall", Tag::Float(0.4)),
+        named("category", Tag::String("plains".into())),
+        named(
+            "effects",
+            Tag::Compound(vec![
+                named("sky_color", Tag::Int(7907327)),
+                named("water_fog_color", Tag::Int(329011)),
+                named("fog_color", Tag::Int(12638463)),
+                named("water_color", Tag::Int(4159204)),
+            ]),
+        ),
+    ])
+}
+
+fn named(name: &str, payload: Tag) -> NamedTag {
+    NamedTag {
+        name: name.into(),
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use craftio_rs::{
+        CraftIo,
+        CraftReader,
+        CraftSyncReader,
+        CraftWriter,
+    };
+
+    use super::*;
+    use crate::protocol::current::{
+        protocol::{
+            PacketDirection,
+            State,
+        },
+        RawPacketLatest,
+    };
+
+    #[test]
+    fn limbo_world_puts_client_in_its_chunk() {
+        let position = Vec3::from((40., 70., -8.));
+        let mut data = Vec::new();
+        let mut writer = CraftWriter::wrap(&mut data, PacketDirection::ClientBound);
+        writer.set_state(State::Play);
+        smol::block_on(send_limbo_world(&mut writer, &position)).unwrap();
+        drop(writer);
+
+        let mut reader = CraftReader::wrap(&data[..], PacketDirection::ClientBound);
+        reader.set_state(State::Play);
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet::<RawPacketLatest>().unwrap() {
+            packets.push(packet);
+        }
+        match &packets[..] {
+            [PacketLatest::PlayJoinGame(join_game), PacketLatest::PlayChunkData(chunk), PacketLatest::PlayServerPlayerPositionAndLook(teleport)] =>
+            {
+                assert_eq!(join_game.world_name, LIMBO_WORLD);
+                assert_eq!((chunk.x, chunk.z), (2, -1));
+                assert_eq!(teleport.location.position, position);
+            }
+            _ => panic!("Unexpected limbo world packets"),
+        }
+    }
+}
@@ -11,6 +11,7 @@ use craftio_rs::{
     CraftIo,
 };
 
+use super::{answer_login_plugin_request, map_dimension_codec, ServerDimension};
 use crate::{
     protocol::{
         current::{
@@ -21,6 +22,7 @@ use crate::{
                 ClientStatusAction,
                 HandshakeNextState,
                 HandshakeSpec,
+                LoginDisconnectSpec,
                 LoginSetCompressionSpec,
                 LoginStartSpec,
                 LoginSuccessSpec,
@@ -32,10 +34,21 @@ use crate::{
                 PlayTagsSpec,
                 PlayTeleportConfirmSpec,
             },
-            protocol::PacketDirection,
-            types::VarInt,
+            protocol::{
+                HasPacketId,
+                HasPacketKind,
+                PacketDirection,
+                RawPacket,
+            },
+            types::{
+                Chat,
+                VarInt,
+            },
             uuid::UUID4,
+            Deserialize,
+            Deserialized,
             PacketLatest,
+            PacketLatestKind,
             RawPacketLatest,
         },
         plugin,
@@ -55,6 +68,25 @@ use crate::{
     },
 };
 
+/// Deserializes a packet sent by a logging in client
+///
+/// Newer clients send more in login start than just their name (ex. the profile public key and
+/// signature added in 1.19, and the profile uuid added in 1.19.1). Only the name is used, so
+/// anything after it is ignored rather than failing the login.
+fn deserialize_client_login_packet(raw_packet: &RawPacketLatest<'_>) -> anyhow::Result<PacketLatest> {
+    if raw_packet.kind() == PacketLatestKind::LoginStart {
+        let Deserialized { value: name, data: rest } = String::mc_deserialize(raw_packet.data())
+            .map_err(|e| anyhow!("Failed to deserialize login start name: {:?}", e))?;
+        if !rest.is_empty() {
+            debug!("Ignoring {} trailing bytes in login start from \"{}\"", rest.len(), &name);
+        }
+        return Ok(PacketLatest::LoginStart(LoginStartSpec { name }));
+    }
+    raw_packet
+        .deserialize()
+        .map_err(|e| anyhow!("Failed to deserialize login packet: {:?}", e))
+}
+
 pub async fn handle_client_login_packet(
     next_sender: &mut PacketDirection,
     builder: &mut ClientBuilder<'_>,
@@ -62,9 +94,13 @@ pub async fn handle_client_login_packet(
 ) -> anyhow::Result<Option<bool>> {
     let packet = match next_sender {
         PacketDirection::ServerBound => {
-            client_conn_reader
-                .read_packet_async::<RawPacketLatest>()
+            match client_conn_reader
+                .read_raw_packet_async::<RawPacketLatest>()
                 .await?
+            {
+                Some(raw_packet) => Some(deserialize_client_login_packet(&raw_packet)?),
+                None => None,
+            }
         }
         PacketDirection::ClientBound => {
             builder
@@ -78,6 +114,11 @@ pub async fn handle_client_login_packet(
         }
     };
     if let Some(packet) = packet {
+        // the state is the client's. a server logged in to after limbo is behind it until the
+        // server's login succeeds
+        if *next_sender == PacketDirection::ServerBound || builder.limbo_server.is_none() {
+            builder.state.expect(&packet.id().state)?;
+        }
         match packet {
             PacketLatest::LoginStart(body) => {
                 builder.login_start(&body.name).await?;
@@ -89,24 +130,22 @@ pub async fn handle_client_login_packet(
             }
             PacketLatest::LoginSuccess(body) => {
                 builder.server_conn.as_mut().unwrap().uuid = body.uuid;
-                // builder.proxy.mapping.lock().await.uuids.insert(
-                // builder.uuid.unwrap(),
-                // (builder.server_conn.as_ref().unwrap().server.id, body.uuid),
-                // );
-                // debug!(
-                // "player uuid ({}, {}) -> {}",
-                // builder.server_conn.as_ref().unwrap().server.id,
-                // body.uuid,
-                // builder.uuid.unwrap()
-                // );
-                // body.uuid = builder.uuid.unwrap(); // we're not relaying
+                builder.proxy.mapping.lock().await.register_uuid_mapping(
+                    builder.server_conn.as_ref().unwrap().server.id,
+                    body.uuid,
+                    builder.uuid.unwrap(),
+                );
+                // we're not relaying the server's login success; the client gets the proxy uuid
                 builder.login_success(client_conn_reader).await?;
                 *next_sender = PacketDirection::ClientBound;
             }
             PacketLatest::PlayJoinGame(mut body) => {
                 builder.server_conn.as_mut().unwrap().eid = body.entity_id;
+                *builder.server_conn.as_mut().unwrap().dimension.get_mut() =
+                    Some(ServerDimension::from(&body));
                 let map = &mut *builder.proxy.mapping.lock().await;
                 let server_id = builder.server_conn.as_ref().unwrap().server.id;
+                map_dimension_codec(map, server_id, &mut body.dimension_codec);
                 body.entity_id = if let Some(existing_id) =
                     map.eids.get_by_right(&(server_id, body.entity_id))
                 {
@@ -120,6 +159,7 @@ pub async fn handle_client_login_packet(
                     builder.server_conn.as_ref().unwrap().eid,
                     body.entity_id,
                 );
+                builder.join_game = Some(body.clone());
                 builder
                     .client_writer
                     .write_packet_async(PacketLatest::PlayJoinGame(body))
@@ -180,6 +220,21 @@ pub async fn handle_client_login_packet(
             PacketLatest::LoginEncryptionRequest(_body) => {
                 bail!("Server attempted to initiate encryption. Did you turn off online mode?");
             }
+            PacketLatest::LoginPluginRequest(body) => {
+                answer_login_plugin_request(
+                    builder.proxy,
+                    builder.server_conn.as_mut().unwrap(),
+                    body,
+                )
+                .await?;
+                *next_sender = PacketDirection::ClientBound;
+            }
+            // the client's packets from limbo may still be arriving
+            _ if builder.limbo_server.is_some() => debug!(
+                "Ignoring packet from {} after limbo: {:?}",
+                builder.client_addr,
+                packet.kind()
+            ),
             _ => warn!(
                 "Unexpected packet from {}: {:?}",
                 builder.client_addr, packet
@@ -199,7 +254,7 @@ pub async fn send_handshake(
         .writer
         .get_mut()
         .write_packet_async(PacketLatest::Handshake(HandshakeSpec {
-            version: proxy.config.protocol.into(),
+            version: proxy.config().protocol.into(),
             server_address: format!("{}", server_conn.server.address.ip()),
             server_port: server_conn.server.address.port(),
             next_state: HandshakeNextState::Login,
@@ -231,6 +286,17 @@ pub async fn send_set_compression(
         .await
         .map_err(|e| e.into())
 }
+pub async fn send_login_disconnect(
+    writer: &mut AsyncCraftWriter,
+    message: &str,
+) -> anyhow::Result<()> {
+    writer
+        .write_packet_async(PacketLatest::LoginDisconnect(LoginDisconnectSpec {
+            message: Chat::from_text(message),
+        }))
+        .await
+        .map_err(|e| e.into())
+}
 pub async fn send_login_success(
     writer: &mut AsyncCraftWriter,
     name: String,
@@ -271,9 +337,9 @@ pub async fn send_client_settings(
         .await
         .map_err(|e| e.into())
 }
-pub async fn send_tags(writer: &mut AsyncCraftWriter, tags: &Tags) -> anyhow::Result<()> {
+pub async fn send_tags(writer: &mut AsyncCraftWriter, tags: PlayTagsSpec) -> anyhow::Result<()> {
     writer
-        .write_packet_async(PacketLatest::PlayTags(PlayTagsSpec::from(tags)))
+        .write_packet_async(PacketLatest::PlayTags(tags))
         .await
         .map_err(|e| e.into())
 }
@@ -306,13 +372,8 @@ pub async fn send_client_status(
         .map_err(|e| e.into())
 }
 
-pub async fn send_held_item_change(
-    server_conn: &mut SplinterServerConnection,
-    slot: i8,
-) -> anyhow::Result<()> {
-    server_conn
-        .writer
-        .get_mut()
+pub async fn send_held_item_change(writer: &mut AsyncCraftWriter, slot: i8) -> anyhow::Result<()> {
+    writer
         .write_packet_async(PacketLatest::PlayClientHeldItemChange(
             PlayClientHeldItemChangeSpec {
                 slot: slot as i16,
@@ -427,3 +488,76 @@ impl From<ClientSettings> for PlayClientSettingsSpec {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mapping::SplinterMapping;
+
+    const SERVER_UUID: u128 = 0x0123_4567_89ab_4def_8123_4567_89ab_cdef;
+
+    /// Login success body as a 1.17.1 server sends it: the uuid as 16 bytes followed by the name
+    fn login_success_data(uuid: u128, name: &str) -> Vec<u8> {
+        let mut data = uuid.to_be_bytes().to_vec();
+        data.push(name.len() as u8);
+        data.extend_from_slice(name.as_bytes());
+        data
+    }
+
+    fn login_start(data: &[u8]) -> anyhow::Result<PacketLatest> {
+        let id = PacketLatest::LoginStart(LoginStartSpec {
+            name: String::new(),
+        })
+        .id();
+        let raw = RawPacketLatest::create(id, data).unwrap();
+        deserialize_client_login_packet(&raw)
+    }
+
+    #[test]
+    fn reads_login_start_name() {
+        match login_start(b"\x08Splinter").unwrap() {
+            PacketLatest::LoginStart(body) => assert_eq!(body.name, "Splinter"),
+            other => panic!("Expected login start, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ignores_trailing_login_start_fields() {
+        // a 1.19 client follows its name with whether it has a profile key, and the key
+        let mut data = b"\x08Splinter".to_vec();
+        data.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 42, 3, 0xAB, 0xCD, 0xEF]);
+        match login_start(&data).unwrap() {
+            PacketLatest::LoginStart(body) => assert_eq!(body.name, "Splinter"),
+            other => panic!("Expected login start, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_login_start_without_a_name() {
+        assert!(login_start(&[]).is_err());
+    }
+
+    #[test]
+    fn reads_login_success_uuid_as_bytes() {
+        let data = login_success_data(SERVER_UUID, "Splinter");
+        let Deserialized { value, data: rest } = LoginSuccessSpec::mc_deserialize(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(value.uuid, UUID4::from(SERVER_UUID));
+        assert_eq!(value.username, "Splinter");
+    }
+
+    #[test]
+    fn maps_login_success_uuid_to_proxy_uuid() {
+        let data = login_success_data(SERVER_UUID, "Splinter");
+        let body = LoginSuccessSpec::mc_deserialize(&data).unwrap().value;
+        let proxy_uuid = UUID4::from(42u128);
+        let mut map = SplinterMapping::new();
+        map.register_uuid_mapping(1, body.uuid, proxy_uuid);
+        assert_eq!(map.map_uuid(1, UUID4::from(SERVER_UUID)), proxy_uuid);
+        // another server's player with the same uuid isn't this player
+        assert_eq!(
+            map.map_uuid(2, UUID4::from(SERVER_UUID)),
+            UUID4::from(SERVER_UUID)
+        );
+    }
+}
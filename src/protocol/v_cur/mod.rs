@@ -1,53 +1,94 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
 
 use anyhow::Context;
 use craftio_rs::{CraftAsyncReader, CraftAsyncWriter, CraftIo};
 
-use super::{AsyncCraftConnection, AsyncCraftReader, AsyncCraftWriter, PacketDestination};
+use super::{
+    is_fatal_read_error, AsyncCraftConnection, AsyncCraftReader, AsyncCraftWriter,
+    PacketDestination,
+};
 use crate::{
     protocol::{
         current::{
             proto::{
-                Packet756 as PacketLatest, PlayDisconnectSpec, PlayServerKeepAliveSpec,
+                Packet756 as PacketLatest, Packet756Kind as PacketLatestKind, PlayDisconnectSpec,
+                PlayServerHeldItemChangeSpec, PlayServerKeepAliveSpec,
                 RawPacket756 as RawPacketLatest, StatusPongSpec, StatusRequestSpec,
                 StatusResponseSpec,
             },
-            protocol::{PacketDirection, State},
+            protocol::{HasPacketId, PacketDirection, RawPacket},
             types::Chat,
         },
         events::LazyDeserializedPacket,
     },
     proxy::{
         client::SplinterClient,
-        server::{SplinterServer, SplinterServerConnection},
+        config::PacketErrorAction,
+        server::SplinterServerConnection,
         ClientKickReason, SplinterProxy,
     },
 };
 
+mod biome;
+mod bossbar;
+mod channels;
 mod chat;
 mod chunk;
+mod commands;
+mod dimension;
+mod effects;
 mod eid;
+mod items;
 mod keepalive;
+mod limbo;
 mod login;
+mod playerinfo;
+mod ratelimit;
+mod replay;
+mod scoreboard;
+mod selector;
+mod selftest;
 mod sync;
+mod tablist;
 mod tags;
+mod teleport;
+mod trace;
+mod worldborder;
+pub use biome::*;
+pub use bossbar::*;
+pub use channels::*;
 pub use chat::*;
+pub use dimension::*;
+pub use effects::*;
 pub use eid::*;
+pub use keepalive::PendingKeepAlives;
+pub use limbo::*;
 pub use login::*;
+pub use playerinfo::*;
+pub use selftest::self_test;
 pub use sync::*;
 pub use tags::*;
+pub use worldborder::*;
 
 pub async fn handle_client_status(
     mut conn: AsyncCraftConnection,
     addr: SocketAddr,
     proxy: Arc<SplinterProxy>,
 ) -> anyhow::Result<()> {
-    conn.set_state(State::Status);
     conn.write_packet_async(PacketLatest::StatusResponse(StatusResponseSpec {
-        response: proxy.config.server_status(&*proxy),
+        response: proxy.config().server_status(&*proxy),
     }))
     .await?;
+    let mut packets_received = 0;
     loop {
+        packets_received += 1;
+        if packets_received > proxy.config().max_pre_play_packets {
+            bail!("{} sent too many packets during status", addr);
+        }
         match conn.read_packet_async::<RawPacketLatest>().await? {
             Some(PacketLatest::StatusPing(body)) => {
                 conn.write_packet_async(PacketLatest::StatusPong(StatusPongSpec {
@@ -68,7 +109,7 @@ pub async fn handle_client_status(
     Ok(())
 }
 
-type RelayPassFn = Box<
+pub type RelayPassFn = Box<
     dyn Send
         + Sync
         + Fn(
@@ -80,38 +121,172 @@ type RelayPassFn = Box<
             &mut PacketDestination,
         ),
 >;
-pub struct RelayPass(pub RelayPassFn);
+/// Changes or reroutes packets as they are relayed between a client and its servers
+///
+/// Passes run in order of `order`, lowest first, each seeing the packet and destination the
+/// passes before it left. Passes with the same order run in no particular order. A pass that drops
+/// a packet doesn't stop the passes after it, which still see the packet, as several passes keep
+/// track of state from packets that aren't sent on
+///
+/// Passes are registered with `inventory::submit!` from this crate's modules. There is no way to
+/// add one at runtime: the proxy is built as a binary only, with no library target another crate
+/// could link against to register its own passes
+pub struct RelayPass {
+    pub order: i32,
+    pub pass: RelayPassFn,
+}
 
 inventory::collect!(RelayPass);
 
+lazy_static! {
+    /// Every `RelayPass`, in the order they run
+    static ref RELAY_PASSES: Vec<&'static RelayPass> =
+        in_order(inventory::iter::<RelayPass>.into_iter().collect());
+}
+
+/// Sorts relay passes by `order`, keeping passes with the same order as they were
+fn in_order(mut passes: Vec<&RelayPass>) -> Vec<&RelayPass> {
+    passes.sort_by_key(|pass| pass.order);
+    passes
+}
+
+/// Runs every `RelayPass` on a packet read from or sent to the given connection
+fn run_relay_passes(
+    proxy: &Arc<SplinterProxy>,
+    connection: &Arc<SplinterServerConnection>,
+    client: &Arc<SplinterClient>,
+    sender: &PacketDirection,
+    lazy_packet: &mut LazyDeserializedPacket,
+    destination: &mut PacketDestination,
+) {
+    for pass in RELAY_PASSES.iter() {
+        (pass.pass)(proxy, connection, client, sender, lazy_packet, destination);
+    }
+}
+
+type PacketObserverFn = Box<
+    dyn Send
+        + Sync
+        + Fn(
+            &Arc<SplinterProxy>,
+            &Arc<SplinterServerConnection>,
+            &Arc<SplinterClient>,
+            &PacketDirection,
+            &LazyDeserializedPacket,
+            &PacketDestination,
+        ),
+>;
+/// Sees every relayed packet without being able to change it or where it goes, ex. for metrics
+/// or logging
+///
+/// Observers run after every `RelayPass`, right before the packet is sent, so they see the final
+/// packet and destination. Packets a pass dropped are observed with `PacketDestination::None`.
+/// Observers can't deserialize packets themselves; use `LazyDeserializedPacket::deserialized` to
+/// see packets a pass already deserialized
+///
+/// Packets from servers are observed with the connection they were read from, including the
+/// connections to servers other than the client's active one. Packets from the client are
+/// observed with the client's active connection
+pub struct PacketObserver(pub PacketObserverFn);
+
+inventory::collect!(PacketObserver);
+
+pub fn observe_packet(
+    proxy: &Arc<SplinterProxy>,
+    connection: &Arc<SplinterServerConnection>,
+    client: &Arc<SplinterClient>,
+    sender: &PacketDirection,
+    lazy_packet: &LazyDeserializedPacket,
+    destination: &PacketDestination,
+) {
+    for observer in inventory::iter::<PacketObserver> {
+        (observer.0)(proxy, connection, client, sender, lazy_packet, destination);
+    }
+}
+
 pub async fn handle_server_packet(
     proxy: &Arc<SplinterProxy>,
     client: &Arc<SplinterClient>,
     reader: &mut AsyncCraftReader,
-    server: &SplinterServer,
+    connection: &Arc<SplinterServerConnection>,
     sender: &PacketDirection,
 ) -> anyhow::Result<Option<()>> {
     // debug!("waiting for packet");
-    let packet_opt = reader
-        .read_raw_packet_async::<RawPacketLatest>()
-        .await
-        .with_context(|| format!("Failed to read packet {}: ", server.id))?;
+    let packet_opt = match reader.read_raw_packet_async::<RawPacketLatest>().await {
+        Ok(packet_opt) => packet_opt,
+        Err(e) => {
+            let e = anyhow::Error::new(e)
+                .context(format!("Failed to read packet {}: ", connection.server.id));
+            // a stream that lost track of where packets start can't be read on from
+            if is_fatal_read_error(&e) {
+                return Err(e);
+            }
+            match proxy.config().backend_packet_errors.unreadable {
+                // `validate` refuses RawForward, as there are no bytes to forward
+                PacketErrorAction::Ignore | PacketErrorAction::RawForward => {
+                    warn!("Dropping unreadable packet: {:?}", e);
+                    return Ok(Some(()));
+                }
+                PacketErrorAction::DisconnectBackend => {
+                    connection.alive.store(false, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+    };
+    connection.mark_activity().await;
     match packet_opt {
+        Some(_) if !connection.alive.load(Ordering::Relaxed) => {
+            // connection is being torn down; anything it sends is no longer meant for the client
+            Ok(Some(()))
+        }
         Some(raw_packet) => {
+            proxy.metrics.record_packet(sender, raw_packet.data().len());
             let mut lazy_packet = LazyDeserializedPacket::from_raw_packet(raw_packet);
             let mut destination = PacketDestination::Client;
-            for pass in inventory::iter::<RelayPass> {
-                (pass.0)(
-                    proxy,
-                    &*client.active_server.load(),
-                    client,
-                    sender,
-                    &mut lazy_packet,
-                    &mut destination,
-                );
-            }
+            run_relay_passes(
+                proxy,
+                connection,
+                client,
+                sender,
+                &mut lazy_packet,
+                &mut destination,
+            );
             let kind = lazy_packet.kind();
             // debug!("got packet of type {:?}", kind);
+            if let Some(e) = lazy_packet.error() {
+                match proxy.config().backend_packet_errors.malformed {
+                    PacketErrorAction::Ignore => {
+                        warn!(
+                            "Dropping malformed packet of kind {:?} from server {}: {:?}",
+                            kind, connection.server.id, e
+                        );
+                        return Ok(Some(()));
+                    }
+                    PacketErrorAction::RawForward => {
+                        lazy_packet.discard_error();
+                    }
+                    PacketErrorAction::DisconnectBackend => {
+                        connection.alive.store(false, Ordering::Relaxed);
+                        bail!(
+                            "Malformed packet of kind {:?} from server {}: {:?}",
+                            kind,
+                            connection.server.id,
+                            e
+                        );
+                    }
+                }
+            }
+            observe_packet(
+                proxy,
+                connection,
+                client,
+                sender,
+                &lazy_packet,
+                &destination,
+            );
+            let relays_disconnect = matches!(kind, PacketLatestKind::PlayDisconnect)
+                && matches!(destination, PacketDestination::Client);
             send_packet(client, &destination, lazy_packet)
                 .await
                 .with_context(|| {
@@ -120,6 +295,9 @@ pub async fn handle_server_packet(
                         kind, &client.name, destination
                     )
                 })?;
+            if relays_disconnect {
+                connection.disconnect_relayed.store(true, Ordering::Relaxed);
+            }
             Ok(Some(()))
         }
         None => Ok(None),
@@ -138,18 +316,32 @@ pub async fn handle_client_packet(
         .with_context(|| format!("Failed to read packet from {}", client.name))?;
     match packet_opt {
         Some(raw_packet) => {
+            client.state.lock().await.expect(&raw_packet.id().state)?;
+            proxy.metrics.record_packet(sender, raw_packet.data().len());
             let mut lazy_packet = LazyDeserializedPacket::from_raw_packet(raw_packet);
             let mut destination = PacketDestination::AllServers;
-            for pass in inventory::iter::<RelayPass> {
-                (pass.0)(
-                    proxy,
-                    &*client.active_server.load(),
-                    client,
-                    sender,
-                    &mut lazy_packet,
-                    &mut destination,
-                );
+            run_relay_passes(
+                proxy,
+                &client.active_server.load(),
+                client,
+                sender,
+                &mut lazy_packet,
+                &mut destination,
+            );
+            if client.in_limbo.load(Ordering::Relaxed)
+                && !matches!(destination, PacketDestination::Client)
+            {
+                // there is no server to send to
+                destination = PacketDestination::None;
             }
+            observe_packet(
+                proxy,
+                &client.active_server.load(),
+                client,
+                sender,
+                &lazy_packet,
+                &destination,
+            );
             send_packet(client, &destination, lazy_packet)
                 .await
                 .with_context(|| {
@@ -168,29 +360,28 @@ pub async fn send_packet<'a>(
 ) -> anyhow::Result<()> {
     match destination {
         PacketDestination::Client => {
-            write_packet(&mut *client.writer.lock().await, lazy_packet)
-                .await
-                .with_context(|| {
-                    format!("Failed to write packet to client \"{}\"", &client.name,)
-                })?;
+            client.write_packet(lazy_packet).await.with_context(|| {
+                format!("Failed to write packet to client \"{}\"", &client.name,)
+            })?;
         }
         PacketDestination::Server(server_id) => {
             let active_server = client.active_server.load();
             let dummy_servers = client.dummy_servers.load();
-            let writer = &mut *(if active_server.server.id == *server_id {
-                active_server.writer.lock().await
+            let server_conn = if active_server.server.id == *server_id {
+                &*active_server
             } else {
                 if let Some((_id, server_conn)) =
                     dummy_servers.iter().find(|(id, _)| *id == *server_id)
                 {
-                    server_conn.writer.lock().await
+                    server_conn
                 } else {
                     bail!("No connected server from mapped server id");
                 }
-            });
-            write_packet(writer, lazy_packet)
+            };
+            write_packet(&mut *server_conn.writer.lock().await, lazy_packet)
                 .await
                 .with_context(|| format!("Failed to write packet to server \"{}\"", server_id))?;
+            server_conn.mark_activity().await;
         }
         PacketDestination::AllServers => {
             for (server_id, server_conn) in client.dummy_servers.load().iter() {
@@ -200,6 +391,7 @@ pub async fn send_packet<'a>(
                     .with_context(|| {
                         format!("Failed to write packet to server \"{}\"", server_id)
                     })?;
+                server_conn.mark_activity().await;
             }
             let active_server = client.active_server.load();
             let writer = &mut *active_server.writer.lock().await;
@@ -210,6 +402,7 @@ pub async fn send_packet<'a>(
                     active_server.server.id
                 )
             })?;
+            active_server.mark_activity().await;
         }
         PacketDestination::None => {}
     };
@@ -235,26 +428,88 @@ async fn write_packet(
 impl SplinterClient {
     pub async fn write_packet(&self, packet: LazyDeserializedPacket<'_>) -> anyhow::Result<()> {
         let mut writer = self.writer.lock().await;
+        let start = Instant::now();
         if packet.is_deserialized() {
             writer.write_packet_async(packet.into_packet()?)
         } else {
             writer.write_raw_packet_async(packet.into_raw_packet().unwrap())
         }
         .await?;
+        self.proxy.metrics.record_client_write(start.elapsed());
         Ok(())
     }
     pub async fn send_kick(&self, reason: ClientKickReason) -> anyhow::Result<()> {
+        let messages = &self.proxy.config().kick_messages;
+        let mut text = reason.text(messages, self.server_id());
+        if reason.is_recoverable() && !messages.reconnect_hint.is_empty() {
+            text.push('\n');
+            text.push_str(&messages.reconnect_hint);
+        }
         self.write_packet(LazyDeserializedPacket::from_packet(
             PacketLatest::PlayDisconnect(PlayDisconnectSpec {
-                reason: Chat::from_text(&reason.text()),
+                reason: Chat::from_text(&text),
             }),
         ))
         .await
     }
+    /// Selects a hotbar slot for both the client and its active server
+    pub async fn set_held_slot(&self, slot: i8) -> anyhow::Result<()> {
+        self.held_slot.store(slot, Ordering::Relaxed);
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayServerHeldItemChange(PlayServerHeldItemChangeSpec { slot }),
+        ))
+        .await?;
+        send_held_item_change(&mut *self.active_server.load().writer.lock().await, slot).await
+    }
     pub async fn send_keep_alive(&self, time: u128) -> anyhow::Result<()> {
+        // anything older has timed out the client already
+        let timeout = self.proxy.config().keep_alive_timeout as u128 * 1000;
+        let id = self.pending_keep_alives.lock().await.send(time, timeout);
         self.write_packet(LazyDeserializedPacket::from_packet(
-            PacketLatest::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: time as i64 }),
+            PacketLatest::PlayServerKeepAlive(PlayServerKeepAliveSpec { id }),
         ))
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(order: i32) -> RelayPass {
+        RelayPass {
+            order,
+            pass: Box::new(|_proxy, _connection, _client, _sender, _lazy_packet, _destination| {}),
+        }
+    }
+
+    #[test]
+    fn passes_run_lowest_order_first() {
+        let passes = [pass(10), pass(-5), pass(0)];
+        let orders = in_order(passes.iter().collect())
+            .into_iter()
+            .map(|pass| pass.order)
+            .collect::<Vec<i32>>();
+        assert_eq!(orders, vec![-5, 0, 10]);
+    }
+
+    #[test]
+    fn passes_with_the_same_order_keep_their_places() {
+        let passes = [pass(1), pass(0), pass(1)];
+        let ordered = in_order(passes.iter().collect());
+        assert!(std::ptr::eq(ordered[0], &passes[1]));
+        assert!(std::ptr::eq(ordered[1], &passes[0]));
+        assert!(std::ptr::eq(ordered[2], &passes[2]));
+    }
+
+    #[test]
+    fn registered_passes_are_in_order() {
+        assert!(RELAY_PASSES
+            .windows(2)
+            .all(|pair| pair[0].order <= pair[1].order));
+        assert_eq!(
+            RELAY_PASSES.len(),
+            inventory::iter::<RelayPass>.into_iter().count()
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::{
+        current::{
+            proto::{PlayPlayerInfoSpec, PlayerInfoActionList},
+            protocol::PacketDirection,
+            uuid::UUID4,
+            PacketLatest,
+            PacketLatestKind,
+        },
+        events::LazyDeserializedPacket,
+    },
+    proxy::{client::SplinterClient, mapping::SplinterMapping},
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, sender, lazy_packet, destination| {
+            if *sender == PacketDirection::ClientBound
+                && lazy_packet.kind() == PacketLatestKind::PlayPlayerInfo
+            {
+                match lazy_packet.packet() {
+                    Ok(PacketLatest::PlayPlayerInfo(body)) => {
+                        if !smol::block_on(client.relay_player_info(connection.server.id, &mut body.actions)) {
+                            *destination = PacketDestination::None;
+                        }
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => error!("Failed to deserialize player info: {}", e),
+                }
+            }
+        }),
+    }
+}
+
+/// Replaces server-side player uuids with proxy-side uuids for every player info action, so that
+/// each action (including latency and gamemode updates) applies to the entry the client knows.
+/// Uuids without a mapping (ex. server-side NPCs) are left alone
+pub fn map_player_info_uuids(
+    map: &SplinterMapping,
+    server_id: u64,
+    actions: &mut PlayerInfoActionList,
+) {
+    let map_uuid = |uuid: &mut UUID4| *uuid = map.map_uuid(server_id, *uuid);
+    match actions {
+        PlayerInfoActionList::Add(list) => list.iter_mut().for_each(|a| map_uuid(&mut a.uuid)),
+        PlayerInfoActionList::UpdateGameMode(list) => {
+            list.iter_mut().for_each(|a| map_uuid(&mut a.uuid))
+        }
+        PlayerInfoActionList::UpdateLatency(list) => {
+            list.iter_mut().for_each(|a| map_uuid(&mut a.uuid))
+        }
+        PlayerInfoActionList::UpdateDisplayName(list) => {
+            list.iter_mut().for_each(|a| map_uuid(&mut a.uuid))
+        }
+        PlayerInfoActionList::Remove(list) => list.iter_mut().for_each(map_uuid),
+    }
+}
+
+impl SplinterClient {
+    /// Takes in player info from one of the client's server connections, active or dummy, and
+    /// leaves in the actions only what should be relayed to the client. Returns whether anything
+    /// is left to relay
+    ///
+    /// Entries being added or removed can change which server's entry the client should be shown,
+    /// so those are sent by `sync_player_info` instead of being relayed. Updates are relayed only
+    /// for entries the client was shown from the same server
+    pub async fn relay_player_info(
+        &self,
+        server_id: u64,
+        actions: &mut PlayerInfoActionList,
+    ) -> bool {
+        {
+            let map = &mut *self.proxy.mapping.lock().await;
+            map_player_info_uuids(map, server_id, actions);
+            map.record_player_info(server_id, actions);
+        }
+        let refreshed = match actions {
+            PlayerInfoActionList::Add(list) => list
+                .iter()
+                .map(|action| (server_id, action.uuid))
+                .collect::<Vec<_>>(),
+            PlayerInfoActionList::Remove(_) => Vec::new(),
+            _ => {
+                let sources = self.player_info_sources.lock().await;
+                let shown = |uuid: &UUID4| sources.get(uuid) == Some(&server_id);
+                return match actions {
+                    PlayerInfoActionList::UpdateGameMode(list) => {
+                        list.retain(|a| shown(&a.uuid));
+                        !list.is_empty()
+                    }
+                    PlayerInfoActionList::UpdateLatency(list) => {
+                        list.retain(|a| shown(&a.uuid));
+                        !list.is_empty()
+                    }
+                    PlayerInfoActionList::UpdateDisplayName(list) => {
+                        list.retain(|a| shown(&a.uuid));
+                        !list.is_empty()
+                    }
+                    _ => unreachable!(),
+                };
+            }
+        };
+        if let Err(e) = self.sync_player_info(&refreshed).await {
+            error!("Failed to send player info to \"{}\": {:?}", &self.name, e);
+        }
+        false
+    }
+    /// Brings the client's tab list in line with the player info entries of the servers it is
+    /// connected to. Entries of the same player from several servers are merged into one,
+    /// preferring the active server's
+    ///
+    /// Entries the client has from the same server are only sent again if they are listed in
+    /// `refreshed` as (server id, proxy-side uuid)
+    pub async fn sync_player_info(&self, refreshed: &[(u64, UUID4)]) -> anyhow::Result<()> {
+        // held while writing so that concurrent syncs reach the client in order
+        let sources = &mut *self.player_info_sources.lock().await;
+        let (removed, added) = {
+            let map = &*self.proxy.mapping.lock().await;
+            let mut merged = HashMap::<UUID4, u64>::new();
+            for server_id in self.connected_servers() {
+                if let Some(entries) = map.player_info.get(&server_id) {
+                    for uuid in entries.keys() {
+                        merged.entry(*uuid).or_insert(server_id);
+                    }
+                }
+            }
+            let removed = sources
+                .keys()
+                .filter(|uuid| !merged.contains_key(uuid))
+                .copied()
+                .collect::<Vec<UUID4>>();
+            let added = merged
+                .iter()
+                .filter(|(uuid, server_id)| {
+                    sources.get(uuid) != Some(server_id)
+                        || refreshed.contains(&(**server_id, **uuid))
+                })
+                .filter_map(|(uuid, server_id)| map.player_info.get(server_id)?.get(uuid).cloned())
+                .collect::<Vec<_>>();
+            *sources = merged;
+            (removed, added)
+        };
+        if !removed.is_empty() {
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayPlayerInfo(PlayPlayerInfoSpec {
+                    actions: PlayerInfoActionList::Remove(removed.into()),
+                }),
+            ))
+            .await?;
+        }
+        if !added.is_empty() {
+            // adding an entry the client already has replaces it
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayPlayerInfo(PlayPlayerInfoSpec {
+                    actions: PlayerInfoActionList::Add(added.into()),
+                }),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::current::proto::{GameMode, PlayerAddActionSpec, PlayerUpdateLatencySpec};
+
+    fn add_action(uuid: UUID4, name: &str, ping_ms: i32) -> PlayerAddActionSpec {
+        PlayerAddActionSpec {
+            uuid,
+            name: name.into(),
+            properties: vec![].into(),
+            game_mode: GameMode::Survival,
+            ping_ms: ping_ms.into(),
+            display_name: None,
+        }
+    }
+
+    /// Maps and records player info from a server the way `relay_player_info` does
+    fn receive(map: &mut SplinterMapping, server_id: u64, mut actions: PlayerInfoActionList) {
+        map_player_info_uuids(map, server_id, &mut actions);
+        map.record_player_info(server_id, &actions);
+    }
+
+    fn ping(map: &SplinterMapping, server_id: u64, uuid: UUID4) -> i32 {
+        *map.player_info[&server_id][&uuid].ping_ms
+    }
+
+    #[test]
+    fn latency_update_only_changes_its_servers_entry() {
+        let (alice, bob) = (UUID4::from(1u128), UUID4::from(2u128));
+        // each server knows alice by its own uuid
+        let (alice_on_1, alice_on_2) = (UUID4::from(11u128), UUID4::from(12u128));
+        let mut map = SplinterMapping::new();
+        map.register_uuid_mapping(1, alice_on_1, alice);
+        map.register_uuid_mapping(2, alice_on_2, alice);
+        receive(
+            &mut map,
+            1,
+            PlayerInfoActionList::Add(
+                vec![
+                    add_action(alice_on_1, "alice", 10),
+                    add_action(bob, "bob", 20),
+                ]
+                .into(),
+            ),
+        );
+        receive(
+            &mut map,
+            2,
+            PlayerInfoActionList::Add(vec![add_action(alice_on_2, "alice", 30)].into()),
+        );
+
+        receive(
+            &mut map,
+            1,
+            PlayerInfoActionList::UpdateLatency(
+                vec![PlayerUpdateLatencySpec {
+                    uuid: alice_on_1,
+                    ping_ms: 99.into(),
+                }]
+                .into(),
+            ),
+        );
+
+        assert_eq!(ping(&map, 1, alice), 99);
+        assert_eq!(ping(&map, 1, bob), 20);
+        assert_eq!(ping(&map, 2, alice), 30);
+    }
+}
@@ -0,0 +1,30 @@
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::current::protocol::PacketDirection,
+    proxy::ClientKickReason,
+    systems::keepalive::unix_time_millis,
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, _connection, client, sender, lazy_packet, destination| {
+            if *sender != PacketDirection::ServerBound {
+                return;
+            }
+            let config = &proxy.config().rate_limits;
+            let limiter = &mut *smol::block_on(client.rate_limiter.lock());
+            if limiter.allow(config, lazy_packet.kind()) {
+                return;
+            }
+            *destination = PacketDestination::None;
+            let drops = limiter.record_drop((unix_time_millis() / 1000) as u64);
+            if config.kick_after == Some(drops) {
+                warn!("Kicking \"{}\" for sending {:?} packets too fast", &client.name, lazy_packet.kind());
+                if let Err(e) = smol::block_on(proxy.kick_client(&client.name, ClientKickReason::RateLimited)) {
+                    error!("Failed to kick \"{}\": {:?}", &client.name, e);
+                }
+            }
+        }),
+    }
+}
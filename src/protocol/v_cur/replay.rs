@@ -0,0 +1,15 @@
+use super::RelayPass;
+use crate::protocol::current::protocol::PacketDirection;
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, _client, sender, lazy_packet, _destination| {
+            if *sender == PacketDirection::ClientBound && proxy.is_replayed(lazy_packet.kind()) {
+                if let Ok(packet) = lazy_packet.packet() {
+                    smol::block_on(connection.record_state(packet));
+                }
+            }
+        }),
+    }
+}
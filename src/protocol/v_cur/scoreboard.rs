@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+
+use super::{
+    PacketDestination,
+    RelayPass,
+};
+use crate::protocol::current::{
+    proto::{
+        ScoreboardObjectiveAction,
+        UpdateScoreSpec,
+    },
+    protocol::PacketDirection,
+    PacketLatest,
+    PacketLatestKind,
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, sender, lazy_packet, destination| {
+            if *sender != PacketDirection::ClientBound
+                || !matches!(
+                    lazy_packet.kind(),
+                    PacketLatestKind::PlayScoreboardObjective
+                        | PacketLatestKind::PlayUpdateScore
+                        | PacketLatestKind::PlayDisplayScoreboard
+                )
+            {
+                return;
+            }
+            let packet = match lazy_packet.packet() {
+                Ok(packet) => packet,
+                Err(e) => return error!("Failed to deserialize scoreboard packet: {:?}", e),
+            };
+            // names are kept with the connection, so they go away with it
+            let objectives = &mut *smol::block_on(connection.objectives.lock());
+            let count = &client.objective_count;
+            match packet {
+                PacketLatest::PlayScoreboardObjective(body) => {
+                    if let ScoreboardObjectiveAction::Remove = body.action {
+                        match objectives.remove(&body.objective_name) {
+                            Some(proxy_name) => body.objective_name = proxy_name,
+                            // the client was never sent the objective under any name
+                            None => *destination = PacketDestination::None,
+                        }
+                    } else {
+                        body.objective_name = map_objective(objectives, count, &body.objective_name);
+                    }
+                }
+                PacketLatest::PlayUpdateScore(body) => {
+                    let objective_name = match &mut body.update {
+                        UpdateScoreSpec::Upsert(upsert) => &mut upsert.objective_name,
+                        UpdateScoreSpec::Remove(remove) => &mut remove.objective_name,
+                    };
+                    // an empty name removes the score from every objective
+                    if !objective_name.is_empty() {
+                        *objective_name = map_objective(objectives, count, objective_name);
+                    }
+                }
+                PacketLatest::PlayDisplayScoreboard(body) => {
+                    // an empty name clears the display slot
+                    if !body.score_name.is_empty() {
+                        body.score_name = map_objective(objectives, count, &body.score_name);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }),
+    }
+}
+
+/// Gets the proxy-side name of one of a server's scoreboard objectives, given the names of the
+/// server's objectives, naming it if it has no name yet
+///
+/// Names are numbered by the client, so objectives of the same name on different servers don't
+/// collide on the client. Objective names are limited to 16 characters, so the names are
+/// generated rather than prefixed with the server id
+fn map_objective(
+    objectives: &mut HashMap<String, String>,
+    count: &AtomicU64,
+    server_name: &str,
+) -> String {
+    objectives
+        .entry(server_name.to_owned())
+        .or_insert_with(|| format!("splinter.{}", count.fetch_add(1, Ordering::Relaxed)))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_objective_on_two_servers_gets_two_names() {
+        let count = AtomicU64::new(0);
+        let (mut first, mut second) = (HashMap::new(), HashMap::new());
+        let first_name = map_objective(&mut first, &count, "kills");
+        let second_name = map_objective(&mut second, &count, "kills");
+        assert_ne!(first_name, second_name);
+        assert_eq!(map_objective(&mut first, &count, "kills"), first_name);
+    }
+
+    #[test]
+    fn removed_objective_is_named_anew() {
+        let count = AtomicU64::new(0);
+        let mut objectives = HashMap::new();
+        let name = map_objective(&mut objectives, &count, "kills");
+        objectives.remove("kills");
+        assert!(objectives.is_empty());
+        // the client may still be removing the old name, so it isn't reused
+        assert_ne!(map_objective(&mut objectives, &count, "kills"), name);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{convert::TryFrom, sync::Arc};
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::{
+        current::{
+            nbt::{NamedTag, Tag},
+            proto::{
+                PlayOpenWindowSpec, PlayServerCloseWindowSpec, PlaySetSlotSpec,
+                PlayWindowItemsSpec, WindowType,
+            },
+            protocol::PacketDirection,
+            types::{Chat, ItemStack, Slot},
+            PacketLatest, PacketLatestKind,
+        },
+        events::LazyDeserializedPacket,
+        v_cur::ITEM_MAP,
+    },
+    proxy::{client::SplinterClient, config::ServerSelectorEntry},
+};
+
+/// Window id used for the server selector. Servers cycle their window ids through 1 to 100, so
+/// this never collides with a server's window
+pub const SELECTOR_WINDOW_ID: u8 = 127;
+/// Window id the client uses for the item held by its cursor
+const CURSOR_WINDOW_ID: i8 = -1;
+
+/// Item used for entries whose item isn't known
+const FALLBACK_ITEM: &str = "grass_block";
+/// Item used for the entry that unpins the client's server
+const AUTOMATIC_ITEM: &str = "compass";
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, _connection, client, sender, lazy_packet, destination| {
+            if *sender != PacketDirection::ServerBound
+                || !matches!(
+                    lazy_packet.kind(),
+                    PacketLatestKind::PlayClickWindow | PacketLatestKind::PlayClientCloseWindow
+                )
+            {
+                return;
+            }
+            let clicked_slot = match lazy_packet.packet() {
+                Ok(PacketLatest::PlayClickWindow(body)) if body.window_id == SELECTOR_WINDOW_ID => {
+                    Some(body.slot)
+                }
+                Ok(PacketLatest::PlayClientCloseWindow(body))
+                    if body.window_id == SELECTOR_WINDOW_ID =>
+                {
+                    None
+                }
+                Ok(_) => return,
+                Err(e) => return error!("Failed to deserialize window packet: {:?}", e),
+            };
+            // the servers never opened this window, so they never see anything done with it
+            *destination = PacketDestination::None;
+            if let Some(slot) = clicked_slot {
+                let client = Arc::clone(client);
+                smol::spawn(async move {
+                    if let Err(e) = client.select_server_slot(slot).await {
+                        error!("Failed to handle server selection for \"{}\": {:?}", &client.name, e);
+                    }
+                })
+                .detach();
+            }
+        }),
+    }
+}
+
+impl SplinterClient {
+    /// Gets the entries shown in the server selector
+    pub async fn server_selector_entries(&self) -> Vec<ServerSelectorEntry> {
+        let entries = &self.proxy.config().server_selector.entries;
+        if !entries.is_empty() {
+            return entries.clone();
+        }
+        let mut ids = self
+            .proxy
+            .servers
+            .read()
+            .await
+            .keys()
+            .copied()
+            .collect::<Vec<u64>>();
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|server_id| ServerSelectorEntry {
+                server_id,
+                name: format!("Server {}", server_id),
+                item: FALLBACK_ITEM.into(),
+            })
+            .collect()
+    }
+    /// Opens the server selector menu for this client
+    pub async fn open_server_selector(&self) -> anyhow::Result<()> {
+        let entries = self.server_selector_entries().await;
+        let rows = selector_rows(entries.len());
+        let slot_count = rows * 9;
+        let mut slots = vec![Slot { item: None }; slot_count];
+        for (slot, entry) in slots.iter_mut().zip(entries.iter()) {
+            *slot = selector_item(&entry.item, &entry.name);
+        }
+        slots[slot_count - 1] = selector_item(AUTOMATIC_ITEM, "Automatic");
+
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayOpenWindow(PlayOpenWindowSpec {
+                id: (SELECTOR_WINDOW_ID as i32).into(),
+                kind: chest_window_type(rows),
+                title: Chat::from_text(&self.proxy.config().server_selector.title),
+            }),
+        ))
+        .await?;
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayWindowItems(PlayWindowItemsSpec {
+                window_id: SELECTOR_WINDOW_ID,
+                state_id: 0.into(),
+                slots: slots.into(),
+                carried_item: Slot { item: None },
+            }),
+        ))
+        .await
+    }
+    /// Handles a click on a slot of the server selector
+    pub async fn select_server_slot(self: &Arc<SplinterClient>, slot: i16) -> anyhow::Result<()> {
+        let entries = self.server_selector_entries().await;
+        let target = if slot as usize == selector_rows(entries.len()) * 9 - 1 {
+            Some(None)
+        } else {
+            usize::try_from(slot)
+                .ok()
+                .and_then(|slot| entries.get(slot))
+                .map(|entry| Some(entry.server_id))
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return Ok(()), // clicked outside of an entry
+        };
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayServerCloseWindow(PlayServerCloseWindowSpec {
+                window_id: SELECTOR_WINDOW_ID,
+            }),
+        ))
+        .await?;
+        // the client thinks it picked up whatever it clicked on, so clear its cursor
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlaySetSlot(PlaySetSlotSpec {
+                window_id: CURSOR_WINDOW_ID,
+                state_id: 0.into(),
+                slot: -1,
+                slot_data: Slot { item: None },
+            }),
+        ))
+        .await?;
+
+        self.pinned_server.store(Arc::new(target));
+        if let Some(server_id) = target {
+            info!("\"{}\" selected server {}", &self.name, server_id);
+            self.switch_server(server_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Rows of the selector window: one per 9 entries, plus the automatic entry
+fn selector_rows(entry_count: usize) -> usize {
+    ((entry_count + 1 + 8) / 9).clamp(1, 6)
+}
+
+fn chest_window_type(rows: usize) -> WindowType {
+    match rows {
+        1 => WindowType::GenericOneRow,
+        2 => WindowType::GenericTwoRow,
+        3 => WindowType::GenericThreeRow,
+        4 => WindowType::GenericFourRow,
+        5 => WindowType::GenericFiveRow,
+        _ => WindowType::GenericSixRow,
+    }
+}
+
+/// One of the given item, shown with the given name
+fn selector_item(item_name: &str, display_name: &str) -> Slot {
+    let item_id = ITEM_MAP
+        .get_by_right(item_name)
+        .or_else(|| ITEM_MAP.get_by_right(FALLBACK_ITEM))
+        .copied()
+        .unwrap_or(1);
+    let name = format!(
+        "{{\"text\":{},\"italic\":false}}",
+        json::stringify(display_name)
+    );
+    // { display: { Name: '{"text":"..","italic":false}' } }
+    let display = Tag::Compound(vec![NamedTag {
+        name: "Name".into(),
+        payload: Tag::String(name),
+    }]);
+    Slot {
+        item: Some(ItemStack {
+            item_id: item_id.into(),
+            item_count: 1,
+            nbt: Some(NamedTag {
+                name: String::new(),
+                payload: Tag::Compound(vec![NamedTag {
+                    name: "display".into(),
+                    payload: display,
+                }]),
+            }),
+        }),
+    }
+}
@@ -0,0 +1,109 @@
+use bimap::BiHashMap;
+
+use super::{BIOME_MAP, BLOCK_MAP, ENTITY_MAP, FLUID_MAP, GAME_EVENT_MAP, ITEM_MAP};
+use crate::protocol::current::{
+    proto::{
+        ChatPosition, HandshakeNextState, HandshakeSpec, LoginStartSpec, PlayClientChatMessageSpec,
+        PlayDestroyEntitiesSpec, PlayServerChatMessageSpec, PlayServerKeepAliveSpec,
+        StatusPongSpec,
+    },
+    protocol::{HasPacketId, HasPacketKind, Packet, RawPacket},
+    types::{Chat, VarInt},
+    uuid::UUID4,
+    BytesSerializer, PacketLatest, RawPacketLatest,
+};
+
+/// Checks that packets survive serializing and deserializing, and that the id registries loaded,
+/// so that a mismatched mcproto-rs or broken data files are caught before players connect
+pub fn self_test() -> anyhow::Result<()> {
+    let packets = vec![
+        PacketLatest::Handshake(HandshakeSpec {
+            version: 756.into(),
+            server_address: "127.0.0.1".into(),
+            server_port: 25565,
+            next_state: HandshakeNextState::Login,
+        }),
+        PacketLatest::StatusPong(StatusPongSpec { payload: 1234 }),
+        PacketLatest::LoginStart(LoginStartSpec {
+            name: "Splinter".into(),
+        }),
+        PacketLatest::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: -42 }),
+        PacketLatest::PlayClientChatMessage(PlayClientChatMessageSpec {
+            message: "/list".into(),
+        }),
+        PacketLatest::PlayServerChatMessage(PlayServerChatMessageSpec {
+            message: Chat::from_text("Splinter"),
+            position: ChatPosition::SystemMessage,
+            sender: UUID4::from(0u128),
+        }),
+        PacketLatest::PlayDestroyEntities(PlayDestroyEntitiesSpec {
+            entity_ids: vec![VarInt::from(1), VarInt::from(-1)].into(),
+        }),
+    ];
+    for packet in packets {
+        round_trip(&packet)?;
+    }
+    debug!("Packets round trip");
+
+    check_registry("block", &BLOCK_MAP, "stone")?;
+    check_registry("item", &ITEM_MAP, "stone")?;
+    check_registry("entity", &ENTITY_MAP, "player")?;
+    check_registry("fluid", &FLUID_MAP, "water")?;
+    check_registry("game event", &GAME_EVENT_MAP, "block_change")?;
+    check_registry("biome", &BIOME_MAP, "plains")?;
+    debug!("Registries loaded");
+    Ok(())
+}
+
+fn serialize(packet: &PacketLatest) -> anyhow::Result<Vec<u8>> {
+    let mut serializer = BytesSerializer::default();
+    packet
+        .mc_serialize_body(&mut serializer)
+        .map_err(|e| anyhow!("Failed to serialize {:?}: {:?}", packet.kind(), e))?;
+    Ok(serializer.into_bytes())
+}
+
+/// Serializes a packet, deserializes it, and serializes it again, failing if the two serialized
+/// forms differ
+fn round_trip(packet: &PacketLatest) -> anyhow::Result<()> {
+    let data = serialize(packet)?;
+    let raw = RawPacketLatest::create(packet.id(), &data)
+        .map_err(|e| anyhow!("Failed to create raw {:?}: {:?}", packet.kind(), e))?;
+    if raw.kind() != packet.kind() {
+        bail!(
+            "{:?} was read back as {:?}; packet ids don't match",
+            packet.kind(),
+            raw.kind()
+        );
+    }
+    let deserialized = raw
+        .deserialize()
+        .map_err(|e| anyhow!("Failed to deserialize {:?}: {:?}", packet.kind(), e))?;
+    if serialize(&deserialized)? != data {
+        bail!("{:?} changed after a round trip", packet.kind());
+    }
+    Ok(())
+}
+
+/// Checks that a registry isn't empty and maps the given name to an id and back
+fn check_registry(
+    registry: &str,
+    map: &BiHashMap<i32, String>,
+    known_name: &str,
+) -> anyhow::Result<()> {
+    if map.is_empty() {
+        bail!("The {} registry is empty", registry);
+    }
+    let id = map
+        .get_by_right(known_name)
+        .ok_or_else(|| anyhow!("The {} registry has no \"{}\"", registry, known_name))?;
+    match map.get_by_left(id) {
+        Some(name) if name == known_name => Ok(()),
+        _ => bail!(
+            "The {} registry maps \"{}\" to {}, but not back",
+            registry,
+            known_name,
+            id
+        ),
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::Ordering;
+
+use super::{PacketDestination, RelayPass};
+use crate::{
+    protocol::{
+        current::{
+            proto::{
+                Packet756 as PacketLatest,
+                Packet756Kind as PacketLatestKind,
+                PlayPlayerListHeaderFooterSpec,
+            },
+            protocol::PacketDirection,
+            types::Chat,
+        },
+        events::LazyDeserializedPacket,
+    },
+    proxy::client::SplinterClient,
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|proxy, connection, _client, sender, lazy_packet, destination| {
+            // the proxy's tab list text takes the place of what the server would show
+            if *sender == PacketDirection::ClientBound
+                && lazy_packet.kind() == PacketLatestKind::PlayPlayerListHeaderFooter
+                && proxy.config().tab_list.text_for(connection.server.id).is_some()
+            {
+                *destination = PacketDestination::None;
+            }
+        }),
+    }
+}
+
+impl SplinterClient {
+    /// Sends the tab list header and footer for the client's active server, if there is one
+    pub async fn send_tab_list(&self) -> anyhow::Result<()> {
+        let server_id = self.server_id();
+        let config = self.proxy.config();
+        let text = match config.tab_list.text_for(server_id) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let online = self.proxy.players.read().await.len().to_string();
+        let server = server_id.to_string();
+        let ping = self.ping.load(Ordering::Relaxed).to_string();
+        let fill = |template: &str| {
+            Chat::from_text(
+                &template
+                    .replace("{online}", &online)
+                    .replace("{server}", &server)
+                    .replace("{ping}", &ping),
+            )
+        };
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayPlayerListHeaderFooter(PlayPlayerListHeaderFooterSpec {
+                header: fill(&text.header),
+                footer: fill(&text.footer),
+            }),
+        ))
+        .await
+    }
+}
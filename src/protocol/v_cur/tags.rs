@@ -1,47 +1,128 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
+    fs,
     iter::FromIterator,
+    sync::Mutex,
 };
 
+use anyhow::Context;
 use bimap::BiHashMap;
 
-use crate::protocol::{
-    current::{
-        proto::{
-            PlayTagsSpec,
-            TagSpec,
-            TagType,
-            TypedTagList,
-        },
-        types::{
-            CountedArray,
-            VarInt,
+use crate::{
+    protocol::{
+        current::{
+            proto::{
+                PlayTagsSpec,
+                TagSpec,
+                TagType,
+                TypedTagList,
+            },
+            types::{
+                CountedArray,
+                VarInt,
+            },
         },
+        load_json_id_name_pairs,
+        TagList,
+        Tags,
     },
-    load_json_id_name_pairs,
-    TagList,
-    Tags,
+    proxy::config::DataPathsConfig,
 };
 
+/// Data bundled with the proxy, by its path from the repository root. With the `embedded-data`
+/// feature it is built into the binary, otherwise it is read from that path when loaded
+#[cfg(feature = "embedded-data")]
+macro_rules! bundled_data {
+    ($path:literal) => {
+        Cow::Borrowed(include_str!(concat!("../../../", $path)))
+    };
+}
+#[cfg(not(feature = "embedded-data"))]
+macro_rules! bundled_data {
+    ($path:literal) => {
+        read_data_file(concat!("./", $path))?
+    };
+}
+
 lazy_static! {
-    pub static ref BLOCK_MAP: BiHashMap<i32, String> =
-        BiHashMap::<i32, String>::from_iter(load_json_id_name_pairs(include_str!(
-            "../../../minecraft-data/data/pc/1.17/blocks.json"
-        )));
-    pub static ref ITEM_MAP: BiHashMap<i32, String> =
-        BiHashMap::<i32, String>::from_iter(load_json_id_name_pairs(include_str!(
-            "../../../minecraft-data/data/pc/1.17/items.json"
-        )));
-    pub static ref ENTITY_MAP: BiHashMap<i32, String> =
-        BiHashMap::<i32, String>::from_iter(load_json_id_name_pairs(include_str!(
-            "../../../minecraft-data/data/pc/1.17/entities.json"
-        )));
-    pub static ref FLUID_MAP: BiHashMap<i32, String> = BiHashMap::<i32, String>::from_iter(
-        load_json_id_name_pairs(include_str!("../../../fluids.json")),
-    );
-    pub static ref GAME_EVENT_MAP: BiHashMap<i32, String> = BiHashMap::<i32, String>::from_iter(
-        load_json_id_name_pairs(include_str!("../../../game_events.json")),
-    );
+    /// Maps read by `load_data`, each taken by its static below when first used
+    static ref LOADED_MAPS: Mutex<HashMap<&'static str, BiHashMap<i32, String>>> =
+        Mutex::new(HashMap::new());
+    pub static ref BLOCK_MAP: BiHashMap<i32, String> = take_loaded_map("blocks");
+    pub static ref ITEM_MAP: BiHashMap<i32, String> = take_loaded_map("items");
+    pub static ref ENTITY_MAP: BiHashMap<i32, String> = take_loaded_map("entities");
+    pub static ref FLUID_MAP: BiHashMap<i32, String> = take_loaded_map("fluids");
+    pub static ref GAME_EVENT_MAP: BiHashMap<i32, String> = take_loaded_map("game_events");
+    /// Proxy-side biome ids, which are the vanilla 1.17 ids
+    pub static ref BIOME_MAP: BiHashMap<i32, String> = take_loaded_map("biomes");
+}
+
+/// Reads every id-name map before any is used, so that bad data fails startup rather than a
+/// player's session. Maps are read from their configured paths, or else from the bundled data
+pub fn load_data(paths: &DataPathsConfig) -> anyhow::Result<()> {
+    let maps = [
+        ("blocks", &paths.blocks),
+        ("items", &paths.items),
+        ("entities", &paths.entities),
+        ("fluids", &paths.fluids),
+        ("game_events", &paths.game_events),
+        ("biomes", &paths.biomes),
+    ];
+    for (name, path) in maps.iter() {
+        let map = load_id_name_map(name, path.as_deref())?;
+        LOADED_MAPS.lock().unwrap().insert(*name, map);
+    }
+    lazy_static::initialize(&BLOCK_MAP);
+    lazy_static::initialize(&ITEM_MAP);
+    lazy_static::initialize(&ENTITY_MAP);
+    lazy_static::initialize(&FLUID_MAP);
+    lazy_static::initialize(&GAME_EVENT_MAP);
+    lazy_static::initialize(&BIOME_MAP);
+    Ok(())
+}
+
+/// Takes a map read by `load_data`. Maps used without `load_data`, ex. by tests, are read from
+/// the bundled data, which is known to be good
+fn take_loaded_map(name: &'static str) -> BiHashMap<i32, String> {
+    let loaded = LOADED_MAPS.lock().unwrap().remove(name);
+    match loaded {
+        Some(map) => map,
+        None => load_id_name_map(name, None).expect("Bundled data is invalid"),
+    }
+}
+
+/// Reads an id-name map from the given path if there is one, otherwise from the bundled data
+fn load_id_name_map(name: &str, path: Option<&str>) -> anyhow::Result<BiHashMap<i32, String>> {
+    let data = match path {
+        Some(path) => read_data_file(path)?,
+        None => bundled_map_data(name)?,
+    };
+    let pairs = load_json_id_name_pairs(data).with_context(|| {
+        format!(
+            "Failed to parse {} from \"{}\"",
+            name,
+            path.unwrap_or("bundled data")
+        )
+    })?;
+    Ok(BiHashMap::from_iter(pairs))
+}
+
+fn bundled_map_data(name: &str) -> anyhow::Result<Cow<'static, str>> {
+    Ok(match name {
+        "blocks" => bundled_data!("minecraft-data/data/pc/1.17/blocks.json"),
+        "items" => bundled_data!("minecraft-data/data/pc/1.17/items.json"),
+        "entities" => bundled_data!("minecraft-data/data/pc/1.17/entities.json"),
+        "fluids" => bundled_data!("fluids.json"),
+        "game_events" => bundled_data!("game_events.json"),
+        "biomes" => bundled_data!("biomes.json"),
+        _ => bail!("There is no bundled {} data", name),
+    })
+}
+
+fn read_data_file(path: &str) -> anyhow::Result<Cow<'static, str>> {
+    let data = fs::read_to_string(path).with_context(|| format!("Failed to read \"{}\"", path))?;
+    Ok(Cow::Owned(data))
 }
 
 pub fn proto_tags_to_tags(
@@ -120,3 +201,20 @@ impl From<&Tags> for PlayTagsSpec {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_bundled_maps() {
+        let blocks = load_id_name_map("blocks", None).unwrap();
+        assert_eq!(blocks.get_by_right("air"), Some(&0));
+    }
+
+    #[test]
+    fn missing_data_file_names_its_path() {
+        let e = load_id_name_map("blocks", Some("./no-such-blocks.json")).unwrap_err();
+        assert!(format!("{:#}", e).contains("no-such-blocks.json"));
+    }
+}
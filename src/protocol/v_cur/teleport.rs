@@ -0,0 +1,44 @@
+use super::{PacketDestination, RelayPass};
+use crate::protocol::current::{
+    protocol::PacketDirection,
+    PacketLatest,
+    PacketLatestKind,
+};
+
+inventory::submit! {
+    RelayPass {
+        order: 0,
+        pass: Box::new(|_proxy, connection, client, sender, lazy_packet, destination| {
+            match sender {
+                PacketDirection::ClientBound => {
+                    if lazy_packet.kind() == PacketLatestKind::PlayServerPlayerPositionAndLook {
+                        if let Ok(PacketLatest::PlayServerPlayerPositionAndLook(body)) = lazy_packet.packet() {
+                            smol::block_on(client.pending_teleports.lock())
+                                .insert(*body.teleport_id, connection.server.id);
+                        }
+                    }
+                }
+                PacketDirection::ServerBound => {
+                    if lazy_packet.kind() == PacketLatestKind::PlayTeleportConfirm {
+                        if let Ok(PacketLatest::PlayTeleportConfirm(body)) = lazy_packet.packet() {
+                            // only the server that asked for the teleport should get the
+                            // confirmation
+                            *destination = match smol::block_on(client.pending_teleports.lock())
+                                .remove(&*body.teleport_id)
+                            {
+                                Some(server_id) => PacketDestination::Server(server_id),
+                                None => {
+                                    debug!(
+                                        "Dropping teleport confirm from \"{}\" for unknown id {}",
+                                        &client.name, *body.teleport_id
+                                    );
+                                    PacketDestination::None
+                                }
+                            };
+                        }
+                    }
+                }
+            }
+        }),
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::atomic::Ordering;
+
+use super::PacketObserver;
+use crate::{
+    protocol::current::protocol::RawPacket,
+    systems::keepalive::unix_time_millis,
+};
+
+/// Most packets traced per second for a client with `debug packets` on
+const MAX_TRACED_PER_SECOND: u32 = 50;
+
+inventory::submit! {
+    PacketObserver(Box::new(|_proxy, _connection, client, sender, lazy_packet, destination| {
+        if log_enabled!(log::Level::Trace) {
+            match lazy_packet.deserialized() {
+                Some(packet) => trace!("\"{}\" {:?} -> {:?}: {:?}", &client.name, sender, destination, packet),
+                None => trace!("\"{}\" {:?} -> {:?}: {:?}", &client.name, sender, destination, lazy_packet.kind()),
+            }
+        }
+        if client.trace_packets.load(Ordering::Relaxed) {
+            let second = (unix_time_millis() / 1000) as u64;
+            let window = &mut *smol::block_on(client.trace_window.lock());
+            if window.0 != second {
+                if window.1 > MAX_TRACED_PER_SECOND {
+                    info!("[{}] {} packets not traced", &client.name, window.1 - MAX_TRACED_PER_SECOND);
+                }
+                *window = (second, 0);
+            }
+            window.1 += 1;
+            if window.1 <= MAX_TRACED_PER_SECOND {
+                let size = lazy_packet
+                    .raw_packet()
+                    .map(|raw| raw.data().len().to_string())
+                    .unwrap_or_else(|| "?".into());
+                info!(
+                    "[{}] {:?} {:?} -> {:?}, {} bytes",
+                    &client.name, lazy_packet.kind(), sender, destination, size
+                );
+            }
+        }
+    }))
+}
@@ -0,0 +1,155 @@
+use std::time::Instant;
+
+use crate::protocol::current::{
+    proto::PlayInitializeWorldBorderSpec, PacketLatest, PacketLatestKind,
+};
+
+/// Kinds of the packets that make up a server's world border: the packet that sets all of it,
+/// and the packets that each change one part of it
+pub const WORLD_BORDER_KINDS: &[PacketLatestKind] = &[
+    PacketLatestKind::PlayInitializeWorldBorder,
+    PacketLatestKind::PlayWorldBorderCenter,
+    PacketLatestKind::PlayWorldBorderLerpSize,
+    PacketLatestKind::PlayWorldBorderSize,
+    PacketLatestKind::PlayWorldBorderWarningDelay,
+    PacketLatestKind::PlayWorldBorderWarningReach,
+];
+
+/// A server's world border as last sent by the server, so that it can be sent whole to a client
+/// switching to that server
+pub struct WorldBorderState {
+    border: PlayInitializeWorldBorderSpec,
+    /// When the border started moving from `old_diameter` to `new_diameter`
+    lerp_started: Instant,
+}
+
+impl WorldBorderState {
+    pub fn new(border: PlayInitializeWorldBorderSpec) -> Self {
+        Self {
+            border,
+            lerp_started: Instant::now(),
+        }
+    }
+    /// Applies any of the world border packets
+    pub fn update(&mut self, packet: &PacketLatest) {
+        match packet {
+            PacketLatest::PlayInitializeWorldBorder(body) => *self = Self::new(body.clone()),
+            PacketLatest::PlayWorldBorderCenter(body) => {
+                self.border.x = body.x;
+                self.border.z = body.z;
+            }
+            PacketLatest::PlayWorldBorderLerpSize(body) => {
+                self.border.old_diameter = body.old_diameter;
+                self.border.new_diameter = body.new_diameter;
+                self.border.speed = body.speed;
+                self.lerp_started = Instant::now();
+            }
+            PacketLatest::PlayWorldBorderSize(body) => {
+                self.border.old_diameter = body.diameter;
+                self.border.new_diameter = body.diameter;
+                self.border.speed = 0.into();
+            }
+            PacketLatest::PlayWorldBorderWarningDelay(body) => {
+                self.border.warning_time = body.warning_time;
+            }
+            PacketLatest::PlayWorldBorderWarningReach(body) => {
+                self.border.warning_blocks = body.warning_blocks;
+            }
+            _ => {}
+        }
+    }
+    /// Packet setting the whole border as it is now. A border still moving picks up where it is
+    /// and only takes the time left, so the client sees the same movement the server's players do
+    pub fn initialize_packet(&self) -> PacketLatest {
+        let mut border = self.border.clone();
+        let duration = *border.speed;
+        if duration > 0 {
+            let elapsed = self.lerp_started.elapsed().as_millis() as i64;
+            if elapsed >= duration {
+                border.old_diameter = border.new_diameter;
+                border.speed = 0.into();
+            } else {
+                let progress = elapsed as f64 / duration as f64;
+                border.old_diameter += (border.new_diameter - border.old_diameter) * progress;
+                border.speed = (duration - elapsed).into();
+            }
+        }
+        PacketLatest::PlayInitializeWorldBorder(border)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::current::proto::{
+        PlayWorldBorderCenterSpec, PlayWorldBorderLerpSizeSpec, PlayWorldBorderSizeSpec,
+    };
+
+    fn state() -> WorldBorderState {
+        WorldBorderState::new(PlayInitializeWorldBorderSpec {
+            x: 0.,
+            z: 0.,
+            old_diameter: 100.,
+            new_diameter: 100.,
+            speed: 0.into(),
+            portal_teleport_boundary: 29999984.into(),
+            warning_blocks: 5.into(),
+            warning_time: 15.into(),
+        })
+    }
+
+    fn border(state: &WorldBorderState) -> PlayInitializeWorldBorderSpec {
+        match state.initialize_packet() {
+            PacketLatest::PlayInitializeWorldBorder(border) => border,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn applies_partial_updates() {
+        let mut state = state();
+        state.update(&PacketLatest::PlayWorldBorderCenter(
+            PlayWorldBorderCenterSpec { x: 8., z: -8. },
+        ));
+        state.update(&PacketLatest::PlayWorldBorderSize(
+            PlayWorldBorderSizeSpec { diameter: 50. },
+        ));
+        let border = border(&state);
+        assert_eq!((border.x, border.z), (8., -8.));
+        assert_eq!((border.old_diameter, border.new_diameter), (50., 50.));
+        assert_eq!(*border.speed, 0);
+        assert_eq!(*border.warning_blocks, 5);
+    }
+
+    #[test]
+    fn moving_border_keeps_only_time_left() {
+        let mut state = state();
+        state.update(&PacketLatest::PlayWorldBorderLerpSize(
+            PlayWorldBorderLerpSizeSpec {
+                old_diameter: 100.,
+                new_diameter: 200.,
+                speed: 1_000_000.into(),
+            },
+        ));
+        let border = border(&state);
+        assert_eq!(border.new_diameter, 200.);
+        assert!(border.old_diameter >= 100. && border.old_diameter < 101.);
+        assert!(*border.speed <= 1_000_000 && *border.speed > 990_000);
+    }
+
+    #[test]
+    fn finished_border_is_sent_still() {
+        let mut state = state();
+        state.update(&PacketLatest::PlayWorldBorderLerpSize(
+            PlayWorldBorderLerpSizeSpec {
+                old_diameter: 100.,
+                new_diameter: 200.,
+                speed: 1.into(),
+            },
+        ));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let border = border(&state);
+        assert_eq!((border.old_diameter, border.new_diameter), (200., 200.));
+        assert_eq!(*border.speed, 0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    net::IpAddr,
+    str::FromStr,
+};
+
+/// A set of IP ranges that connections are allowed from
+///
+/// Ranges are stored as inclusive pairs of IPv6 addresses (IPv4 addresses are mapped into IPv6),
+/// sorted and merged so that a lookup is a binary search.
+pub struct IpAllowlist {
+    ranges: Vec<(u128, u128)>,
+}
+
+impl IpAllowlist {
+    /// Parses a list of CIDR strings, ex. `10.0.0.0/8` or `::1/128`
+    pub fn from_cidrs(cidrs: &[String]) -> anyhow::Result<Self> {
+        let mut ranges = cidrs
+            .iter()
+            .map(|cidr| parse_cidr(cidr))
+            .collect::<anyhow::Result<Vec<(u128, u128)>>>()?;
+        ranges.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(Self {
+            ranges: merged,
+        })
+    }
+    /// Whether there are no ranges, in which case every address is allowed
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let addr = ip_to_u128(addr);
+        // find the last range starting at or before the address
+        let ind = self.ranges.partition_point(|(start, _)| *start <= addr);
+        ind > 0 && addr <= self.ranges[ind - 1].1
+    }
+}
+
+fn ip_to_u128(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u128::from(addr.to_ipv6_mapped()),
+        IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+fn parse_cidr(cidr: &str) -> anyhow::Result<(u128, u128)> {
+    let (addr_str, prefix_str) = match cidr.split_once('/') {
+        Some((addr_str, prefix_str)) => (addr_str, Some(prefix_str)),
+        None => (cidr, None),
+    };
+    let addr = IpAddr::from_str(addr_str.trim())
+        .map_err(|e| anyhow!("Invalid address in CIDR \"{}\": {}", cidr, e))?;
+    let max_prefix = match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let prefix = match prefix_str {
+        Some(prefix_str) => prefix_str
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| anyhow!("Invalid prefix length in CIDR \"{}\": {}", cidr, e))?,
+        None => max_prefix,
+    };
+    if prefix > max_prefix {
+        bail!("Prefix length in CIDR \"{}\" is too long", cidr);
+    }
+    // v4 addresses take up the last 32 bits of their mapped v6 address
+    let host_bits = max_prefix - prefix;
+    let mask = if host_bits >= 128 {
+        u128::MAX
+    } else {
+        (1u128 << host_bits) - 1
+    };
+    let start = ip_to_u128(addr) & !mask;
+    Ok((start, start | mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(cidrs: &[&str]) -> IpAllowlist {
+        let cidrs = cidrs
+            .iter()
+            .map(|cidr| cidr.to_string())
+            .collect::<Vec<String>>();
+        IpAllowlist::from_cidrs(&cidrs).unwrap()
+    }
+
+    fn allows(list: &IpAllowlist, addr: &str) -> bool {
+        list.allows(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn empty_list_allows_everything() {
+        let list = allowlist(&[]);
+        assert!(allows(&list, "203.0.113.7"));
+        assert!(allows(&list, "::1"));
+    }
+
+    #[test]
+    fn ipv4_ranges() {
+        let list = allowlist(&["10.0.0.0/8", "192.168.1.5"]);
+        assert!(allows(&list, "10.0.0.0"));
+        assert!(allows(&list, "10.255.255.255"));
+        assert!(!allows(&list, "11.0.0.0"));
+        assert!(allows(&list, "192.168.1.5"));
+        assert!(!allows(&list, "192.168.1.6"));
+        // the v4 range doesn't cover unrelated v6 addresses
+        assert!(!allows(&list, "::a00:1"));
+    }
+
+    #[test]
+    fn ipv6_ranges() {
+        let list = allowlist(&["2001:db8::/32", "::1/128"]);
+        assert!(allows(&list, "2001:db8:ffff::1"));
+        assert!(!allows(&list, "2001:db9::1"));
+        assert!(allows(&list, "::1"));
+        assert!(!allows(&list, "127.0.0.1"));
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_merge() {
+        let list = allowlist(&["10.0.1.0/24", "10.0.0.0/24", "10.0.0.128/25"]);
+        assert_eq!(list.ranges.len(), 1);
+        assert!(allows(&list, "10.0.1.255"));
+        assert!(!allows(&list, "10.0.2.0"));
+    }
+
+    #[test]
+    fn zero_prefix_covers_the_family() {
+        let list = allowlist(&["::/0"]);
+        assert!(allows(&list, "2001:db8::1"));
+        assert!(allows(&list, "203.0.113.7"));
+    }
+
+    #[test]
+    fn invalid_cidrs_are_errors() {
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("::/129").is_err());
+        assert!(parse_cidr("10.0.0/8").is_err());
+        assert!(parse_cidr("10.0.0.0/x").is_err());
+    }
+}
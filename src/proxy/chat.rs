@@ -15,7 +15,10 @@ use crate::{
         client::SplinterClient,
         SplinterProxy,
     },
-    systems::commands::CommandSender,
+    systems::commands::{
+        process_command,
+        CommandSender,
+    },
 };
 
 pub trait ToChat {
@@ -74,6 +77,101 @@ pub fn format_chat_message(sender: &CommandSender, message: impl ToChat + ToStri
     })
 }
 
+/// What a `ChatHandler` does with a chat message
+pub enum ChatAction {
+    /// Leaves the message as it is
+    Pass,
+    /// Puts another message in its place for the handlers after it
+    Replace(String),
+    /// Stops the message from being relayed or broadcast
+    Drop,
+}
+
+type ChatHandlerFn =
+    Box<dyn Send + Sync + Fn(&Arc<SplinterProxy>, &Arc<SplinterClient>, &str) -> ChatAction>;
+/// Sees every chat message a player sends before it is relayed to their server (if it starts
+/// with `/`) or broadcast to every player (otherwise). Proxy commands are run before any handler
+/// sees them, and never reach the handlers
+///
+/// Handlers run in order of `priority`, lowest first, each seeing the message as the handlers
+/// before it left it
+pub struct ChatHandler {
+    pub priority: i32,
+    pub handle: ChatHandlerFn,
+}
+
+inventory::collect!(ChatHandler);
+
+inventory::submit! {
+    ChatHandler {
+        priority: 100,
+        handle: Box::new(|proxy, _client, msg| {
+            match filter_words(msg, &proxy.config().chat_filter) {
+                Some(filtered) => ChatAction::Replace(filtered),
+                None => ChatAction::Pass,
+            }
+        }),
+    }
+}
+
+inventory::submit! {
+    // after the filter, so that the prefix itself is never masked
+    ChatHandler {
+        priority: 200,
+        handle: Box::new(|proxy, client, msg| {
+            match &proxy.config().chat_prefix {
+                // commands are left alone, or the server wouldn't recognize them
+                Some(prefix) if !msg.starts_with('/') => ChatAction::Replace(format!(
+                    "{}{}",
+                    prefix.replace("{server}", &client.server_id().to_string()),
+                    msg
+                )),
+                _ => ChatAction::Pass,
+            }
+        }),
+    }
+}
+
+/// Splits a chat message into a command and its arguments if it is one of the given player
+/// commands, which run on the proxy instead of being passed on to the server
+fn player_command<'a>(msg: &'a str, player_commands: &[String]) -> Option<(&'a str, Vec<&'a str>)> {
+    let mut split = msg.strip_prefix('/')?.split_whitespace();
+    let cmd = split
+        .next()
+        .filter(|cmd| player_commands.iter().any(|name| name == cmd))?;
+    Some((cmd, split.collect()))
+}
+
+/// Masks every occurrence of the given words with asterisks, ignoring case. Returns `None` if
+/// there was nothing to mask
+fn filter_words(msg: &str, words: &[String]) -> Option<String> {
+    // ascii lowercasing keeps byte offsets the same as in the original message
+    let lower = msg.to_ascii_lowercase();
+    let mut masked = Vec::new();
+    for word in words.iter().filter(|word| !word.is_empty()) {
+        let word = word.to_ascii_lowercase();
+        masked.extend(
+            lower
+                .match_indices(word.as_str())
+                .map(|(start, found)| start..start + found.len()),
+        );
+    }
+    if masked.is_empty() {
+        return None;
+    }
+    Some(
+        msg.char_indices()
+            .map(|(i, c)| {
+                if masked.iter().any(|range| range.contains(&i)) {
+                    '*'
+                } else {
+                    c
+                }
+            })
+            .collect(),
+    )
+}
+
 pub async fn receive_chat_message(
     proxy: &Arc<SplinterProxy>,
     client: &Arc<SplinterClient>,
@@ -89,8 +187,32 @@ pub async fn receive_chat_message(
     let cmd_sender = CommandSender::Player(Arc::clone(client));
     let msg_string = format_chat_message_string(&cmd_sender, msg);
     info!("{}", msg_string);
-    if let Some('/') = msg.chars().next() {
-        if let Err(e) = client.relay_message(msg).await {
+    if let Some((cmd, args)) = player_command(msg, &proxy.config().player_commands) {
+        if let Err(e) = process_command(proxy, cmd, args.as_slice(), &cmd_sender).await {
+            if let Err(e) = cmd_sender.respond(format!("Command failed: {}", e)).await {
+                error!(
+                    "Failed to send command failure message to {}: {}",
+                    cmd_sender.name(),
+                    e
+                );
+            }
+        }
+        return;
+    }
+    let mut handlers = inventory::iter::<ChatHandler>
+        .into_iter()
+        .collect::<Vec<&ChatHandler>>();
+    handlers.sort_by_key(|handler| handler.priority);
+    let mut msg = msg.to_owned();
+    for handler in handlers {
+        match (handler.handle)(proxy, client, &msg) {
+            ChatAction::Pass => {}
+            ChatAction::Replace(replacement) => msg = replacement,
+            ChatAction::Drop => return,
+        }
+    }
+    if msg.starts_with('/') {
+        if let Err(e) = client.relay_message(&msg).await {
             error!(
                 "Failed to relay chat message from \"{}\" to server \"{}\": {}",
                 &client.name,
@@ -118,3 +240,42 @@ pub async fn broadcast_message(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_player_commands() {
+        let commands = vec!["list".to_owned(), "connect".to_owned()];
+        assert_eq!(
+            player_command("/connect 2", &commands),
+            Some(("connect", vec!["2"]))
+        );
+        assert_eq!(player_command("/list", &commands), Some(("list", vec![])));
+    }
+
+    #[test]
+    fn leaves_other_messages_alone() {
+        let commands = vec!["list".to_owned()];
+        assert_eq!(player_command("list", &commands), None);
+        assert_eq!(player_command("/gamemode creative", &commands), None);
+        assert_eq!(player_command("/", &commands), None);
+    }
+
+    #[test]
+    fn masks_filtered_words() {
+        let words = vec!["darn".to_owned(), "".to_owned()];
+        assert_eq!(
+            filter_words("Darn it, DARNED", &words).as_deref(),
+            Some("**** it, ****ED")
+        );
+        assert_eq!(filter_words("all good", &words), None);
+    }
+
+    #[test]
+    fn masks_overlapping_words_and_keeps_other_characters() {
+        let words = vec!["ab".to_owned(), "bc".to_owned()];
+        assert_eq!(filter_words("éabcé", &words).as_deref(), Some("é***é"));
+    }
+}
@@ -4,9 +4,10 @@ use std::{
     net::{SocketAddr, TcpStream},
     str,
     sync::{
-        atomic::{AtomicBool, AtomicI8, Ordering},
+        atomic::{AtomicBool, AtomicI8, AtomicU64, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
@@ -15,7 +16,7 @@ use async_compat::CompatExt;
 use async_dup::Arc as AsyncArc;
 use craftio_rs::{CraftAsyncReader, CraftAsyncWriter, CraftConnection, CraftIo};
 use smallvec::SmallVec;
-use smol::{lock::Mutex, Async};
+use smol::{lock::Mutex, Async, Timer};
 
 use crate::{
     protocol::{
@@ -23,17 +24,32 @@ use crate::{
         current::{
             proto::{
                 ClientStatusAction, PlayClientPlayerPositionAndRotationSpec,
-                PlayClientPluginMessageSpec,
+                PlayClientPluginMessageSpec, PlayDestroyEntitiesSpec, PlayJoinGameSpec,
             },
-            protocol::{PacketDirection, State},
-            types::Vec3,
+            protocol::{HasPacketKind, PacketDirection, State},
+            types::{VarInt, Vec3},
             uuid::UUID4,
             PacketLatest, RawPacketLatest,
         },
-        v_cur, AsyncCraftWriter,
+        events::LazyDeserializedPacket,
+        state::ConnectionState,
+        v_cur::{self, send_position_set, PendingKeepAlives},
+        AsyncCraftWriter,
+    },
+    proxy::{
+        logging::LogSpan,
+        lru::LruMap,
+        mapping,
+        proxyprotocol,
+        ratelimit::RateLimiter,
+        server::SplinterServerConnection,
+        SplinterProxy,
+    },
+    systems::{
+        commands::CommandSender,
+        keepalive::{self, watch_dummy},
+        playersave::PlInfoPlayer,
     },
-    proxy::{mapping, server::SplinterServerConnection, SplinterProxy},
-    systems::keepalive::{self, watch_dummy},
 };
 
 pub struct ChunkLoadData {
@@ -44,6 +60,10 @@ pub struct ChunkLoadData {
 
 pub struct SplinterClient {
     pub name: String,
+    /// Address the client connected from, as given by the PROXY protocol if it is used
+    pub address: SocketAddr,
+    /// Protocol version the client gave in its handshake
+    pub protocol_version: i32,
     pub writer: Mutex<AsyncCraftWriter>,
     pub alive: AtomicBool,
     pub uuid: UUID4,
@@ -52,23 +72,66 @@ pub struct SplinterClient {
     pub dummy_servers: ArcSwap<Vec<(u64, Arc<SplinterServerConnection>)>>,
     pub proxy: Arc<SplinterProxy>,
     pub last_keep_alive: Mutex<u128>,
+    /// Unix time in milliseconds that the client's active server last changed through zoning, or
+    /// that it joined
+    pub last_server_switch: Mutex<u128>,
+    /// Keep alives sent to the client that have yet to be answered
+    pub pending_keep_alives: Mutex<PendingKeepAlives>,
+    /// Round trip time of the client's last keep alive, in milliseconds
+    pub ping: AtomicU64,
+    pub reconnecting: AtomicBool,
+    /// Whether the client has been taken off of every server with `send_to_limbo`
+    pub in_limbo: AtomicBool,
+    /// Join game packet the client was sent when it joined, which takes it back out of the limbo
+    /// world
+    pub join_game: Mutex<Option<PlayJoinGameSpec>>,
+    pub state: Mutex<ConnectionState>,
 
     pub held_slot: AtomicI8,
-    pub known_chunks: Mutex<HashMap<(i32, i32), ChunkLoadData>>,
+    /// Serialized item stacks in the client's inventory window, by slot. Empty slots are left out.
+    /// Only kept for the player data; the client's server is what restores its inventory
+    pub inventory: Mutex<HashMap<i16, Vec<u8>>>,
+    pub known_chunks: Mutex<LruMap<(i32, i32), ChunkLoadData>>,
     pub known_eids: Mutex<HashSet<i32>>,
+    /// Number of entity spawns dropped because the client had too many mapped entities
+    pub entity_cap_hits: AtomicU64,
+    /// Number of scoreboard objectives named for the client, which numbers the next one
+    pub objective_count: AtomicU64,
+    /// Only the client's relay uses this, so it is never contended
+    pub rate_limiter: Mutex<RateLimiter>,
+    /// Teleport ids the client has yet to confirm, mapped to the id of the server that sent them
+    pub pending_teleports: Mutex<HashMap<i32, u64>>,
+    /// Server the client chose to stay on regardless of zoning
+    pub pinned_server: ArcSwap<Option<u64>>,
+    /// Whether this client's packets are logged, toggled by `debug packets`
+    pub trace_packets: AtomicBool,
+    /// Second (since the unix epoch) that packets were last traced in, and how many were traced
+    /// in it
+    pub trace_window: Mutex<(u64, u32)>,
     pub position: ArcSwap<Vec3<f64>>,
+    /// Proxy-side uuids of the player info entries the client has been sent, mapped to the id of
+    /// the server whose entry it was
+    pub player_info_sources: Mutex<HashMap<UUID4, u64>>,
+    /// Entered by the client's relay loops, so their log lines name the client
+    pub log_span: LogSpan,
 }
 impl SplinterClient {
     pub fn new(
         proxy: Arc<SplinterProxy>,
         name: String,
+        address: SocketAddr,
+        protocol_version: i32,
         writer: AsyncCraftWriter,
         active_server: Arc<SplinterServerConnection>,
         position: Vec3<f64>,
     ) -> Self {
         let uuid = mapping::uuid_from_name(&name);
+        let max_tracked_chunks = proxy.config().max_tracked_chunks;
+        let log_span = LogSpan::client(&name, uuid);
         Self {
             name,
+            address,
+            protocol_version,
             writer: Mutex::new(writer),
             alive: AtomicBool::new(true),
             uuid,
@@ -77,18 +140,64 @@ impl SplinterClient {
             dummy_servers: ArcSwap::new(Arc::new(Vec::new())),
             proxy,
             last_keep_alive: Mutex::new(keepalive::unix_time_millis()),
+            last_server_switch: Mutex::new(keepalive::unix_time_millis()),
+            pending_keep_alives: Mutex::new(PendingKeepAlives::default()),
+            ping: AtomicU64::new(0),
+            reconnecting: AtomicBool::new(false),
+            in_limbo: AtomicBool::new(false),
+            join_game: Mutex::new(None),
+            state: Mutex::new(ConnectionState::Play),
             held_slot: AtomicI8::new(0),
-            known_chunks: Mutex::new(HashMap::new()),
+            inventory: Mutex::new(HashMap::new()),
+            known_chunks: Mutex::new(LruMap::new(max_tracked_chunks)),
             known_eids: Mutex::new(HashSet::new()),
+            entity_cap_hits: AtomicU64::new(0),
+            objective_count: AtomicU64::new(0),
+            rate_limiter: Mutex::new(RateLimiter::default()),
+            pending_teleports: Mutex::new(HashMap::new()),
+            pinned_server: ArcSwap::new(Arc::new(None)),
+            trace_packets: AtomicBool::new(false),
+            trace_window: Mutex::new((0, 0)),
             position: ArcSwap::new(Arc::new(position)),
+            player_info_sources: Mutex::new(HashMap::new()),
+            log_span,
         }
     }
     pub async fn set_alive(&self, value: bool) {
         self.alive.store(value, Ordering::Relaxed);
+        if !value {
+            // a connection can always die
+            self.state
+                .lock()
+                .await
+                .transition(ConnectionState::Dead)
+                .ok();
+        }
+    }
+    /// Records the client's current position, held slot, and inventory in the proxy's player data
+    pub async fn store_player_info(&self) {
+        let player = PlInfoPlayer::new(
+            &self.name,
+            &**self.position.load(),
+            self.held_slot.load(Ordering::Relaxed),
+            &*self.inventory.lock().await,
+        );
+        self.proxy
+            .player_data
+            .lock()
+            .await
+            .players
+            .insert(self.uuid, player);
     }
     pub fn server_id(&self) -> u64 {
         self.active_server.load().server.id
     }
+    /// Gets the ids of every server this client is connected to, starting with the active server
+    pub fn connected_servers(&self) -> Vec<u64> {
+        let mut ids = vec![self.server_id()];
+        ids.extend(self.dummy_servers.load().iter().map(|(id, _)| *id));
+        ids
+    }
     pub async fn disconnect_dummy(&self, target_id: u64) -> anyhow::Result<()> {
         debug!("disconecting {}-{}", &self.name, target_id);
         let dummy_servers = &**self.dummy_servers.load();
@@ -99,7 +208,7 @@ impl SplinterClient {
         let mut new_dummy_servers = dummy_servers.clone();
         let (_dummy_id, dummy) = new_dummy_servers.remove(ind);
         self.dummy_servers.store(Arc::new(new_dummy_servers));
-        dummy.alive.store(false, Ordering::Relaxed);
+        dummy.close();
         Ok(())
     }
     /// takes a dummy away from the client's dummy servers and returns it
@@ -131,40 +240,105 @@ impl SplinterClient {
         let dummy = self.grab_dummy(target_id)?;
         // remember the dummy player's eid
         let dummy_eid = dummy.eid;
+        let replayed_packets = dummy.replayed_state.lock().await.packets();
         // swap the dummy connection with the active connection
         let previously_active_conn = self.active_server.swap(dummy);
-        // get the ampping tables
-        let mapping = &mut *self.proxy.mapping.lock().await;
-        // find the corresponding proxy-side ids
-        let proxy_eid = *mapping
-            .eids
-            .get_by_right(&(previously_active_conn.server.id, previously_active_conn.eid))
-            .unwrap();
-        // replace what the proxy side ids map to to the now active previously dummy eid
-        mapping.eids.insert(proxy_eid, (target_id, dummy_eid));
+        *self.last_server_switch.lock().await = keepalive::unix_time_millis();
+        // show the client the now active connection's player state
+        for packet in replayed_packets {
+            self.write_packet(LazyDeserializedPacket::from_packet(packet))
+                .await
+                .with_context(|| format!("Failed to replay state to \"{}\"", &self.name))?;
+        }
+        // only the active server's boss bars are shown unless all servers' are
+        if !self.shows_background_boss_bars() {
+            let now_active_conn = self.active_server.load_full();
+            self.hide_boss_bars(&previously_active_conn)
+                .await
+                .with_context(|| format!("Failed to send boss bars to \"{}\"", &self.name))?;
+            self.show_boss_bars(&now_active_conn)
+                .await
+                .with_context(|| format!("Failed to send boss bars to \"{}\"", &self.name))?;
+        }
+        {
+            // get the ampping tables
+            let mapping = &mut *self.proxy.mapping.lock().await;
+            // find the corresponding proxy-side ids
+            let proxy_eid = *mapping
+                .eids
+                .get_by_right(&(previously_active_conn.server.id, previously_active_conn.eid))
+                .unwrap();
+            // replace what the proxy side ids map to to the now active previously dummy eid
+            mapping.eids.insert(proxy_eid, (target_id, dummy_eid));
+        }
         // put the previously active connection into the dummy connections
         self.add_dummy(&previously_active_conn);
         // watch the now dummy previously active connection
         watch_dummy(Arc::clone(self), previously_active_conn).await;
+        // the now active server's player info entries take precedence
+        self.sync_player_info(&[])
+            .await
+            .with_context(|| format!("Failed to send player info to \"{}\"", &self.name))?;
+        self.send_tab_list()
+            .await
+            .with_context(|| format!("Failed to send tab list to \"{}\"", &self.name))?;
+        self.send_routing_message().await.with_context(|| {
+            format!("Failed to send routing message to \"{}\"", &self.name)
+        })?;
         Ok(())
     }
     pub async fn connect_dummy(self: &Arc<SplinterClient>, target_id: u64) -> anyhow::Result<()> {
         debug!("connecting {}-{}", &self.name, target_id);
-        let server = Arc::clone(self.proxy.servers.read().await.get(&target_id).unwrap());
-        let (server_reader, server_writer) = server
-            .connect()
+        let start = Instant::now();
+        let arc_conn = Arc::new(self.login_server_connection(target_id).await?);
+        debug!(
+            "connected {}-{} in {:?}",
+            &self.name,
+            target_id,
+            start.elapsed()
+        );
+        if self.shows_background_boss_bars() {
+            self.show_boss_bars(&arc_conn)
+                .await
+                .with_context(|| format!("Failed to send boss bars to \"{}\"", &self.name))?;
+        }
+        self.add_dummy(&arc_conn);
+        watch_dummy(Arc::clone(self), arc_conn).await;
+        self.sync_player_info(&[])
             .await
-            .with_context(|| format!("Failed to connect dummy to server {}", target_id))?
-            .into_split();
-        let mut server_conn = SplinterServerConnection {
-            writer: Mutex::new(server_writer),
-            reader: Mutex::new(server_reader),
-            server: (*server).clone(),
-            alive: AtomicBool::new(true),
-            eid: -1,
-            uuid: UUID4::from(0u128),
-            known_chunks: Mutex::new(HashSet::new()),
-        };
+            .with_context(|| format!("Failed to send player info to \"{}\"", &self.name))?;
+        Ok(())
+    }
+    /// Logs in to the target server as this client and waits until the server places the player
+    /// in the world. The returned connection is not attached to the client
+    pub async fn login_server_connection(
+        &self,
+        target_id: u64,
+    ) -> anyhow::Result<SplinterServerConnection> {
+        if !self
+            .proxy
+            .config()
+            .server_allows_version(target_id, self.protocol_version)
+        {
+            bail!(
+                "Server {} does not allow protocol version {} of \"{}\"",
+                target_id,
+                self.protocol_version,
+                &self.name
+            );
+        }
+        let server = Arc::clone(
+            self.proxy
+                .servers
+                .read()
+                .await
+                .get(&target_id)
+                .ok_or_else(|| anyhow!("No server with id {}", target_id))?,
+        );
+        let mut server_conn = server
+            .open_connection(self.address)
+            .await
+            .with_context(|| format!("Failed to connect dummy to server {}", target_id))?;
 
         // let mut player_position = None;
 
@@ -178,13 +352,23 @@ impl SplinterClient {
                 .get_mut()
                 .read_packet_async::<RawPacketLatest>()
                 .await?;
+            if let Some(packet) = packet.as_ref() {
+                if self.proxy.is_replayed(packet.kind()) {
+                    server_conn.record_state(packet).await;
+                    continue;
+                }
+            }
             match packet {
                 Some(PacketLatest::LoginEncryptionRequest(_)) => bail!(
                     "Failed to connect to server {} because it requested encryption",
                     target_id
                 ),
+                Some(PacketLatest::LoginPluginRequest(body)) => {
+                    v_cur::answer_login_plugin_request(&self.proxy, &mut server_conn, body).await?;
+                }
                 Some(PacketLatest::LoginSetCompression(body)) => {
-                    let threshold = if *body.threshold > 0 {
+                    // zero compresses every packet; only a negative threshold turns it off
+                    let threshold = if *body.threshold >= 0 {
                         Some(*body.threshold)
                     } else {
                         None
@@ -200,11 +384,22 @@ impl SplinterClient {
                 }
                 Some(PacketLatest::LoginSuccess(body)) => {
                     server_conn.uuid = body.uuid;
+                    self.proxy
+                        .mapping
+                        .lock()
+                        .await
+                        .register_uuid_mapping(target_id, body.uuid, self.uuid);
                     server_conn.writer.get_mut().set_state(State::Play);
                     server_conn.reader.get_mut().set_state(State::Play);
                 }
-                Some(PacketLatest::PlayJoinGame(body)) => {
+                Some(PacketLatest::PlayJoinGame(mut body)) => {
                     server_conn.eid = body.entity_id;
+                    *server_conn.dimension.get_mut() = Some(v_cur::ServerDimension::from(&body));
+                    v_cur::map_dimension_codec(
+                        &mut *self.proxy.mapping.lock().await,
+                        target_id,
+                        &mut body.dimension_codec,
+                    );
                     // note: we do not map here. any mapping would get in the way of the active
                     // connections main eid mapping
                     // send brand here if wanted, but its not really necessary
@@ -226,13 +421,6 @@ impl SplinterClient {
                         }
                     }
                 }
-                Some(PacketLatest::PlayServerDifficulty(_body)) => {
-                    // ignore
-                }
-                Some(PacketLatest::PlayServerPlayerAbilities(_body)) => {
-                    // ignore
-                    // TODO: may need to do something with this ex. transitioning a player between servers when theyre flying
-                }
                 Some(PacketLatest::PlayServerHeldItemChange(_body)) => {
                     // ignore
                 }
@@ -268,14 +456,17 @@ impl SplinterClient {
                     v_cur::send_client_status(&mut server_conn, ClientStatusAction::PerformRespawn)
                         .await?;
                     v_cur::send_held_item_change(
-                        &mut server_conn,
+                        server_conn.writer.get_mut(),
                         self.held_slot.load(Ordering::Relaxed),
                     )
                     .await?;
                     break;
                 }
-                Some(PacketLatest::PlayPlayerInfo(_body)) => {
-                    // ignore
+                Some(PacketLatest::PlayPlayerInfo(mut body)) => {
+                    // the client is sent these once the connection is attached to it
+                    let map = &mut *self.proxy.mapping.lock().await;
+                    v_cur::map_player_info_uuids(map, target_id, &mut body.actions);
+                    map.record_player_info(target_id, &body.actions);
                 }
                 Some(PacketLatest::PlayUpdateViewPosition(_body)) => {
                     // ignore
@@ -289,15 +480,289 @@ impl SplinterClient {
                 Some(PacketLatest::PlaySpawnPosition(_body)) => {
                     // ignore
                 }
+                Some(PacketLatest::PlaySetExperience(_body)) => {
+                    // ignore
+                }
+                Some(PacketLatest::PlayUpdateHealth(_body)) => {
+                    // ignore
+                }
+                Some(PacketLatest::PlayServerDifficulty(_body)) => {
+                    // ignore
+                }
+                Some(PacketLatest::PlayServerPlayerAbilities(_body)) => {
+                    // ignore
+                }
+                Some(PacketLatest::PlayBossBar(body)) => {
+                    // shown to the client once the connection is in use
+                    server_conn.record_boss_bar(&body).await;
+                }
                 Some(packet) => warn!("Unexpected packet during login {:?}", packet),
                 None => bail!("Connection attempt to server {} closed", target_id),
             }
         }
-        let arc_conn = Arc::new(server_conn);
-        self.add_dummy(&arc_conn);
-        watch_dummy(Arc::clone(self), arc_conn).await;
+        Ok(server_conn)
+    }
+    /// Tears down the active server connection and logs in to the same server again, without
+    /// disconnecting the client from the proxy
+    pub async fn reconnect(self: &Arc<SplinterClient>) -> anyhow::Result<()> {
+        let target_id = self.server_id();
+        debug!("reconnecting {}-{}", &self.name, target_id);
+        self.reconnecting.store(true, Ordering::Relaxed);
+        let res = self.replace_active_connection(target_id).await;
+        self.reconnecting.store(false, Ordering::Relaxed);
+        res
+    }
+    /// Moves the client off of an active connection that closed unexpectedly. The same server is
+    /// tried again with backoff, and then the configured fallback server. Errors if the client
+    /// could not be moved to either
+    pub async fn fail_over(self: &Arc<SplinterClient>) -> anyhow::Result<()> {
+        let failed_id = self.server_id();
+        let config = &self.proxy.config().failover;
+        self.reconnecting.store(true, Ordering::Relaxed);
+        let mut delay = config.retry_delay;
+        let mut res = Err(anyhow!("Server {} went down", failed_id));
+        for attempt in 1..=config.retries {
+            Timer::after(Duration::from_millis(delay)).await;
+            delay = delay.saturating_mul(2);
+            res = self.replace_active_connection(failed_id).await;
+            match &res {
+                Ok(()) => break,
+                Err(e) => warn!(
+                    "Reconnect attempt {} of \"{}\" to server {} failed: {:?}",
+                    attempt, &self.name, failed_id, e
+                ),
+            }
+        }
+        if res.is_err() {
+            if let Some(fallback_id) = config.fallback_server.filter(|id| *id != failed_id) {
+                res = self.replace_active_connection(fallback_id).await;
+                if res.is_ok() {
+                    // keep zoning from moving the client straight back to the downed server
+                    self.pinned_server.store(Arc::new(Some(fallback_id)));
+                    let message = config
+                        .fallback_message
+                        .replace("{failed}", &failed_id.to_string())
+                        .replace("{server}", &fallback_id.to_string());
+                    if let Err(e) = self.send_message(message, &CommandSender::Console).await {
+                        error!("Failed to send fallback message to {}: {}", &self.name, e);
+                    }
+                }
+            }
+        }
+        self.reconnecting.store(false, Ordering::Relaxed);
+        res
+    }
+    /// Disconnects the client from every server while keeping it connected to the proxy. The
+    /// client is shown the proxy's empty limbo world and kept alive by the proxy until
+    /// `leave_limbo` moves it to a server
+    pub async fn send_to_limbo(&self) -> anyhow::Result<()> {
+        if self.in_limbo.swap(true, Ordering::Relaxed) {
+            bail!("\"{}\" is already in limbo", &self.name);
+        }
+        debug!("sending {} to limbo", &self.name);
+        for (server_id, _) in self.dummy_servers.load().iter() {
+            if let Err(e) = self.disconnect_dummy(*server_id).await {
+                // the client is still on its active server
+                self.in_limbo.store(false, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        self.active_server.load().close();
+        v_cur::send_limbo_world(&mut *self.writer.lock().await, &**self.position.load())
+            .await
+            .with_context(|| format!("Failed to send limbo world to \"{}\"", &self.name))
+    }
+    /// Moves a client in limbo to the target server
+    pub async fn leave_limbo(self: &Arc<SplinterClient>, target_id: u64) -> anyhow::Result<()> {
+        if !self.in_limbo.load(Ordering::Relaxed) {
+            bail!("\"{}\" is not in limbo", &self.name);
+        }
+        debug!("moving {} out of limbo to {}", &self.name, target_id);
+        // the limbo world's join game replaced the servers' worlds and biomes the client knew of
+        let join_game =
+            self.join_game.lock().await.clone().ok_or_else(|| {
+                anyhow!("\"{}\" has no join game to leave limbo with", &self.name)
+            })?;
+        self.write_packet(LazyDeserializedPacket::from_packet(
+            PacketLatest::PlayJoinGame(join_game),
+        ))
+        .await
+        .with_context(|| format!("Failed to take \"{}\" out of limbo", &self.name))?;
+        // the client is in no server's world until it is moved into the target's
+        *self.active_server.load().dimension.lock().await = None;
+        self.replace_active_connection(target_id).await?;
+        self.in_limbo.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+    /// Logs in to the target server and makes it the active connection in place of the current
+    /// one, which is closed. The server relay must be held off with `reconnecting` or `in_limbo`
+    /// while this runs
+    async fn replace_active_connection(
+        self: &Arc<SplinterClient>,
+        target_id: u64,
+    ) -> anyhow::Result<()> {
+        let old_conn = self.active_server.load_full();
+        // stop relaying from the old connection before the server kicks it for logging in again
+        old_conn.close();
+        let new_conn = Arc::new(self.login_server_connection(target_id).await?);
+        self.active_server.store(Arc::clone(&new_conn));
+        let old_id = old_conn.server.id;
+        // move the client into the new connection's world if it was shown another one
+        let old_dimension = old_conn.dimension.lock().await.clone();
+        let dimension_packets = match &*new_conn.dimension.lock().await {
+            Some(dimension) => dimension.change_packets(old_dimension.as_ref()),
+            None => vec![],
+        };
+        let changed_world = !dimension_packets.is_empty();
+        for packet in dimension_packets {
+            self.write_packet(LazyDeserializedPacket::from_packet(packet))
+                .await
+                .with_context(|| format!("Failed to change world of \"{}\"", &self.name))?;
+        }
+        if old_id != target_id || changed_world {
+            // show the client the new server's player state, as on a server switch. respawning
+            // also resets it
+            let replayed_packets = new_conn.replayed_state.lock().await.packets();
+            for packet in replayed_packets {
+                self.write_packet(LazyDeserializedPacket::from_packet(packet))
+                    .await
+                    .with_context(|| format!("Failed to replay state to \"{}\"", &self.name))?;
+            }
+        }
+        // the old connection's boss bars went with it
+        self.hide_boss_bars(&old_conn)
+            .await
+            .with_context(|| format!("Failed to send boss bars to \"{}\"", &self.name))?;
+        self.show_boss_bars(&new_conn)
+            .await
+            .with_context(|| format!("Failed to send boss bars to \"{}\"", &self.name))?;
+
+        // point the player's proxy-side eid at the new server-side eid, and find every entity
+        // the old connection had given the client. the new connection will send them again under
+        // new ids
+        let stale_eids = {
+            let mapping = &mut *self.proxy.mapping.lock().await;
+            let proxy_eid = *mapping
+                .eids
+                .get_by_right(&(old_id, old_conn.eid))
+                .ok_or_else(|| anyhow!("No eid mapping for the old connection"))?;
+            mapping.eids.insert(proxy_eid, (target_id, new_conn.eid));
+            let known_eids = &mut *self.known_eids.lock().await;
+            let stale_eids = known_eids
+                .iter()
+                .copied()
+                .filter(|eid| {
+                    *eid != proxy_eid
+                        && matches!(mapping.eids.get_by_left(eid), Some((id, _)) if *id == old_id)
+                })
+                .collect::<Vec<i32>>();
+            for eid in stale_eids.iter() {
+                known_eids.remove(eid);
+                mapping.free_eid(*eid);
+            }
+            stale_eids
+        };
+        if !stale_eids.is_empty() {
+            self.write_packet(LazyDeserializedPacket::from_packet(
+                PacketLatest::PlayDestroyEntities(PlayDestroyEntitiesSpec {
+                    entity_ids: stale_eids
+                        .into_iter()
+                        .map(VarInt::from)
+                        .collect::<Vec<VarInt>>()
+                        .into(),
+                }),
+            ))
+            .await
+            .with_context(|| format!("Failed to despawn stale entities for \"{}\"", &self.name))?;
+        }
+        // release the old connection's hold on the client's chunks so the new connection can
+        // send them again
+        let old_chunks = old_conn
+            .known_chunks
+            .lock()
+            .await
+            .iter()
+            .copied()
+            .collect::<Vec<(i32, i32)>>();
+        for chunk in old_chunks {
+            old_conn.remove_chunk(self, chunk).await;
+        }
+
+        let pos = &**self.position.load();
+        send_position_set(&mut *new_conn.writer.lock().await, pos.x, pos.y, pos.z)
+            .await
+            .with_context(|| format!("Failed to send position set to server {}", target_id))?;
+        self.sync_player_info(&[])
+            .await
+            .with_context(|| format!("Failed to send player info to \"{}\"", &self.name))?;
         Ok(())
     }
+    /// Makes the target server the active server, connecting to it first if needed
+    pub async fn switch_server(self: &Arc<SplinterClient>, target_id: u64) -> anyhow::Result<()> {
+        if self.server_id() == target_id {
+            return Ok(());
+        }
+        if !self
+            .dummy_servers
+            .load()
+            .iter()
+            .any(|(id, _)| *id == target_id)
+        {
+            self.connect_dummy(target_id).await?;
+        }
+        if !self.dummy_in_same_world(target_id).await {
+            // the dummy's chunks were never shown to the client, and changing worlds leaves the
+            // client with none. logging in again has the server send all of them
+            self.disconnect_dummy(target_id).await?;
+            self.reconnecting.store(true, Ordering::Relaxed);
+            let res = self.replace_active_connection(target_id).await;
+            self.reconnecting.store(false, Ordering::Relaxed);
+            return res;
+        }
+        self.swap_dummy(target_id).await
+    }
+    /// Whether the target dummy connection's player is in the same world as the active
+    /// connection's, so that the client can be moved to it without changing worlds. Unknown
+    /// worlds are taken to be the same
+    async fn dummy_in_same_world(&self, target_id: u64) -> bool {
+        let dummy = match self
+            .dummy_servers
+            .load()
+            .iter()
+            .find(|(id, _)| *id == target_id)
+        {
+            Some((_, dummy)) => Arc::clone(dummy),
+            None => return true,
+        };
+        let active_dimension = self.active_server.load().dimension.lock().await.clone();
+        let dummy_dimension = dummy.dimension.lock().await;
+        match (active_dimension, &*dummy_dimension) {
+            (Some(active), Some(dummy)) => active.same_world(dummy),
+            _ => true,
+        }
+    }
+    /// Closes this client's connection to the target server without kicking the client. If it is
+    /// the active connection, the client is moved to a live dummy connection, or reconnected to
+    /// the same server if there is none
+    pub async fn drop_connection(self: &Arc<SplinterClient>, target_id: u64) -> anyhow::Result<()> {
+        if self.server_id() != target_id {
+            return self.disconnect_dummy(target_id).await;
+        }
+        let fallback_id = self
+            .dummy_servers
+            .load()
+            .iter()
+            .find(|(_, conn)| conn.alive.load(Ordering::Relaxed))
+            .map(|(id, _)| *id);
+        match fallback_id {
+            Some(fallback_id) => {
+                self.swap_dummy(fallback_id).await?;
+                // the dropped connection is now a dummy
+                self.disconnect_dummy(target_id).await
+            }
+            None => self.reconnect().await,
+        }
+    }
     // if this fails, this probably isnt really recoverable without a lot of effort lol
     pub async fn update_touching_servers(
         self: &Arc<SplinterClient>,
@@ -395,13 +860,28 @@ pub fn handle(
     addr: SocketAddr,
     proxy: Arc<SplinterProxy>,
 ) -> anyhow::Result<()> {
-    let arc_stream = AsyncArc::new(stream);
-    let (reader, writer) = (
-        AsyncArc::clone(&arc_stream).compat(),
-        AsyncArc::clone(&arc_stream).compat(),
-    );
-    let conn = CraftConnection::from_async((reader, writer), PacketDirection::ServerBound);
     smol::spawn(async move {
+        // the real address may come before anything else on the stream
+        let addr = match proxyprotocol::resolve_client_address(&proxy, &stream, addr).await {
+            Ok(addr) => addr,
+            Err(e) => return error!("Refusing connection from {}: {:?}", addr, e),
+        };
+        if !proxy.ip_allowlist.allows(addr.ip()) {
+            return info!("Refusing connection from {}: not in the IP allowlist", addr);
+        }
+        if let Err(e) = stream
+            .get_ref()
+            .set_nodelay(proxy.config().flush_policy.nodelay())
+        {
+            warn!("Failed to set the flush policy for {}: {}", addr, e);
+        }
+        let arc_stream = AsyncArc::new(stream);
+        let (reader, writer) = (
+            AsyncArc::clone(&arc_stream).compat(),
+            AsyncArc::clone(&arc_stream).compat(),
+        );
+        let mut conn = CraftConnection::from_async((reader, writer), PacketDirection::ServerBound);
+        conn.set_max_packet_size(proxy.config().max_packet_size);
         // wait for initial handshake
         if let Err(e) = protocol::handle_handshake(conn, addr, proxy).await {
             error!("Failed to handle handshake: {:?}", e,);
@@ -1,12 +1,18 @@
 use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
     fs::{
         self,
         File,
     },
     io::Write,
+    net::SocketAddr,
     path::Path,
+    str::FromStr,
 };
 
+use anyhow::Context;
+
 use ron::ser::PrettyConfig;
 use serde::{
     Deserialize,
@@ -16,27 +22,815 @@ use serde::{
 use crate::{
     protocol::current::{
         status::{
+            StatusFaviconSpec,
             StatusPlayerSampleSpec,
             StatusPlayersSpec,
             StatusSpec,
             StatusVersionSpec,
         },
         types::Chat,
+        PacketLatestKind,
+    },
+    proxy::{
+        server::kind_by_name,
+        SplinterProxy,
     },
-    proxy::SplinterProxy,
 };
 
+pub const CONFIG_FILENAME: &str = "./config.ron";
+/// Chunks a client holds at the largest view distance, 32 chunks each way around its own
+pub const MAX_VIEW_DISTANCE_CHUNKS: usize = 65 * 65;
+
+/// Comments on the main fields of the example config written on first run
+const EXAMPLE_COMMENTS: &[(&str, &str)] = &[
+    ("protocol", "Protocol version of the simulation servers. 756 is 1.17.1"),
+    (
+        "simulation_servers",
+        "Simulation servers as (id, address). Addresses without a port are looked up through their SRV record",
+    ),
+    ("default_server", "Server that owns the parts of the world no zone covers"),
+    (
+        "proxy_address",
+        "Address and port the proxy listens for players on, ex. \"0.0.0.0:25565\"",
+    ),
+    ("max_players", "Player limit shown in the server list, or None to always show room for one more"),
+    ("motd", "Message shown under the proxy in the server list"),
+    ("compression_threshold", "Smallest packet size in bytes that is compressed, or None to not compress"),
+    ("player_commands", "Proxy commands that players may run from chat, ex. \"list\""),
+    ("kick_messages", "Messages shown to players when they are disconnected"),
+    ("limbo", "Holding of players whose server can't be reached when they log in"),
+    ("logging", "Log format (Plain or Json) and the most detailed level logged"),
+];
+
+/// What to do when a server sends a packet the proxy cannot handle
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PacketErrorAction {
+    /// Drop the packet and continue
+    Ignore,
+    /// Forward the packet's bytes to the client without parsing them
+    RawForward,
+    /// Close the connection to the server
+    DisconnectBackend,
+}
+
+/// Which of a client's servers it is shown the boss bars of
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BossBarVisibility {
+    /// Only the server the client is on
+    ActiveServer,
+    /// Every server the client is connected to
+    AllServers,
+}
+
+/// What becomes of the sounds and particles of servers other than the one a client is on, which
+/// happen around the player's position on that server and not necessarily where the player is
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BackgroundEffects {
+    /// None of them are shown to the client
+    Suppress,
+    /// All of them are shown to the client
+    Pass,
+    /// Only those within this many blocks of the player are shown to the client
+    WithinDistance(f64),
+}
+
+/// Actions to take for each kind of error a server's packets can cause during play
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackendPacketErrorPolicy {
+    /// Packet was framed correctly but its contents failed to deserialize
+    pub malformed: PacketErrorAction,
+    /// Packet could not be read at all, ex. an unknown packet id. There are no bytes to forward,
+    /// so this can only be `Ignore` or `DisconnectBackend`
+    pub unreadable: PacketErrorAction,
+}
+impl Default for BackendPacketErrorPolicy {
+    fn default() -> Self {
+        Self {
+            malformed: PacketErrorAction::RawForward,
+            unreadable: PacketErrorAction::Ignore,
+        }
+    }
+}
+
+/// Templates for the message a client is shown when kicked, by kick reason
+///
+/// `{server}` is replaced by the id of the server the client was on. `kicked` and
+/// `kicked_with_reason` also replace `{by}` with who kicked the client, and `kicked_with_reason`
+/// replaces `{reason}` with the given reason. Kicks the client can recover from by joining again
+/// (timing out, a restart, or their server going down) add `reconnect_hint` on a new line, unless
+/// it is empty
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KickMessages {
+    pub timed_out: String,
+    pub kicked: String,
+    pub kicked_with_reason: String,
+    pub shutdown: String,
+    pub restart: String,
+    pub maintenance: String,
+    pub server_down: String,
+    pub reconnect_hint: String,
+    /// Shown to banned players, where `{reason}` is the ban reason
+    pub banned: String,
+    /// Shown to players who aren't whitelisted while `whitelist_enabled` is set
+    pub not_whitelisted: String,
+    /// Shown to players kicked for sending packets too fast
+    pub rate_limited: String,
+}
+impl Default for KickMessages {
+    fn default() -> Self {
+        Self {
+            timed_out: "Timed out".into(),
+            kicked: "Kicked by {by}".into(),
+            kicked_with_reason: "Kicked by {by} because \"{reason}\"".into(),
+            shutdown: "Server shut down".into(),
+            restart: "Server is restarting".into(),
+            maintenance: "Server is down for maintenance".into(),
+            server_down: "Lost connection to server {server}".into(),
+            reconnect_hint: "You can reconnect in a moment".into(),
+            banned: "You are banned from this server: {reason}".into(),
+            not_whitelisted: "You are not whitelisted on this server".into(),
+            rate_limited: "You are sending packets too fast".into(),
+        }
+    }
+}
+
+/// An entry in the server selector menu
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerSelectorEntry {
+    pub server_id: u64,
+    pub name: String,
+    /// Item shown in the entry's slot, ex. `grass_block`
+    pub item: String,
+}
+
+/// Layout of the server selector menu. If there are no entries, each simulation server gets an
+/// entry
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSelectorConfig {
+    pub title: String,
+    pub entries: Vec<ServerSelectorEntry>,
+}
+impl Default for ServerSelectorConfig {
+    fn default() -> Self {
+        Self {
+            title: "Select a server".into(),
+            entries: vec![],
+        }
+    }
+}
+
+/// Where the online player count in the server status comes from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum StatusPlayerCount {
+    /// Number of clients connected to the proxy
+    ProxyClients,
+    /// Sum of the player counts each simulation server last reported
+    BackendTotal,
+}
+
+/// When packets written to a socket are sent. Each packet is handed to the socket whole as soon
+/// as it is written, so this decides whether the socket sends it right away or holds small
+/// writes back to send them together
+///
+/// The metrics endpoint reports the packets written to clients and the time spent writing them,
+/// to compare the policies under load such as chunk-load bursts
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FlushPolicy {
+    /// Send every packet immediately, for the lowest latency
+    PerPacket,
+    /// Let the socket coalesce small packets into fewer segments while earlier data is
+    /// unacknowledged, for higher throughput during bursts such as chunk loading
+    Coalesce,
+}
+impl FlushPolicy {
+    /// Gets whether sockets should have Nagle's algorithm disabled under this policy
+    pub fn nodelay(&self) -> bool {
+        matches!(self, FlushPolicy::PerPacket)
+    }
+}
+
+/// Header and footer shown above and below a client's tab list
+///
+/// `{online}` is replaced by the number of clients connected to the proxy, `{server}` by the id of
+/// the client's active server, and `{ping}` by the client's round trip time in milliseconds
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TabListText {
+    pub header: String,
+    pub footer: String,
+}
+
+/// Tab list header and footer, by server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TabListConfig {
+    /// Shown on servers without their own entry. Nothing is sent for those servers if `None`
+    pub global: Option<TabListText>,
+    pub servers: HashMap<u64, TabListText>,
+    /// Seconds between refreshes of every client's tab list
+    pub update_interval: u64,
+}
+impl Default for TabListConfig {
+    fn default() -> Self {
+        Self {
+            global: None,
+            servers: HashMap::new(),
+            update_interval: 5,
+        }
+    }
+}
+impl TabListConfig {
+    /// Gets the tab list text shown on the given server
+    pub fn text_for(&self, server_id: u64) -> Option<&TabListText> {
+        self.servers.get(&server_id).or_else(|| self.global.as_ref())
+    }
+}
+
+/// Where a message to a player is shown
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MessageChannel {
+    /// The message isn't sent
+    None,
+    Chat,
+    ActionBar,
+}
+
+/// Message sent to a player once they are routed to a server. `{server}` is replaced by the id
+/// of the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoutingMessage {
+    pub channel: MessageChannel,
+    pub text: String,
+}
+impl Default for RoutingMessage {
+    fn default() -> Self {
+        Self {
+            channel: MessageChannel::None,
+            text: "Now on server {server}".into(),
+        }
+    }
+}
+
+/// Routing messages, by server
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingMessageConfig {
+    /// Sent for servers without their own entry
+    pub global: RoutingMessage,
+    pub servers: HashMap<u64, RoutingMessage>,
+}
+impl RoutingMessageConfig {
+    /// Gets the routing message for the given server
+    pub fn message_for(&self, server_id: u64) -> &RoutingMessage {
+        self.servers.get(&server_id).unwrap_or(&self.global)
+    }
+}
+
+/// Maintenance mode refuses logins and kicks connected players, except for exempt players. The
+/// message they are shown is `KickMessages::maintenance`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Whether maintenance mode is on at startup. Once toggled by command, the last toggled state
+    /// is used instead
+    pub enabled: bool,
+    /// Shown in the server list in place of `motd` while in maintenance mode
+    pub motd: String,
+    /// Names of players who may stay and join during maintenance
+    pub exempt: Vec<String>,
+}
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            motd: "Down for maintenance".into(),
+            exempt: vec![],
+        }
+    }
+}
+impl MaintenanceConfig {
+    pub fn is_exempt(&self, name: &str) -> bool {
+        self.exempt.iter().any(|exempt| exempt == name)
+    }
+}
+
+/// What to do with a joining player whose saved position is in a zone of a server that isn't one
+/// of the simulation servers, ex. after the server was decommissioned
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MissingServerPolicy {
+    /// Send the player to the given server instead
+    Redirect(u64),
+    /// Refuse the login with the given message
+    Reject(String),
+}
+
+/// Where a joining player goes
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoinTarget {
+    /// The server of the player's zone exists, join it
+    Server(u64),
+    /// The server of the player's zone is missing, join another server
+    Redirect { missing: u64, to: u64 },
+    /// The server of the player's zone is missing, refuse the login with the given message
+    Reject(String),
+}
+
+impl MissingServerPolicy {
+    /// Picks where a player whose zone is on the server with the given id goes, given which
+    /// servers exist
+    pub fn join_target(
+        &self,
+        server_id: u64,
+        exists: impl Fn(u64) -> bool,
+    ) -> anyhow::Result<JoinTarget> {
+        if exists(server_id) {
+            return Ok(JoinTarget::Server(server_id));
+        }
+        match self {
+            MissingServerPolicy::Redirect(target_id) => {
+                if !exists(*target_id) {
+                    bail!(
+                        "Server {} to redirect to from missing server {} does not exist",
+                        target_id,
+                        server_id
+                    );
+                }
+                Ok(JoinTarget::Redirect {
+                    missing: server_id,
+                    to: *target_id,
+                })
+            }
+            MissingServerPolicy::Reject(message) => Ok(JoinTarget::Reject(message.clone())),
+        }
+    }
+}
+
+/// Handling of joining players whose server no longer exists
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MissingServerConfig {
+    pub policy: MissingServerPolicy,
+    /// Sent in chat to redirected players. `{missing}` is replaced by the id of the missing
+    /// server and `{server}` by the id of the server they were sent to
+    pub redirect_message: String,
+}
+impl Default for MissingServerConfig {
+    fn default() -> Self {
+        Self {
+            policy: MissingServerPolicy::Redirect(0),
+            redirect_message:
+                "Server {missing} is no longer available, so you were sent to server {server}"
+                    .into(),
+        }
+    }
+}
+
+/// Which chunks sent by a client's background (dummy) server connections are relayed to the
+/// client. Chunks near the player usually come from the active server as well
+///
+/// A chunk left out isn't sent again by its server, so a radius smaller than the servers' view
+/// distance can leave gaps the player sees after switching servers
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackgroundChunkConfig {
+    /// Relay no chunks from background servers at all
+    pub cull: bool,
+    /// Chunks further than this many chunks from the player, along either axis, aren't relayed.
+    /// `None` relays chunks at any distance
+    pub radius: Option<u32>,
+}
+
+/// Files to read the id-name maps from instead of the data bundled with the proxy, ex. to use
+/// newer data without rebuilding. Each file is a JSON array of objects with an `id` and a `name`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataPathsConfig {
+    pub blocks: Option<String>,
+    pub items: Option<String>,
+    pub entities: Option<String>,
+    pub fluids: Option<String>,
+    pub game_events: Option<String>,
+    pub biomes: Option<String>,
+}
+
+/// Token bucket limit on the packets of a kind a client may send
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Packets allowed per second, on average
+    pub per_second: f64,
+    /// Packets that may be sent at once after a quiet period
+    pub burst: f64,
+}
+
+/// Packet kinds clients send during play, which can be rate limited, by name
+pub const RATE_LIMITABLE_KINDS: &[(&str, PacketLatestKind)] = &[
+    ("PlayTeleportConfirm", PacketLatestKind::PlayTeleportConfirm),
+    ("PlayQueryBlockNbt", PacketLatestKind::PlayQueryBlockNbt),
+    ("PlaySetDifficulty", PacketLatestKind::PlaySetDifficulty),
+    (
+        "PlayClientChatMessage",
+        PacketLatestKind::PlayClientChatMessage,
+    ),
+    ("PlayClientStatus", PacketLatestKind::PlayClientStatus),
+    ("PlayClientSettings", PacketLatestKind::PlayClientSettings),
+    (
+        "PlayClientTabComplete",
+        PacketLatestKind::PlayClientTabComplete,
+    ),
+    (
+        "PlayClickWindowButton",
+        PacketLatestKind::PlayClickWindowButton,
+    ),
+    ("PlayClickWindow", PacketLatestKind::PlayClickWindow),
+    (
+        "PlayClientCloseWindow",
+        PacketLatestKind::PlayClientCloseWindow,
+    ),
+    (
+        "PlayClientPluginMessage",
+        PacketLatestKind::PlayClientPluginMessage,
+    ),
+    ("PlayEditBook", PacketLatestKind::PlayEditBook),
+    ("PlayQueryEntityNbt", PacketLatestKind::PlayQueryEntityNbt),
+    ("PlayInteractEntity", PacketLatestKind::PlayInteractEntity),
+    (
+        "PlayGenerateStructure",
+        PacketLatestKind::PlayGenerateStructure,
+    ),
+    ("PlayClientKeepAlive", PacketLatestKind::PlayClientKeepAlive),
+    ("PlayLockDifficulty", PacketLatestKind::PlayLockDifficulty),
+    (
+        "PlayClientPlayerPosition",
+        PacketLatestKind::PlayClientPlayerPosition,
+    ),
+    (
+        "PlayClientPlayerPositionAndRotation",
+        PacketLatestKind::PlayClientPlayerPositionAndRotation,
+    ),
+    (
+        "PlayClientPlayerRotation",
+        PacketLatestKind::PlayClientPlayerRotation,
+    ),
+    (
+        "PlayClientPlayerMovement",
+        PacketLatestKind::PlayClientPlayerMovement,
+    ),
+    (
+        "PlayClientVehicleMove",
+        PacketLatestKind::PlayClientVehicleMove,
+    ),
+    ("PlayClientSteerBoat", PacketLatestKind::PlayClientSteerBoat),
+    ("PlayPickItem", PacketLatestKind::PlayPickItem),
+    (
+        "PlayCraftRecipeRequest",
+        PacketLatestKind::PlayCraftRecipeRequest,
+    ),
+    (
+        "PlayClientPlayerAbilities",
+        PacketLatestKind::PlayClientPlayerAbilities,
+    ),
+    ("PlayPlayerDigging", PacketLatestKind::PlayPlayerDigging),
+    ("PlayEntityAction", PacketLatestKind::PlayEntityAction),
+    ("PlaySteerVehicle", PacketLatestKind::PlaySteerVehicle),
+    ("PlayPong", PacketLatestKind::PlayPong),
+    (
+        "PlaySetDisplayedRecipe",
+        PacketLatestKind::PlaySetDisplayedRecipe,
+    ),
+    (
+        "PlaySetRecipeBookState",
+        PacketLatestKind::PlaySetRecipeBookState,
+    ),
+    ("PlayNameItem", PacketLatestKind::PlayNameItem),
+    (
+        "PlayResourcePackStatus",
+        PacketLatestKind::PlayResourcePackStatus,
+    ),
+    ("PlayAdvancementTab", PacketLatestKind::PlayAdvancementTab),
+    ("PlaySelectTrade", PacketLatestKind::PlaySelectTrade),
+    ("PlaySetBeaconEffect", PacketLatestKind::PlaySetBeaconEffect),
+    (
+        "PlayClientHeldItemChange",
+        PacketLatestKind::PlayClientHeldItemChange,
+    ),
+    (
+        "PlayUpdateCommandBlock",
+        PacketLatestKind::PlayUpdateCommandBlock,
+    ),
+    (
+        "PlayUpdateCommandBlockMinecart",
+        PacketLatestKind::PlayUpdateCommandBlockMinecart,
+    ),
+    (
+        "PlayCreativeInventoryAction",
+        PacketLatestKind::PlayCreativeInventoryAction,
+    ),
+    (
+        "PlayUpdateJigsawBlock",
+        PacketLatestKind::PlayUpdateJigsawBlock,
+    ),
+    (
+        "PlayUpdateStructureBlock",
+        PacketLatestKind::PlayUpdateStructureBlock,
+    ),
+    ("PlayUpdateSign", PacketLatestKind::PlayUpdateSign),
+    ("PlayAnimation", PacketLatestKind::PlayAnimation),
+    ("PlaySpectate", PacketLatestKind::PlaySpectate),
+    ("PlayBlockPlacement", PacketLatestKind::PlayBlockPlacement),
+    ("PlayUseItem", PacketLatestKind::PlayUseItem),
+];
+
+/// Limits on how fast clients may send packets. Packets over a limit are dropped
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Limit of the packet kinds not listed in `kinds`, or `None` to not limit them
+    pub default: Option<RateLimit>,
+    /// Limits by packet kind, ex. `"PlayClientChatMessage"`. Kinds are named as in
+    /// `RATE_LIMITABLE_KINDS`
+    pub kinds: HashMap<String, RateLimit>,
+    /// Kicks clients that have this many packets dropped within ten seconds, or `None` to never
+    /// kick
+    pub kick_after: Option<u32>,
+}
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            default: None,
+            kinds: vec![
+                (
+                    "PlayClientChatMessage".to_owned(),
+                    RateLimit {
+                        per_second: 2.,
+                        burst: 10.,
+                    },
+                ),
+                (
+                    "PlayAnimation".to_owned(),
+                    RateLimit {
+                        per_second: 20.,
+                        burst: 40.,
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            kick_after: None,
+        }
+    }
+}
+impl RateLimitConfig {
+    /// Gets the limit on packets of the given kind
+    pub fn limit_for(&self, kind: PacketLatestKind) -> Option<RateLimit> {
+        self.kinds
+            .get(&format!("{:?}", kind))
+            .or_else(|| self.default.as_ref())
+            .copied()
+    }
+}
+
+/// Handling of players whose active server connection closes unexpectedly
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// Times to try logging in to the same server again before giving up on it
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry. Each later retry waits twice as long as the
+    /// one before it
+    pub retry_delay: u64,
+    /// Server to move players to once their server can't be reached. Players are disconnected if
+    /// `None`
+    pub fallback_server: Option<u64>,
+    /// Sent in chat to players moved to the fallback server. `{failed}` is replaced by the id of
+    /// the server that went down and `{server}` by the id of the fallback server
+    pub fallback_message: String,
+}
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            retry_delay: 500,
+            fallback_server: None,
+            fallback_message: "Server {failed} went down, so you were moved to server {server}"
+                .into(),
+        }
+    }
+}
+
+/// Remote console listener, which runs proxy commands sent over the RCON protocol
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RconConfig {
+    pub enabled: bool,
+    pub address: String,
+    /// Password remote console clients log in with. The listener is not started if this is empty
+    pub password: String,
+}
+impl Default for RconConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:25575".into(),
+            password: String::new(),
+        }
+    }
+}
+
+/// Holding of players whose server can't be reached when they log in
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimboConfig {
+    /// Whether players are kept connected in an empty world until their server can be reached,
+    /// rather than failing their login
+    pub enabled: bool,
+    /// Sent in chat to players put in limbo. `{server}` is replaced by the id of the server they
+    /// are waiting for
+    pub message: String,
+    /// Seconds between attempts to connect a player in limbo to its server
+    pub retry_interval: u64,
+}
+impl Default for LimboConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            message: "Server {server} is unavailable. You will be connected once it is back".into(),
+            retry_interval: 5,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// Human readable lines, with the player and server they are about in front of the message
+    Plain,
+    /// One JSON object per line, with the player and server they are about as fields
+    Json,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Most detailed level logged: `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+}
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Plain,
+            level: "debug".into(),
+        }
+    }
+}
+
+/// Server list icon, configured as a base64 encoded 64x64 PNG and decoded when the config is
+/// loaded. A `data:image/png;base64,` prefix is allowed, so the icon can be copied from a vanilla
+/// server's status response
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Favicon {
+    encoded: String,
+    pub png: Vec<u8>,
+}
+impl TryFrom<String> for Favicon {
+    type Error = String;
+    fn try_from(encoded: String) -> Result<Self, Self::Error> {
+        let data = encoded.trim();
+        let data = data
+            .strip_prefix("data:image/png;base64,")
+            .unwrap_or(data)
+            .split_ascii_whitespace()
+            .collect::<String>();
+        let png =
+            base64::decode(data).map_err(|e| format!("favicon is not valid base64: {}", e))?;
+        Ok(Self { encoded, png })
+    }
+}
+impl From<Favicon> for String {
+    fn from(favicon: Favicon) -> Self {
+        favicon.encoded
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SplinterConfig {
     pub protocol: i32,
     pub display_version: Option<String>,
     pub simulation_servers: Vec<(u64, String)>,
+    /// Server that owns the parts of the world no zone covers
+    pub default_server: u64,
+    /// Flush policy for sockets to clients and to simulation servers
+    pub flush_policy: FlushPolicy,
+    /// Whether the `connect` command may connect players to servers that aren't in
+    /// `simulation_servers`. Meant for testing a new server; leave off in production
+    pub allow_adhoc_servers: bool,
+    /// Number of sockets kept connected ahead of time to each simulation server, by server id,
+    /// to speed up server switches. Servers not listed have no pool
+    pub server_pool_sizes: HashMap<u64, usize>,
+    /// Protocol versions each simulation server accepts, by server id. Servers not listed accept
+    /// every version
+    pub server_allowed_versions: HashMap<u64, Vec<i32>>,
+    /// Paths to JSON files listing the entity type ids of simulation servers whose ids differ
+    /// from the proxy's, by server id. The files are in the format of minecraft-data's
+    /// `entities.json`: an array of objects with an `id` and a `name`
+    pub server_entity_types: HashMap<u64, String>,
     pub proxy_address: String,
+    /// Addresses to listen on in addition to `proxy_address`, ex. an IPv6 address or other ports.
+    /// IPv6 addresses only take IPv6 connections, so `"[::]:25565"` can be listened on alongside
+    /// `"0.0.0.0:25565"` for both stacks
+    pub additional_proxy_addresses: Vec<String>,
     pub max_players: Option<i32>,
     pub motd: String,
+    /// Smallest packet size, in bytes, compressed on the link to clients. `None` or a negative
+    /// value leaves the link uncompressed
     pub compression_threshold: Option<i32>,
     pub improper_version_disconnect_message: String,
     pub brand: String,
+    pub backend_packet_errors: BackendPacketErrorPolicy,
+    pub status_player_count: StatusPlayerCount,
+    /// Most player names listed in the server list's player sample
+    pub status_sample_size: usize,
+    /// Server list icon, as a base64 encoded 64x64 PNG
+    pub favicon: Option<Favicon>,
+    /// Seconds between keep alives sent to each client
+    pub keep_alive_interval: u64,
+    /// Seconds a client may go without answering a keep alive before it is kicked
+    pub keep_alive_timeout: u64,
+    /// Seconds between status queries to each simulation server
+    pub backend_status_interval: u64,
+    /// Seconds a dummy server connection may go without any traffic before it is closed
+    pub idle_connection_timeout: u64,
+    /// Most packets a client may send during status and login before it is disconnected. A
+    /// normal client sends only a handful
+    pub max_pre_play_packets: usize,
+    /// Largest packet in bytes read from a client or server. A longer length prefix closes the
+    /// connection instead of the packet being read into memory
+    pub max_packet_size: usize,
+    /// Most packets in a row that may fail to be read or relayed before the connection is
+    /// closed. Reading is retried with a growing delay until then
+    pub max_consecutive_packet_errors: u32,
+    pub rate_limits: RateLimitConfig,
+    pub data_paths: DataPathsConfig,
+    /// Names of proxy commands that players may run from chat. These are also added to the
+    /// client's command completion
+    pub player_commands: Vec<String>,
+    /// Seconds a player must wait between uses of a command, by command name
+    pub command_cooldowns: HashMap<String, u64>,
+    /// Words masked with asterisks in chat messages players send, matched regardless of case
+    pub chat_filter: Vec<String>,
+    /// Text put in front of chat messages players send to everyone, where `{server}` is the
+    /// sender's server id, ex. `"[{server}] "`
+    pub chat_prefix: Option<String>,
+    /// Whether only players in `whitelist.json` may join
+    pub whitelist_enabled: bool,
+    /// CIDR ranges that clients may connect from. Any address may connect if this is empty
+    pub ip_allowlist: Vec<String>,
+    /// Whether connections from load balancers start with a PROXY protocol v2 header giving the
+    /// client's real address
+    pub proxy_protocol: bool,
+    /// CIDR ranges of the load balancers whose PROXY protocol headers are trusted. Must not be
+    /// empty while `proxy_protocol` is set
+    pub proxy_protocol_trusted: Vec<String>,
+    /// Whether connections to servers start with a PROXY protocol v2 header giving the client's
+    /// real address, for servers that expect one
+    pub send_proxy_protocol: bool,
+    /// Seconds after startup at which the proxy shuts itself down so that a supervisor can
+    /// restart it. Never restarts if `None`
+    pub restart_interval: Option<u64>,
+    pub kick_messages: KickMessages,
+    /// Most chunks tracked per client. The least recently used chunks are forgotten beyond this,
+    /// so less than `MAX_VIEW_DISTANCE_CHUNKS` forgets chunks the client still has loaded
+    pub max_tracked_chunks: usize,
+    pub background_chunks: BackgroundChunkConfig,
+    pub boss_bars: BossBarVisibility,
+    pub background_effects: BackgroundEffects,
+    /// Most entities mapped per client. Entities spawned beyond this aren't shown to the client
+    pub max_mapped_entities: usize,
+    /// Kinds of packets whose latest instance from each server is replayed to the client when
+    /// it switches to that server, ex. `PlayUpdateHealth`
+    pub replayed_packets: Vec<String>,
+    pub server_selector: ServerSelectorConfig,
+    pub tab_list: TabListConfig,
+    pub routing_messages: RoutingMessageConfig,
+    pub maintenance: MaintenanceConfig,
+    pub missing_server: MissingServerConfig,
+    pub failover: FailoverConfig,
+    pub rcon: RconConfig,
+    pub limbo: LimboConfig,
+    pub logging: LoggingConfig,
+    /// Blocks a player must be past the edge of their server's zones before zoning moves them to
+    /// another server, so that walking along a border doesn't switch back and forth
+    pub zone_switch_margin: f64,
+    /// Fewest milliseconds a player stays on a server after zoning moves them to it
+    pub zone_switch_cooldown: u64,
+    /// Address to serve Prometheus metrics on, at `/metrics`. Not served if `None`
+    pub metrics_address: Option<String>,
+    /// Whether the player data file is saved gzipped. Either kind is read back regardless
+    pub compress_player_data: bool,
 }
 impl Default for SplinterConfig {
     fn default() -> Self {
@@ -44,24 +838,248 @@ impl Default for SplinterConfig {
             protocol: 756,
             display_version: Some("Splinter 1.17.1".into()),
             simulation_servers: vec![(0, "127.0.0.1:25400".into())],
+            default_server: 0,
+            flush_policy: FlushPolicy::PerPacket,
+            allow_adhoc_servers: false,
+            server_pool_sizes: HashMap::new(),
+            server_allowed_versions: HashMap::new(),
+            server_entity_types: HashMap::new(),
             proxy_address: "127.0.0.1:25565".into(),
+            additional_proxy_addresses: vec![],
             max_players: None,
             motd: "Splinter Proxy".into(),
             compression_threshold: Some(256),
             improper_version_disconnect_message: "Your client version is not supported".into(),
             brand: "Splinter".into(),
+            backend_packet_errors: BackendPacketErrorPolicy::default(),
+            status_player_count: StatusPlayerCount::ProxyClients,
+            status_sample_size: 12,
+            favicon: None,
+            keep_alive_interval: 15,
+            keep_alive_timeout: 30,
+            backend_status_interval: 10,
+            idle_connection_timeout: 60,
+            max_pre_play_packets: 32,
+            max_packet_size: 8 * 1024 * 1024,
+            max_consecutive_packet_errors: 8,
+            rate_limits: RateLimitConfig::default(),
+            data_paths: DataPathsConfig::default(),
+            player_commands: vec![],
+            command_cooldowns: HashMap::new(),
+            chat_filter: vec![],
+            chat_prefix: None,
+            whitelist_enabled: false,
+            ip_allowlist: vec![],
+            proxy_protocol: false,
+            proxy_protocol_trusted: vec![],
+            send_proxy_protocol: false,
+            restart_interval: None,
+            kick_messages: KickMessages::default(),
+            max_tracked_chunks: MAX_VIEW_DISTANCE_CHUNKS,
+            background_chunks: BackgroundChunkConfig::default(),
+            boss_bars: BossBarVisibility::ActiveServer,
+            background_effects: BackgroundEffects::Suppress,
+            max_mapped_entities: 32768,
+            replayed_packets: vec![
+                "PlaySetExperience".into(),
+                "PlayUpdateHealth".into(),
+                "PlayServerHeldItemChange".into(),
+                "PlayServerDifficulty".into(),
+                "PlayServerPlayerAbilities".into(),
+            ],
+            server_selector: ServerSelectorConfig::default(),
+            tab_list: TabListConfig::default(),
+            routing_messages: RoutingMessageConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            missing_server: MissingServerConfig::default(),
+            failover: FailoverConfig::default(),
+            rcon: RconConfig::default(),
+            limbo: LimboConfig::default(),
+            logging: LoggingConfig::default(),
+            zone_switch_margin: 4.,
+            zone_switch_cooldown: 2000,
+            metrics_address: None,
+            compress_player_data: false,
         }
     }
 }
 
 impl SplinterConfig {
-    /// Attempts to read splinter config from a string
+    /// Attempts to read and validate splinter config from a string
     pub fn from_str(data: impl AsRef<str>) -> anyhow::Result<SplinterConfig> {
-        ron::de::from_str(data.as_ref()).map_err(anyhow::Error::new)
+        let config: SplinterConfig =
+            ron::de::from_str(data.as_ref()).map_err(|e| anyhow!("Invalid config at {}", e))?;
+        config.validate()?;
+        Ok(config)
     }
-    /// Attempts to read splinter config from a file
+    /// Attempts to read and validate splinter config from a file
     pub fn from_file(filepath: impl AsRef<Path>) -> anyhow::Result<SplinterConfig> {
-        Self::from_str(fs::read_to_string(filepath)?)
+        let filepath = filepath.as_ref();
+        let data = fs::read_to_string(filepath)
+            .with_context(|| format!("Failed to read config from \"{}\"", filepath.display()))?;
+        Self::from_str(data)
+            .with_context(|| format!("Failed to load config from \"{}\"", filepath.display()))
+    }
+    /// Reads splinter config from a file. If there is no file, the default config is written to
+    /// it as a commented example and used
+    pub fn load(filepath: impl AsRef<Path>) -> anyhow::Result<SplinterConfig> {
+        let filepath = filepath.as_ref();
+        if filepath.exists() {
+            return Self::from_file(filepath);
+        }
+        let config = Self::default();
+        let example = config.to_example_string()?;
+        File::create(filepath)
+            .and_then(|mut file| file.write_all(example.as_bytes()))
+            .with_context(|| {
+                format!(
+                    "Failed to write example config to \"{}\"",
+                    filepath.display()
+                )
+            })?;
+        Ok(config)
+    }
+    /// Checks the values that the proxy can't run without, naming the first offending field
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let proxy_addresses = std::iter::once(("proxy_address".to_owned(), &self.proxy_address))
+            .chain(
+                self.additional_proxy_addresses
+                    .iter()
+                    .enumerate()
+                    .map(|(i, address)| (format!("additional_proxy_addresses[{}]", i), address)),
+            );
+        for (key, address) in proxy_addresses {
+            if SocketAddr::from_str(address).is_err() {
+                bail!(
+                    "`{}` \"{}\" is not an address and port, ex. \"0.0.0.0:25565\"",
+                    key,
+                    address
+                );
+            }
+        }
+        if log::LevelFilter::from_str(&self.logging.level).is_err() {
+            bail!(
+                "`logging.level` \"{}\" is not one of off, error, warn, info, debug or trace",
+                &self.logging.level
+            );
+        }
+        if self.proxy_protocol && self.proxy_protocol_trusted.is_empty() {
+            bail!(
+                "`proxy_protocol_trusted` must list the load balancers' addresses while \
+                 `proxy_protocol` is set, or any client could claim any address"
+            );
+        }
+        if self.max_packet_size == 0 {
+            bail!("`max_packet_size` must be at least 1");
+        }
+        if self.max_consecutive_packet_errors == 0 {
+            bail!("`max_consecutive_packet_errors` must be at least 1");
+        }
+        if self.backend_packet_errors.unreadable == PacketErrorAction::RawForward {
+            bail!(
+                "`backend_packet_errors.unreadable` can't be RawForward, as an unreadable packet \
+                 has no bytes to forward; use Ignore or DisconnectBackend"
+            );
+        }
+        if let BackgroundEffects::WithinDistance(distance) = self.background_effects {
+            if !(distance >= 0.) {
+                bail!("`background_effects` distance must be at least 0");
+            }
+        }
+        let rate_limits = self
+            .rate_limits
+            .default
+            .iter()
+            .map(|limit| ("rate_limits.default".to_owned(), limit))
+            .chain(
+                self.rate_limits
+                    .kinds
+                    .iter()
+                    .map(|(kind, limit)| (format!("rate_limits.kinds[\"{}\"]", kind), limit)),
+            );
+        for (key, limit) in rate_limits {
+            if !(limit.per_second > 0.) || !(limit.burst >= 1.) {
+                bail!(
+                    "`{}` must allow more than 0 packets per second and a burst of at least 1",
+                    key
+                );
+            }
+        }
+        for kind in self.rate_limits.kinds.keys() {
+            if kind_by_name(RATE_LIMITABLE_KINDS, kind).is_none() {
+                bail!(
+                    "`rate_limits.kinds` has \"{}\", which is not a packet kind clients send",
+                    kind
+                );
+            }
+        }
+        if self.simulation_servers.is_empty() {
+            bail!("`simulation_servers` must list at least one server");
+        }
+        let mut server_ids = HashSet::new();
+        for (i, (id, address)) in self.simulation_servers.iter().enumerate() {
+            if !server_ids.insert(*id) {
+                bail!("`simulation_servers[{}]` reuses server id {}", i, id);
+            }
+            validate_server_address(address)
+                .with_context(|| format!("`simulation_servers[{}]` has an invalid address", i))?;
+        }
+        let messages = [
+            ("motd", &self.motd),
+            (
+                "improper_version_disconnect_message",
+                &self.improper_version_disconnect_message,
+            ),
+            ("brand", &self.brand),
+            ("kick_messages.timed_out", &self.kick_messages.timed_out),
+            ("kick_messages.kicked", &self.kick_messages.kicked),
+            (
+                "kick_messages.kicked_with_reason",
+                &self.kick_messages.kicked_with_reason,
+            ),
+            ("kick_messages.shutdown", &self.kick_messages.shutdown),
+            ("kick_messages.restart", &self.kick_messages.restart),
+            ("kick_messages.maintenance", &self.kick_messages.maintenance),
+            ("kick_messages.server_down", &self.kick_messages.server_down),
+            ("kick_messages.banned", &self.kick_messages.banned),
+            (
+                "kick_messages.not_whitelisted",
+                &self.kick_messages.not_whitelisted,
+            ),
+            (
+                "kick_messages.rate_limited",
+                &self.kick_messages.rate_limited,
+            ),
+        ];
+        for (key, message) in messages.iter() {
+            if message.trim().is_empty() {
+                bail!("`{}` must not be empty", key);
+            }
+        }
+        Ok(())
+    }
+    /// Converts this splinter config to a string with comments on its main fields
+    pub fn to_example_string(&self) -> anyhow::Result<String> {
+        let mut out =
+            String::from("// Splinter proxy config. Fields left out take their default values\n");
+        for line in self.to_string()?.lines() {
+            // top level fields are the lines indented exactly once
+            let key = line
+                .strip_prefix("    ")
+                .filter(|rest| !rest.starts_with(' '))
+                .and_then(|rest| rest.split(':').next());
+            if let Some((_, comment)) = EXAMPLE_COMMENTS
+                .iter()
+                .find(|(field, _)| Some(*field) == key)
+            {
+                out.push_str("    // ");
+                out.push_str(comment);
+                out.push('\n');
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        Ok(out)
     }
     /// Attempts to convert this splinter config to a string
     pub fn to_string(&self) -> anyhow::Result<String> {
@@ -73,10 +1091,34 @@ impl SplinterConfig {
             .write_all(self.to_string()?.as_bytes())
             .map_err(anyhow::Error::new)
     }
+    /// Whether the server accepts clients with the given protocol version
+    pub fn server_allows_version(&self, server_id: u64, protocol_version: i32) -> bool {
+        self.server_allowed_versions
+            .get(&server_id)
+            .map_or(true, |versions| versions.contains(&protocol_version))
+    }
+    /// Gets the lowest id among the given servers that accepts clients with the given protocol
+    /// version
+    pub fn compatible_server(
+        &self,
+        server_ids: impl IntoIterator<Item = u64>,
+        protocol_version: i32,
+    ) -> Option<u64> {
+        server_ids
+            .into_iter()
+            .filter(|id| self.server_allows_version(*id, protocol_version))
+            .min()
+    }
     /// Gets the server status given the config and the proxy
     pub fn server_status(&self, proxy: &SplinterProxy) -> StatusSpec {
         let players = smol::block_on(proxy.players.read());
-        let total_players = players.len();
+        let total_players = match self.status_player_count {
+            StatusPlayerCount::ProxyClients => players.len(),
+            StatusPlayerCount::BackendTotal => smol::block_on(proxy.backend_player_counts.lock())
+                .values()
+                .map(|count| *count as usize)
+                .sum(),
+        };
         StatusSpec {
             version: self.display_version.as_ref().map(|name| StatusVersionSpec {
                 name: name.clone(),
@@ -87,14 +1129,149 @@ impl SplinterConfig {
                 online: total_players as i32,
                 sample: players
                     .iter()
+                    .take(self.status_sample_size)
                     .map(|(name, client)| StatusPlayerSampleSpec {
                         name: name.clone(),
                         id: client.uuid,
                     })
                     .collect::<Vec<StatusPlayerSampleSpec>>(),
             },
-            description: Chat::from_text(self.motd.as_str()),
-            favicon: None,
+            description: Chat::from_text(if proxy.in_maintenance() {
+                self.maintenance.motd.as_str()
+            } else {
+                self.motd.as_str()
+            }),
+            favicon: self.favicon.as_ref().map(|favicon| StatusFaviconSpec {
+                content_type: "image/png".into(),
+                data: favicon.png.clone(),
+            }),
+        }
+    }
+}
+
+/// Checks a simulation server address in the forms `resolve_server_address` takes
+fn validate_server_address(address: &str) -> anyhow::Result<()> {
+    if SocketAddr::from_str(address).is_ok() {
+        return Ok(());
+    }
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            port.parse::<u16>()
+                .map_err(|_| anyhow!("\"{}\" has an invalid port \"{}\"", address, port))?;
+            host
         }
+        None => address,
+    };
+    if host.trim().is_empty() {
+        bail!("\"{}\" has no host", address);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exists(id: u64) -> bool {
+        id == 0 || id == 1
+    }
+
+    #[test]
+    fn joins_existing_zone_server() {
+        let policy = MissingServerPolicy::Reject("gone".into());
+        assert_eq!(
+            policy.join_target(1, exists).unwrap(),
+            JoinTarget::Server(1)
+        );
+    }
+
+    #[test]
+    fn redirects_from_missing_server() {
+        let policy = MissingServerPolicy::Redirect(0);
+        assert_eq!(
+            policy.join_target(5, exists).unwrap(),
+            JoinTarget::Redirect { missing: 5, to: 0 }
+        );
+    }
+
+    #[test]
+    fn rejects_missing_server() {
+        let policy = MissingServerPolicy::Reject("gone".into());
+        assert_eq!(
+            policy.join_target(5, exists).unwrap(),
+            JoinTarget::Reject("gone".into())
+        );
+    }
+
+    #[test]
+    fn fails_redirect_to_missing_server() {
+        let policy = MissingServerPolicy::Redirect(7);
+        assert!(policy.join_target(5, exists).is_err());
+    }
+
+    fn version_config() -> SplinterConfig {
+        let mut config = SplinterConfig::default();
+        config.server_allowed_versions.insert(0, vec![754]);
+        config.server_allowed_versions.insert(1, vec![754, 756]);
+        config
+    }
+
+    #[test]
+    fn unlisted_servers_allow_every_version() {
+        let config = version_config();
+        assert!(config.server_allows_version(2, 756));
+        assert!(config.server_allows_version(2, 1));
+    }
+
+    #[test]
+    fn incompatible_version_goes_to_lowest_compatible_server() {
+        let config = version_config();
+        assert!(!config.server_allows_version(0, 756));
+        assert_eq!(config.compatible_server(vec![2, 0, 1], 756), Some(1));
+        assert_eq!(config.compatible_server(vec![1, 0], 754), Some(0));
+    }
+
+    #[test]
+    fn incompatible_version_without_compatible_server() {
+        let config = version_config();
+        assert_eq!(config.compatible_server(vec![0, 1], 755), None);
+    }
+
+    #[test]
+    fn decodes_favicon_with_data_prefix() {
+        let favicon = Favicon::try_from("data:image/png;base64,iVBO\n Rw0K".to_owned()).unwrap();
+        assert_eq!(favicon.png, b"\x89PNG\r\n");
+    }
+
+    #[test]
+    fn rejects_invalid_favicon() {
+        assert!(Favicon::try_from("not base64!".to_owned()).is_err());
+        assert!(SplinterConfig::from_str("(favicon: Some(\"not base64!\"))").is_err());
+    }
+
+    #[test]
+    fn accepts_known_rate_limited_kinds() {
+        assert!(SplinterConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_rate_limited_kinds() {
+        let mut config = SplinterConfig::default();
+        // a clientbound kind, which clients never send
+        config.rate_limits.kinds.insert(
+            "PlayServerChatMessage".to_owned(),
+            RateLimit {
+                per_second: 1.,
+                burst: 1.,
+            },
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_forwarding_unreadable_packets() {
+        let mut config = SplinterConfig::default();
+        config.backend_packet_errors.unreadable = PacketErrorAction::RawForward;
+        assert!(config.validate().is_err());
     }
 }
@@ -1,10 +1,17 @@
 use std::{
+    cell::RefCell,
     fs::{
         self,
         metadata,
         File,
     },
+    future::Future,
+    io::Write,
     path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{self, Poll},
 };
 
 use anyhow::Context;
@@ -12,6 +19,8 @@ use chrono::{
     DateTime,
     Local,
 };
+use log::{Log, Metadata, Record};
+use mcproto_rs::uuid::UUID4;
 use simplelog::{
     ColorChoice,
     CombinedLogger,
@@ -22,6 +31,8 @@ use simplelog::{
     WriteLogger,
 };
 
+use crate::proxy::config::{LogFormat, LoggingConfig};
+
 pub const LATEST_LOG_FILENAME: &str = "./latest.log";
 
 pub fn push_back_latest_log() -> anyhow::Result<()> {
@@ -48,24 +59,179 @@ pub fn push_back_latest_log() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn init() -> anyhow::Result<()> {
+pub fn init(config: &LoggingConfig) -> anyhow::Result<()> {
     if Path::new(LATEST_LOG_FILENAME).is_file() {
         push_back_latest_log()
             .with_context(|| format!("Trying to move {} into logs folder", LATEST_LOG_FILENAME))?;
     }
-    let config = ConfigBuilder::default().set_time_to_local(true).build();
-    CombinedLogger::init(vec![
-        TermLogger::new(
-            LevelFilter::Debug,
-            config.clone(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        ),
-        WriteLogger::new(
-            LevelFilter::Debug, /* setting to trace will result in a lot from the async libraries used in this project */
-            config,
-            File::create(LATEST_LOG_FILENAME).unwrap(),
-        ),
-    ])?;
+    // setting to trace will result in a lot from the async libraries used in this project
+    let level = LevelFilter::from_str(&config.level)
+        .map_err(|_| anyhow!("Unknown log level \"{}\"", &config.level))?;
+    let file = File::create(LATEST_LOG_FILENAME)
+        .with_context(|| format!("Creating {}", LATEST_LOG_FILENAME))?;
+    let output = match config.format {
+        LogFormat::Plain => {
+            let config = ConfigBuilder::default().set_time_to_local(true).build();
+            LogOutput::Plain(CombinedLogger::new(vec![
+                TermLogger::new(
+                    level,
+                    config.clone(),
+                    TerminalMode::Mixed,
+                    ColorChoice::Auto,
+                ),
+                WriteLogger::new(level, config, file),
+            ]))
+        }
+        LogFormat::Json => LogOutput::Json(Mutex::new(file)),
+    };
+    log::set_boxed_logger(Box::new(SpanLogger { level, output }))?;
+    log::set_max_level(level);
     Ok(())
 }
+
+/// Context put on every log line written while it is entered, ex. the player a relay loop is
+/// for. Spans are entered by polling a future wrapped with `Instrument::instrument`
+#[derive(Clone)]
+pub struct LogSpan(Arc<SpanData>);
+
+struct SpanData {
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl LogSpan {
+    pub fn new(name: &'static str, fields: Vec<(&'static str, String)>) -> Self {
+        Self(Arc::new(SpanData { name, fields }))
+    }
+    pub fn client(name: &str, uuid: UUID4) -> Self {
+        Self::new(
+            "client",
+            vec![("player", name.to_owned()), ("uuid", uuid.to_string())],
+        )
+    }
+    pub fn server(server_id: u64) -> Self {
+        Self::new("server", vec![("id", server_id.to_string())])
+    }
+}
+
+thread_local! {
+    /// Spans entered by the futures being polled on this thread, outermost first
+    static ENTERED_SPANS: RefCell<Vec<LogSpan>> = RefCell::new(Vec::new());
+}
+
+/// Pops the span pushed by `Instrumented::poll`, even if the inner future panics
+struct SpanGuard;
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        ENTERED_SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+pub struct Instrumented<F> {
+    inner: Pin<Box<F>>,
+    span: LogSpan,
+}
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        ENTERED_SPANS.with(|spans| spans.borrow_mut().push(this.span.clone()));
+        let _guard = SpanGuard;
+        this.inner.as_mut().poll(cx)
+    }
+}
+
+pub trait Instrument: Future + Sized {
+    /// Enters the span every time this future is polled, so that its log lines carry it
+    fn instrument(self, span: LogSpan) -> Instrumented<Self> {
+        Instrumented {
+            inner: Box::pin(self),
+            span,
+        }
+    }
+}
+impl<F: Future> Instrument for F {}
+
+enum LogOutput {
+    Plain(Box<CombinedLogger>),
+    /// One JSON object per line, written to the terminal and the latest log
+    Json(Mutex<File>),
+}
+
+/// Puts the entered spans on each log record before writing it out
+struct SpanLogger {
+    level: LevelFilter,
+    output: LogOutput,
+}
+
+impl Log for SpanLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let spans = ENTERED_SPANS.with(|spans| spans.borrow().clone());
+        match &self.output {
+            LogOutput::Plain(logger) if spans.is_empty() => logger.log(record),
+            LogOutput::Plain(logger) => {
+                // ex. `client{player=Steve uuid=...}:server{id=0}: message`
+                let prefix = spans
+                    .iter()
+                    .map(|span| {
+                        let fields = span
+                            .0
+                            .fields
+                            .iter()
+                            .map(|(key, value)| format!("{}={}", key, value))
+                            .collect::<Vec<String>>()
+                            .join(" ");
+                        format!("{}{{{}}}", span.0.name, fields)
+                    })
+                    .collect::<Vec<String>>()
+                    .join(":");
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("{}: {}", prefix, record.args()))
+                        .metadata(record.metadata().clone())
+                        .module_path(record.module_path())
+                        .file(record.file())
+                        .line(record.line())
+                        .build(),
+                );
+            }
+            LogOutput::Json(file) => {
+                let mut spans_json = json::JsonValue::new_array();
+                for span in spans.iter() {
+                    let mut span_json = json::object! { name: span.0.name };
+                    for (key, value) in span.0.fields.iter() {
+                        span_json[*key] = value.as_str().into();
+                    }
+                    spans_json.push(span_json).ok();
+                }
+                let line = json::stringify(json::object! {
+                    time: Local::now().to_rfc3339(),
+                    level: record.level().to_string(),
+                    target: record.target(),
+                    spans: spans_json,
+                    message: record.args().to_string(),
+                });
+                println!("{}", line);
+                if let Ok(mut file) = file.lock() {
+                    writeln!(file, "{}", line).ok();
+                }
+            }
+        }
+    }
+    fn flush(&self) {
+        match &self.output {
+            LogOutput::Plain(logger) => logger.flush(),
+            LogOutput::Json(file) => {
+                if let Ok(mut file) = file.lock() {
+                    file.flush().ok();
+                }
+            }
+        }
+    }
+}
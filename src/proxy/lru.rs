@@ -0,0 +1,124 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+/// A map that holds at most a fixed number of entries, evicting the least recently used entry
+/// when full
+///
+/// Recency is tracked with a queue of (key, tick) pairs that is invalidated lazily: touching an
+/// entry pushes a new pair instead of moving the old one, and pairs whose tick no longer matches
+/// the entry are skipped when evicting.
+pub struct LruMap<K, V> {
+    capacity: usize,
+    map: HashMap<K, (V, u64)>,
+    order: VecDeque<(K, u64)>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            map: HashMap::new(),
+            order: VecDeque::new(),
+            tick: 0,
+        }
+    }
+    /// Gets a mutable reference to the value for the key, marking it as recently used
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if self.map.contains_key(key) {
+            self.touch(key.clone());
+        }
+        self.map.get_mut(key).map(|(value, _)| value)
+    }
+    /// Inserts a value, evicting the least recently used entry if the map is full. Returns the
+    /// previous value for the key
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.tick += 1;
+        let old = self
+            .map
+            .insert(key.clone(), (value, self.tick))
+            .map(|(value, _)| value);
+        self.order.push_back((key, self.tick));
+        self.evict();
+        old
+    }
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key).map(|(value, _)| value)
+    }
+    fn touch(&mut self, key: K) {
+        self.tick += 1;
+        if let Some((_, tick)) = self.map.get_mut(&key) {
+            *tick = self.tick;
+        }
+        self.order.push_back((key, self.tick));
+        self.compact();
+    }
+    fn evict(&mut self) {
+        while self.map.len() > self.capacity {
+            match self.order.pop_front() {
+                Some((key, tick)) => {
+                    if matches!(self.map.get(&key), Some((_, entry_tick)) if *entry_tick == tick) {
+                        self.map.remove(&key);
+                    }
+                }
+                None => break,
+            }
+        }
+        self.compact();
+    }
+    /// Drops stale pairs from the recency queue once it gets much larger than the map
+    fn compact(&mut self) {
+        if self.order.len() > self.capacity * 4 {
+            let map = &self.map;
+            self.order.retain(
+                |(key, tick)| matches!(map.get(key), Some((_, entry_tick)) if entry_tick == tick),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::SplinterConfig;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut map = LruMap::new(2);
+        map.insert(1, "a");
+        map.insert(2, "b");
+        // 1 is now more recently used than 2
+        assert!(map.get_mut(&1).is_some());
+        map.insert(3, "c");
+        assert!(map.get_mut(&2).is_none());
+        assert!(map.get_mut(&1).is_some());
+        assert!(map.get_mut(&3).is_some());
+    }
+
+    #[test]
+    fn reinserting_keeps_one_entry() {
+        let mut map = LruMap::new(2);
+        assert_eq!(map.insert(1, "a"), None);
+        assert_eq!(map.insert(1, "b"), Some("a"));
+        map.insert(2, "c");
+        assert_eq!(map.get_mut(&1).copied(), Some("b"));
+        assert_eq!(map.get_mut(&2).copied(), Some("c"));
+    }
+
+    #[test]
+    fn default_capacity_holds_max_view_distance() {
+        let mut map = LruMap::new(SplinterConfig::default().max_tracked_chunks);
+        for x in -32..=32 {
+            for z in -32..=32 {
+                map.insert((x, z), ());
+            }
+        }
+        for x in -32..=32 {
+            for z in -32..=32 {
+                assert!(map.get_mut(&(x, z)).is_some());
+            }
+        }
+    }
+}
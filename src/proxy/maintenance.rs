@@ -0,0 +1,15 @@
+use std::fs;
+
+pub const MAINTENANCE_FILENAME: &str = "./maintenance.ron";
+
+/// Reads whether maintenance mode was last turned on or off, if it was ever toggled
+pub fn load_maintenance_state() -> anyhow::Result<bool> {
+    Ok(ron::de::from_str(&fs::read_to_string(
+        MAINTENANCE_FILENAME,
+    )?)?)
+}
+
+/// Saves whether maintenance mode is on, so that it is kept after a restart
+pub fn save_maintenance_state(enabled: bool) -> anyhow::Result<()> {
+    fs::write(MAINTENANCE_FILENAME, ron::ser::to_string(&enabled)?).map_err(anyhow::Error::new)
+}
@@ -9,18 +9,39 @@ use std::{
 use bimap::BiHashMap;
 use mcproto_rs::uuid::UUID4;
 
+use crate::protocol::current::proto::{PlayerAddActionSpec, PlayerInfoActionList};
+
 pub struct SplinterMapping {
     pub eids: BiHashMap<i32, (u64, i32)>,
+    /// Server-side player uuids by server id, mapped to the proxy-side uuid
+    pub uuids: HashMap<(u64, UUID4), UUID4>,
     pub entity_data: HashMap<i32, EntityData>,
     pub eid_gen: IdGenerator,
+    /// Biome ids of each server that differ from the proxy-side id of the same biome, by server
+    /// id
+    pub biomes: HashMap<u64, HashMap<i32, i32>>,
+    /// Entity type ids of each server with configured entity types, mapped to the proxy-side id
+    /// of the same type, by server id
+    pub entity_types: HashMap<u64, HashMap<i32, i32>>,
+    /// Server-side entity type ids that weren't in their server's entity types, so that each is
+    /// only warned about once
+    pub unknown_entity_types: HashSet<(u64, i32)>,
+    /// Player info entries each server has added and not yet removed, by server id, keyed by
+    /// proxy-side uuid
+    pub player_info: HashMap<u64, HashMap<UUID4, PlayerAddActionSpec>>,
 }
 
 impl SplinterMapping {
     pub fn new() -> Self {
         Self {
             eids: BiHashMap::new(),
+            uuids: HashMap::new(),
             eid_gen: IdGenerator::new(),
             entity_data: HashMap::new(),
+            biomes: HashMap::new(),
+            entity_types: HashMap::new(),
+            unknown_entity_types: HashSet::new(),
+            player_info: HashMap::new(),
         }
     }
     pub fn register_eid_mapping(&mut self, server_id: u64, server_eid: i32) -> i32 {
@@ -32,6 +53,110 @@ impl SplinterMapping {
         );
         new_eid
     }
+    /// Removes the mapping of a proxy-side eid, returning the id to be reused
+    pub fn free_eid(&mut self, proxy_eid: i32) {
+        if let Some((_, (server_id, server_eid))) = self.eids.remove_by_left(&proxy_eid) {
+            debug!(
+                "destroying map s->p ({}, {}) to {}",
+                server_id, server_eid, proxy_eid
+            );
+            self.entity_data.remove(&proxy_eid);
+            self.eid_gen.return_id(proxy_eid as u64);
+        }
+    }
+    pub fn register_uuid_mapping(&mut self, server_id: u64, server_uuid: UUID4, proxy_uuid: UUID4) {
+        if let Some(existing_uuid) = self.uuids.insert((server_id, server_uuid), proxy_uuid) {
+            if existing_uuid != proxy_uuid {
+                warn!(
+                    "Server {} reused uuid {} for a different player ({} -> {})",
+                    server_id, server_uuid, existing_uuid, proxy_uuid
+                );
+            }
+        }
+        debug!(
+            "New mapping s->p uuid ({}, {}) to {}",
+            server_id, server_uuid, proxy_uuid
+        );
+    }
+    /// Gets the proxy-side uuid for a server-side uuid. Mappings are kept per server, so two
+    /// servers giving different players the same uuid still map to distinct proxy-side uuids.
+    /// Uuids without a mapping (ex. server-side NPCs) are returned as is
+    pub fn map_uuid(&self, server_id: u64, server_uuid: UUID4) -> UUID4 {
+        self.uuids
+            .get(&(server_id, server_uuid))
+            .copied()
+            .unwrap_or(server_uuid)
+    }
+    /// Gets the proxy-side id of a server's entity type. Types of servers without configured
+    /// entity types, and types missing from them, are passed through unchanged
+    pub fn map_entity_type(&mut self, server_id: u64, server_type: i32) -> i32 {
+        let types = match self.entity_types.get(&server_id) {
+            Some(types) => types,
+            None => return server_type,
+        };
+        match types.get(&server_type) {
+            Some(proxy_type) => *proxy_type,
+            None => {
+                if self.unknown_entity_types.insert((server_id, server_type)) {
+                    warn!(
+                        "Server {} spawned entity type {}, which is not in its entity types",
+                        server_id, server_type
+                    );
+                }
+                server_type
+            }
+        }
+    }
+    /// Keeps a server's player info entries up to date with player info it sent, once its uuids
+    /// are mapped. Updates to entries the server never added are ignored
+    pub fn record_player_info(&mut self, server_id: u64, actions: &PlayerInfoActionList) {
+        let entries = self
+            .player_info
+            .entry(server_id)
+            .or_insert_with(HashMap::new);
+        match actions {
+            PlayerInfoActionList::Add(list) => {
+                for action in list.iter() {
+                    entries.insert(action.uuid, action.clone());
+                }
+            }
+            PlayerInfoActionList::UpdateGameMode(list) => {
+                for action in list.iter() {
+                    if let Some(entry) = entries.get_mut(&action.uuid) {
+                        entry.game_mode = action.game_mode;
+                    }
+                }
+            }
+            PlayerInfoActionList::UpdateLatency(list) => {
+                for action in list.iter() {
+                    if let Some(entry) = entries.get_mut(&action.uuid) {
+                        entry.ping_ms = action.ping_ms;
+                    }
+                }
+            }
+            PlayerInfoActionList::UpdateDisplayName(list) => {
+                for action in list.iter() {
+                    if let Some(entry) = entries.get_mut(&action.uuid) {
+                        entry.display_name = action.display_name.clone();
+                    }
+                }
+            }
+            PlayerInfoActionList::Remove(list) => {
+                for uuid in list.iter() {
+                    entries.remove(uuid);
+                }
+            }
+        }
+    }
+    /// Gets the proxy-side id of a server's biome. Ids the server's dimension codec didn't list
+    /// under a known name are passed through unchanged
+    pub fn map_biome(&self, server_id: u64, server_biome: i32) -> i32 {
+        self.biomes
+            .get(&server_id)
+            .and_then(|ids| ids.get(&server_biome))
+            .copied()
+            .unwrap_or(server_biome)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -55,6 +180,11 @@ pub fn uuid_from_name(name: impl AsRef<str>) -> UUID4 {
     uuid_from_bytes(format!("OfflinePlayer:{}", name.as_ref()).as_bytes())
 }
 
+/// Proxy-side uuid of a server's boss bar, so that boss bars of different servers never share one
+pub fn map_boss_bar_uuid(server_id: u64, uuid: UUID4) -> UUID4 {
+    uuid_from_bytes(format!("BossBar:{}:{}", server_id, uuid).as_bytes())
+}
+
 pub struct EntityData {
     pub id: i32,
     pub entity_type: i32,
@@ -72,14 +202,20 @@ impl IdGenerator {
             available_ids_set: HashSet::from_iter([INITIAL_ID]),
         }
     }
+    /// Takes an available id, preferring the most recently returned one. The last available id
+    /// is always the lowest id never handed out
     pub fn take_id(&mut self) -> u64 {
-        if self.available_ids.len() > 1 {
+        let id = if self.available_ids.len() > 1 {
             self.available_ids.remove(self.available_ids.len() - 2) // remove second to last
         } else {
             let id = self.available_ids.remove(0);
             self.available_ids.push(id + 1);
+            self.available_ids_set.insert(id + 1);
             id
-        }
+        };
+        // taken ids must leave the set, or they could never be returned again
+        self.available_ids_set.remove(&id);
+        id
     }
     pub fn return_id(&mut self, id: u64) {
         if self.available_ids_set.insert(id) {
@@ -87,3 +223,60 @@ impl IdGenerator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_server_uuid_on_two_servers_stays_distinct() {
+        let server_uuid = UUID4::from(7u128);
+        let (alice, bob) = (uuid_from_name("alice"), uuid_from_name("bob"));
+        let mut map = SplinterMapping::new();
+        map.register_uuid_mapping(1, server_uuid, alice);
+        map.register_uuid_mapping(2, server_uuid, bob);
+        assert_eq!(map.map_uuid(1, server_uuid), alice);
+        assert_eq!(map.map_uuid(2, server_uuid), bob);
+    }
+
+    #[test]
+    fn recycled_eids_keep_high_water_mark_bounded() {
+        let mut map = SplinterMapping::new();
+        let mut highest = 0;
+        for round in 0..1000 {
+            let eids = (0..16)
+                .map(|i| map.register_eid_mapping(1, round * 16 + i))
+                .collect::<Vec<i32>>();
+            highest = highest.max(*eids.iter().max().unwrap());
+            for eid in eids {
+                map.free_eid(eid);
+            }
+        }
+        assert_eq!(highest, 16);
+        assert!(map.eids.is_empty());
+    }
+
+    #[test]
+    fn returned_ids_can_be_taken_and_returned_again() {
+        let mut gen = IdGenerator::new();
+        let (first, second) = (gen.take_id(), gen.take_id());
+        for _ in 0..100 {
+            gen.return_id(first);
+            assert_eq!(gen.take_id(), first);
+        }
+        gen.return_id(second);
+        gen.return_id(second);
+        assert_eq!(gen.take_id(), second);
+        // returned once, so only handed out once
+        assert_eq!(gen.take_id(), 3);
+    }
+
+    #[test]
+    fn freeing_unmapped_eid_does_nothing() {
+        let mut map = SplinterMapping::new();
+        let eid = map.register_eid_mapping(1, 10);
+        map.free_eid(eid + 1);
+        assert_eq!(map.eids.get_by_left(&eid), Some(&(1, 10)));
+        assert_eq!(map.register_eid_mapping(1, 11), eid + 1);
+    }
+}
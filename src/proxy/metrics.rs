@@ -0,0 +1,148 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{protocol::current::protocol::PacketDirection, proxy::SplinterProxy};
+
+/// Counters exposed by the metrics endpoint. These are only atomics, so that the relay loops
+/// never wait to count a packet
+#[derive(Default)]
+pub struct Metrics {
+    pub clientbound_packets: AtomicU64,
+    pub serverbound_packets: AtomicU64,
+    pub clientbound_bytes: AtomicU64,
+    pub serverbound_bytes: AtomicU64,
+    /// Clients that got through login to play
+    pub logins: AtomicU64,
+    /// Clients whose connection closed after getting to play
+    pub disconnects: AtomicU64,
+    /// Entity spawns dropped because a client had reached `max_mapped_entities`
+    pub entity_cap_hits: AtomicU64,
+    /// Packets written to clients
+    pub client_writes: AtomicU64,
+    /// Time spent writing packets to clients, in microseconds. Divided by `client_writes`, this
+    /// shows what each flush policy costs during bursts such as chunk loading
+    pub client_write_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts a packet read during play, given the length of its uncompressed body
+    pub fn record_packet(&self, direction: &PacketDirection, len: usize) {
+        let (packets, bytes) = match direction {
+            PacketDirection::ClientBound => (&self.clientbound_packets, &self.clientbound_bytes),
+            PacketDirection::ServerBound => (&self.serverbound_packets, &self.serverbound_bytes),
+        };
+        packets.fetch_add(1, Ordering::Relaxed);
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+    /// Counts a packet written to a client, given how long writing it took
+    pub fn record_client_write(&self, elapsed: Duration) {
+        self.client_writes.fetch_add(1, Ordering::Relaxed);
+        self.client_write_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+    /// Writes the counters, along with the current client and server connection counts, in the
+    /// Prometheus text exposition format
+    pub async fn render(&self, proxy: &SplinterProxy) -> String {
+        let clients = proxy
+            .players
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut server_connections = BTreeMap::new();
+        for server_id in proxy.servers.read().await.keys() {
+            server_connections.insert(*server_id, 0u64);
+        }
+        for client in clients.iter() {
+            for server_id in client.connected_servers() {
+                *server_connections.entry(server_id).or_insert(0) += 1;
+            }
+        }
+
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, values: &[(&str, u64)]| {
+            writeln!(out, "# HELP {} {}", name, help).ok();
+            writeln!(out, "# TYPE {} counter", name).ok();
+            for (labels, value) in values {
+                writeln!(out, "{}{} {}", name, labels, value).ok();
+            }
+        };
+        counter(
+            "splinter_packets_total",
+            "Packets relayed during play",
+            &[
+                (
+                    "{direction=\"clientbound\"}",
+                    self.clientbound_packets.load(Ordering::Relaxed),
+                ),
+                (
+                    "{direction=\"serverbound\"}",
+                    self.serverbound_packets.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        counter(
+            "splinter_packet_bytes_total",
+            "Uncompressed bytes of packets relayed during play",
+            &[
+                (
+                    "{direction=\"clientbound\"}",
+                    self.clientbound_bytes.load(Ordering::Relaxed),
+                ),
+                (
+                    "{direction=\"serverbound\"}",
+                    self.serverbound_bytes.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        counter(
+            "splinter_logins_total",
+            "Clients that logged in",
+            &[("", self.logins.load(Ordering::Relaxed))],
+        );
+        counter(
+            "splinter_disconnects_total",
+            "Clients that disconnected after logging in",
+            &[("", self.disconnects.load(Ordering::Relaxed))],
+        );
+        counter(
+            "splinter_entity_cap_hits_total",
+            "Entity spawns dropped because a client had the most entities it may have mapped",
+            &[("", self.entity_cap_hits.load(Ordering::Relaxed))],
+        );
+        counter(
+            "splinter_client_writes_total",
+            "Packets written to clients",
+            &[("", self.client_writes.load(Ordering::Relaxed))],
+        );
+        counter(
+            "splinter_client_write_microseconds_total",
+            "Time spent writing packets to clients",
+            &[("", self.client_write_micros.load(Ordering::Relaxed))],
+        );
+
+        writeln!(out, "# HELP splinter_clients Clients connected to the proxy").ok();
+        writeln!(out, "# TYPE splinter_clients gauge").ok();
+        writeln!(out, "splinter_clients {}", clients.len()).ok();
+        writeln!(
+            out,
+            "# HELP splinter_server_connections Client connections to each simulation server"
+        )
+        .ok();
+        writeln!(out, "# TYPE splinter_server_connections gauge").ok();
+        for (server_id, count) in server_connections {
+            writeln!(
+                out,
+                "splinter_server_connections{{server=\"{}\"}} {}",
+                server_id, count
+            )
+            .ok();
+        }
+        out
+    }
+}
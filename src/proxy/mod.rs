@@ -1,51 +1,97 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::{SocketAddr, TcpListener},
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use arc_swap::ArcSwap;
+use futures_lite::future;
 use smol::{
     lock::{Mutex, RwLock},
     Async, Timer,
 };
+use socket2::{Domain, Protocol, Socket, Type};
 
+pub mod allowlist;
 pub mod chat;
 pub mod client;
 pub mod config;
 pub mod logging;
+pub mod lru;
+pub mod maintenance;
 pub mod mapping;
+pub mod metrics;
+pub mod playerlist;
+pub mod proxyprotocol;
+pub mod ratelimit;
+pub mod resolve;
 pub mod server;
 
+use allowlist::IpAllowlist;
 use client::SplinterClient;
-use config::SplinterConfig;
+use config::{KickMessages, SplinterConfig};
+use maintenance::{load_maintenance_state, save_maintenance_state};
 use mapping::SplinterMapping;
+use metrics::Metrics;
+use playerlist::{PlayerList, BANLIST_FILENAME, WHITELIST_FILENAME};
 use server::SplinterServer;
 
 use crate::{
-    protocol::Tags,
+    protocol::{
+        current::{proto::PlayTagsSpec, uuid::UUID4, PacketLatestKind},
+        v_cur, Tags,
+    },
     systems::{
         playersave::{
-            load_player_data, save_player_data, PlInfo, PlInfoPlayer, PLAYER_DATA_FILENAME,
+            load_player_data, save_player_data, PlInfo, PLAYER_DATA_FILENAME,
         },
         zoning::{Zone, Zoner},
     },
 };
 
+/// Ids given to servers added at runtime start here, well away from configured ids
+const ADHOC_SERVER_ID_START: u64 = 1 << 48;
+
 pub struct SplinterProxy {
     pub alive: AtomicBool,
-    pub config: SplinterConfig,
+    /// Set once shutdown begins, after which no new connections are accepted
+    pub shutting_down: AtomicBool,
+    /// Whether only players exempt from maintenance may be connected
+    pub maintenance: AtomicBool,
+    /// Packet kinds replayed to clients on a server switch
+    pub replayed_kinds: Vec<PacketLatestKind>,
+    pub start_time: Instant,
+    /// Swapped for the new config on a reload. Read it through `config`
+    pub config: ArcSwap<SplinterConfig>,
     pub players: RwLock<HashMap<String, Arc<SplinterClient>>>,
     pub servers: RwLock<HashMap<u64, Arc<SplinterServer>>>,
     pub mapping: Mutex<SplinterMapping>,
     pub tags: Mutex<Option<Tags>>,
+    /// Tags packet converted from `tags`, cached so it isn't rebuilt for every client
+    pub tags_spec: Mutex<Option<PlayTagsSpec>>,
+    /// Player counts reported by each simulation server's last status query
+    pub backend_player_counts: Mutex<HashMap<u64, i32>>,
+    /// When each player last used each command that has a cooldown
+    pub command_last_used: Mutex<HashMap<(UUID4, String), Instant>>,
+    /// Ids of servers added at runtime with `connect` rather than from the config. When both are
+    /// needed, this is locked before `servers`
+    pub adhoc_servers: Mutex<HashSet<u64>>,
 
     pub player_data: Mutex<PlInfo>,
     pub zoner: Zoner,
+    pub ip_allowlist: IpAllowlist,
+    /// Players allowed to join while `whitelist_enabled` is set
+    pub whitelist: RwLock<PlayerList>,
+    /// Players who may not join
+    pub banlist: RwLock<PlayerList>,
+    /// Sources whose PROXY protocol headers are trusted
+    pub proxy_protocol_trusted: IpAllowlist,
+    pub metrics: Metrics,
 }
 
 impl SplinterProxy {
@@ -53,23 +99,59 @@ impl SplinterProxy {
         let servers = {
             let mut map = HashMap::new();
             for (id, addr_str) in config.simulation_servers.iter() {
+                // one server that can't be found shouldn't keep the proxy from starting
+                let address = match smol::block_on(resolve::resolve_server_address(addr_str)) {
+                    Ok(address) => address,
+                    Err(e) => {
+                        error!(
+                            "Failed to resolve server {} at \"{}\", skipping it: {:?}",
+                            id, addr_str, e
+                        );
+                        continue;
+                    }
+                };
                 map.insert(
                     *id,
-                    Arc::new(SplinterServer {
-                        id: *id,
-                        address: SocketAddr::from_str(addr_str)?,
-                    }),
+                    Arc::new(SplinterServer::new(
+                        *id,
+                        address,
+                        config.server_pool_sizes.get(id).copied().unwrap_or(0),
+                        config.flush_policy.nodelay(),
+                        config.send_proxy_protocol,
+                        config.max_packet_size,
+                    )),
                 );
             }
             RwLock::new(map)
         };
+        let mut mapping = SplinterMapping::new();
+        for (server_id, path) in config.server_entity_types.iter() {
+            mapping
+                .entity_types
+                .insert(*server_id, v_cur::load_entity_types(path)?);
+        }
+        let ip_allowlist = IpAllowlist::from_cidrs(&config.ip_allowlist)?;
+        let proxy_protocol_trusted = IpAllowlist::from_cidrs(&config.proxy_protocol_trusted)?;
+        let whitelist = PlayerList::load(WHITELIST_FILENAME)?;
+        let banlist = PlayerList::load(BANLIST_FILENAME)?;
+        let default_server = config.default_server;
         Ok(Self {
             alive: AtomicBool::new(true),
-            config,
+            shutting_down: AtomicBool::new(false),
+            maintenance: AtomicBool::new(
+                load_maintenance_state().unwrap_or(config.maintenance.enabled),
+            ),
+            replayed_kinds: server::replayed_kinds(&config.replayed_packets),
+            start_time: Instant::now(),
+            config: ArcSwap::from_pointee(config),
             players: RwLock::new(HashMap::new()),
             servers,
-            mapping: Mutex::new(SplinterMapping::new()),
+            mapping: Mutex::new(mapping),
             tags: Mutex::new(None),
+            tags_spec: Mutex::new(None),
+            backend_player_counts: Mutex::new(HashMap::new()),
+            command_last_used: Mutex::new(HashMap::new()),
+            adhoc_servers: Mutex::new(HashSet::new()),
             zoner: Zoner {
                 zones: vec![
                     (
@@ -91,15 +173,280 @@ impl SplinterProxy {
                         },
                     ),
                 ],
+                default_server,
             },
             player_data: Mutex::new(
                 load_player_data(PLAYER_DATA_FILENAME).unwrap_or(PlInfo::default()),
             ),
+            ip_allowlist,
+            whitelist: RwLock::new(whitelist),
+            banlist: RwLock::new(banlist),
+            proxy_protocol_trusted,
+            metrics: Metrics::default(),
         })
     }
+    /// Gets the config the proxy currently runs with. A reload swaps in a new one, so code that
+    /// needs several settings to agree should get it once
+    pub fn config(&self) -> Arc<SplinterConfig> {
+        self.config.load_full()
+    }
+    /// Sets the tags sent to clients, invalidating the cached tags packet
+    pub async fn set_tags(&self, tags: Tags) {
+        let mut tags_spec = self.tags_spec.lock().await;
+        *self.tags.lock().await = Some(tags);
+        *tags_spec = None;
+    }
+    /// Gets the tags packet to send to clients, only converting the tags if they have changed
+    /// since the last call
+    pub async fn tags_spec(&self) -> Option<PlayTagsSpec> {
+        let mut tags_spec = self.tags_spec.lock().await;
+        if tags_spec.is_none() {
+            *tags_spec = self.tags.lock().await.as_ref().map(PlayTagsSpec::from);
+        }
+        tags_spec.clone()
+    }
+    pub fn uptime(&self) -> Duration {
+        self.start_time.elapsed()
+    }
     pub fn is_alive(&self) -> bool {
         self.alive.load(Ordering::Relaxed)
     }
+    /// Checks if packets of this kind are part of the state replayed to the client on a server
+    /// switch. The world border always is
+    pub fn is_replayed(&self, kind: PacketLatestKind) -> bool {
+        self.replayed_kinds.contains(&kind) || v_cur::WORLD_BORDER_KINDS.contains(&kind)
+    }
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+    /// Turns maintenance mode on or off, kicking players who aren't exempt when turned on
+    pub async fn set_maintenance(&self, enabled: bool) -> anyhow::Result<()> {
+        self.maintenance.store(enabled, Ordering::Relaxed);
+        save_maintenance_state(enabled)?;
+        if enabled {
+            let names = self
+                .players
+                .read()
+                .await
+                .keys()
+                .filter(|name| !self.config().maintenance.is_exempt(name))
+                .cloned()
+                .collect::<Vec<String>>();
+            for name in names {
+                if let Err(e) = self.kick_client(&name, ClientKickReason::Maintenance).await {
+                    error!("Error kicking player \"{}\" for maintenance: {}", &name, e);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Swaps in the given config and brings the simulation servers in line with its servers,
+    /// returning how many were added and removed. Servers whose address changed count as both.
+    /// Clients keep their connections to removed servers until they leave them, and are sent
+    /// their command tree again with the new config's proxy commands
+    pub async fn reload_config(&self, config: SplinterConfig) -> (usize, usize) {
+        self.config.store(Arc::new(config));
+        let config = self.config();
+        let mut resolved = HashMap::new();
+        for (id, addr_str) in config.simulation_servers.iter() {
+            match resolve::resolve_server_address(addr_str).await {
+                Ok(address) => {
+                    resolved.insert(*id, address);
+                }
+                Err(e) => error!(
+                    "Failed to resolve server {} at \"{}\", leaving it as it was: {:?}",
+                    id, addr_str, e
+                ),
+            }
+        }
+        let adhoc_servers = self.adhoc_servers.lock().await;
+        let mut servers = self.servers.write().await;
+        let (mut added, mut removed) = (0, 0);
+        let stale = servers
+            .iter()
+            .filter(|(id, server)| {
+                let id = **id;
+                let configured = config
+                    .simulation_servers
+                    .iter()
+                    .any(|(new_id, _)| *new_id == id);
+                (!configured && !adhoc_servers.contains(&id))
+                    || matches!(resolved.get(&id), Some(address) if *address != server.address)
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<u64>>();
+        for id in stale {
+            servers.remove(&id);
+            removed += 1;
+        }
+        for (id, address) in resolved {
+            if servers.contains_key(&id) {
+                continue;
+            }
+            servers.insert(
+                id,
+                Arc::new(SplinterServer::new(
+                    id,
+                    address,
+                    config.server_pool_sizes.get(&id).copied().unwrap_or(0),
+                    config.flush_policy.nodelay(),
+                    config.send_proxy_protocol,
+                    config.max_packet_size,
+                )),
+            );
+            added += 1;
+        }
+        drop(servers);
+        drop(adhoc_servers);
+        for (_, client) in self.players.read().await.iter() {
+            if let Err(e) = client.resend_commands().await {
+                error!("Failed to resend commands to {}: {}", &client.name, e);
+            }
+        }
+        (added, removed)
+    }
+    /// Reads the whitelist and ban list from their files again, returning how many players are
+    /// on each. Neither list is changed if either fails to load
+    pub async fn reload_player_lists(&self) -> anyhow::Result<(usize, usize)> {
+        let whitelist = PlayerList::load(WHITELIST_FILENAME)?;
+        let banlist = PlayerList::load(BANLIST_FILENAME)?;
+        let counts = (whitelist.entries.len(), banlist.entries.len());
+        *self.whitelist.write().await = whitelist;
+        *self.banlist.write().await = banlist;
+        Ok(counts)
+    }
+    /// Adds a server that isn't in the config, and returns the id it was given
+    pub async fn add_adhoc_server(&self, address: SocketAddr) -> u64 {
+        let adhoc_servers = &mut *self.adhoc_servers.lock().await;
+        let servers = &mut *self.servers.write().await;
+        let mut server_id = ADHOC_SERVER_ID_START;
+        while servers.contains_key(&server_id) {
+            server_id += 1;
+        }
+        servers.insert(
+            server_id,
+            Arc::new(SplinterServer::new(
+                server_id,
+                address,
+                0,
+                self.config().flush_policy.nodelay(),
+                self.config().send_proxy_protocol,
+                self.config().max_packet_size,
+            )),
+        );
+        adhoc_servers.insert(server_id);
+        info!("Added server {} at {}", server_id, address);
+        server_id
+    }
+    /// Adds a simulation server under the given id. Like servers in the config, it stays until
+    /// removed or until the config is reloaded without it
+    pub async fn add_server(&self, server_id: u64, address: SocketAddr) -> anyhow::Result<()> {
+        let servers = &mut *self.servers.write().await;
+        if servers.contains_key(&server_id) {
+            bail!("There is already a server with id {}", server_id);
+        }
+        servers.insert(
+            server_id,
+            Arc::new(SplinterServer::new(
+                server_id,
+                address,
+                self.config()
+                    .server_pool_sizes
+                    .get(&server_id)
+                    .copied()
+                    .unwrap_or(0),
+                self.config().flush_policy.nodelay(),
+                self.config().send_proxy_protocol,
+                self.config().max_packet_size,
+            )),
+        );
+        info!("Added server {} at {}", server_id, address);
+        Ok(())
+    }
+    /// Removes a simulation server, unless zoning can send players to it or it is any player's
+    /// active or pinned server. Players connected to it in the background keep their connections
+    /// until they leave it
+    pub async fn remove_server(&self, server_id: u64) -> anyhow::Result<()> {
+        if self.zoner.uses_server(server_id) {
+            bail!(
+                "Server {} is the default server or the server of a zone",
+                server_id
+            );
+        }
+        let adhoc_servers = &mut *self.adhoc_servers.lock().await;
+        let players = self.players.read().await;
+        let active_players = players
+            .values()
+            .filter(|client| client.server_id() == server_id)
+            .count();
+        if active_players > 0 {
+            bail!(
+                "Server {} is the active server of {} players",
+                server_id,
+                active_players
+            );
+        }
+        let pinned_players = players
+            .values()
+            .filter(|client| **client.pinned_server.load() == Some(server_id))
+            .count();
+        if pinned_players > 0 {
+            bail!(
+                "Server {} is the pinned server of {} players",
+                server_id,
+                pinned_players
+            );
+        }
+        if self.servers.write().await.remove(&server_id).is_none() {
+            bail!("There is no server with id {}", server_id);
+        }
+        adhoc_servers.remove(&server_id);
+        self.backend_player_counts.lock().await.remove(&server_id);
+        info!("Removed server {}", server_id);
+        Ok(())
+    }
+    /// Removes every server added with `add_adhoc_server` that no player is connected or pinned to
+    pub async fn remove_unused_adhoc_servers(&self) {
+        let adhoc_servers = &mut *self.adhoc_servers.lock().await;
+        if adhoc_servers.is_empty() {
+            return;
+        }
+        let mut used = HashSet::new();
+        for (_, client) in self.players.read().await.iter() {
+            used.extend(client.connected_servers());
+            if let Some(server_id) = **client.pinned_server.load() {
+                used.insert(server_id);
+            }
+        }
+        let unused = adhoc_servers
+            .iter()
+            .copied()
+            .filter(|server_id| !used.contains(server_id))
+            .collect::<Vec<u64>>();
+        let servers = &mut *self.servers.write().await;
+        let player_counts = &mut *self.backend_player_counts.lock().await;
+        for server_id in unused {
+            adhoc_servers.remove(&server_id);
+            servers.remove(&server_id);
+            player_counts.remove(&server_id);
+            info!("Removed unused server {}", server_id);
+        }
+    }
+    /// Whether new connections may be accepted
+    pub fn is_accepting(&self) -> bool {
+        self.is_alive() && !self.shutting_down.load(Ordering::Relaxed)
+    }
+    /// Removes the client from the player list, unless the name has since been taken by a newer
+    /// connection
+    pub async fn remove_player(&self, client: &Arc<SplinterClient>) {
+        let players = &mut *self.players.write().await;
+        if players
+            .get(&client.name)
+            .map_or(false, |player| Arc::ptr_eq(player, client))
+        {
+            players.remove(&client.name);
+        }
+    }
     pub async fn kick_client(
         &self,
         client_name: impl AsRef<str>,
@@ -110,23 +457,16 @@ impl SplinterProxy {
         if let Some(client) = cl_opt {
             client.send_kick(reason).await?;
             client.set_alive(false).await;
-            self.players.write().await.remove(&name_string);
-            let pos = &**client.position.load();
-            self.player_data.lock().await.players.insert(
-                client.uuid,
-                PlInfoPlayer {
-                    x: pos.x,
-                    y: pos.y,
-                    z: pos.z,
-                    name: client.name.clone(),
-                },
-            );
+            self.remove_player(&client).await;
+            client.store_player_info().await;
         } else {
             bail!("Failed to find client by the name \"{}\"", name_string);
         }
         Ok(())
     }
-    pub async fn shutdown(&self) {
+    /// Disconnects every client with the given reason, saves player data, and stops the proxy
+    pub async fn shutdown(&self, reason: ClientKickReason) {
+        self.shutting_down.store(true, Ordering::Relaxed);
         let names = self
             .players
             .read()
@@ -137,13 +477,17 @@ impl SplinterProxy {
         if !names.is_empty() {
             info!("Disconnecting clients");
             for name in names {
-                if let Err(e) = self.kick_client(&name, ClientKickReason::Shutdown).await {
+                if let Err(e) = self.kick_client(&name, reason.clone()).await {
                     error!("Error kicking player \"{}\": {}", &name, e);
                 }
             }
         }
 
-        if let Err(e) = save_player_data(&*self.player_data.lock().await, PLAYER_DATA_FILENAME) {
+        if let Err(e) = save_player_data(
+            &*self.player_data.lock().await,
+            PLAYER_DATA_FILENAME,
+            self.config().compress_player_data,
+        ) {
             error!("Error saving player data: {:?}", e);
         }
         info!("Shutting down");
@@ -160,47 +504,61 @@ pub enum ClientKickReason {
     Kicked(String, Option<String>),
     /// Server shut down
     Shutdown,
+    /// Server shut down to be restarted
+    Restart,
+    /// Maintenance mode was turned on
+    Maintenance,
+    /// Client was banned, for the given reason
+    Banned(String),
+    /// Client's server went down and it could not be moved to another
+    ServerDown,
+    /// Client sent packets too fast for too long
+    RateLimited,
 }
 
 impl ClientKickReason {
-    pub fn text(&self) -> String {
-        match self {
-            ClientKickReason::TimedOut => "Timed out".into(),
-            ClientKickReason::Kicked(by, reason) => format!(
-                "Kicked by {}{}",
-                by,
-                if let Some(reason) = reason {
-                    format!(" because \"{}\"", reason)
-                } else {
-                    "".into()
-                }
-            ),
-            ClientKickReason::Shutdown => "Server shut down".into(),
-        }
+    /// Whether the client should be able to join again soon after this kick
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            ClientKickReason::TimedOut | ClientKickReason::Restart | ClientKickReason::ServerDown
+        )
+    }
+    /// Gets the message shown to the kicked client, filled in from the configured templates
+    pub fn text(&self, messages: &KickMessages, server_id: u64) -> String {
+        let text = match self {
+            ClientKickReason::TimedOut => messages.timed_out.clone(),
+            ClientKickReason::Kicked(by, reason) => match reason {
+                Some(reason) => messages
+                    .kicked_with_reason
+                    .replace("{by}", by)
+                    .replace("{reason}", reason),
+                None => messages.kicked.replace("{by}", by),
+            },
+            ClientKickReason::Shutdown => messages.shutdown.clone(),
+            ClientKickReason::Restart => messages.restart.clone(),
+            ClientKickReason::Maintenance => messages.maintenance.clone(),
+            ClientKickReason::Banned(reason) => messages.banned.replace("{reason}", reason),
+            ClientKickReason::ServerDown => messages.server_down.clone(),
+            ClientKickReason::RateLimited => messages.rate_limited.clone(),
+        };
+        text.replace("{server}", &server_id.to_string())
     }
 }
 
 pub async fn run(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
-    let address = SocketAddr::from_str(proxy.config.proxy_address.as_str())?;
-    let listener = Async::<TcpListener>::bind(address)?;
+    let mut listening = 0;
+    for address_str in std::iter::once(&proxy.config().proxy_address)
+        .chain(proxy.config().additional_proxy_addresses.iter())
     {
-        let proxy = Arc::clone(&proxy);
-        smol::spawn(async move {
-            info!("Listening for incoming connections on {}", address);
-            loop {
-                let (stream, addr) = match listener.accept().await {
-                    Ok(s) => s,
-                    Err(e) => {
-                        error!("Failed to accept a client: {}", e);
-                        continue;
-                    }
-                };
-                if let Err(e) = client::handle(stream, addr, Arc::clone(&proxy)) {
-                    error!("Failed to handle connection from {}: {}", addr, e);
-                }
-            }
-        })
-        .detach();
+        // one address failing shouldn't keep the proxy off of the others
+        match listen(&proxy, address_str) {
+            Ok(()) => listening += 1,
+            Err(e) => error!("Failed to listen on {}: {:?}", address_str, e),
+        }
+    }
+    if listening == 0 {
+        bail!("Failed to listen on any address");
     }
     loop {
         if !proxy.is_alive() {
@@ -210,3 +568,59 @@ pub async fn run(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Binds to the given address and spawns a loop accepting clients from it
+fn listen(proxy: &Arc<SplinterProxy>, address_str: &str) -> anyhow::Result<()> {
+    let address = SocketAddr::from_str(address_str)?;
+    let listener = Async::new(bind_listener(address)?)?;
+    let proxy = Arc::clone(proxy);
+    smol::spawn(async move {
+        info!("Listening for incoming connections on {}", address);
+        loop {
+            // wake up every so often to see if we should stop listening
+            let accepted = future::or(async { Some(listener.accept().await) }, async {
+                Timer::after(Duration::from_secs(1)).await;
+                None
+            })
+            .await;
+            if !proxy.is_accepting() {
+                break;
+            }
+            let (stream, addr) = match accepted {
+                Some(Ok(s)) => s,
+                Some(Err(e)) => {
+                    error!("Failed to accept a client: {}", e);
+                    continue;
+                }
+                None => continue,
+            };
+            if let Err(e) = client::handle(stream, addr, Arc::clone(&proxy)) {
+                error!("Failed to handle connection from {}: {}", addr, e);
+            }
+        }
+        // dropping the listener closes it, so further connections are refused
+        info!("Stopped listening for connections on {}", address);
+    })
+    .detach();
+    Ok(())
+}
+
+/// Binds a listener to the address. IPv6 listeners are made IPv6 only, since on most systems
+/// they would otherwise take the IPv4 side of their port too and keep an IPv4 listener on the
+/// same port from binding
+fn bind_listener(address: SocketAddr) -> anyhow::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // as std does, so restarting the proxy isn't held up by connections in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
@@ -0,0 +1,170 @@
+use std::{fs, io, path::Path};
+
+use anyhow::Context;
+use mcproto_rs::uuid::UUID4;
+
+use crate::systems::playersave::write_file_atomic;
+
+pub const WHITELIST_FILENAME: &str = "./whitelist.json";
+pub const BANLIST_FILENAME: &str = "./banlist.json";
+
+/// A player on a `PlayerList`, by name, uuid, or both
+#[derive(Clone, Debug)]
+pub struct PlayerListEntry {
+    pub name: Option<String>,
+    /// Uuid in any case, with or without hyphens
+    pub uuid: Option<String>,
+    /// Why the player was put on the list, ex. the ban reason
+    pub reason: Option<String>,
+}
+
+impl PlayerListEntry {
+    /// Whether the entry is for the player with this name or uuid. Names are matched regardless
+    /// of case
+    pub fn matches(&self, name: &str, uuid: UUID4) -> bool {
+        let name_matches = match &self.name {
+            Some(entry_name) => entry_name.eq_ignore_ascii_case(name),
+            None => false,
+        };
+        let uuid_matches = match &self.uuid {
+            Some(entry_uuid) => normalize_uuid(entry_uuid) == normalize_uuid(&uuid.to_string()),
+            None => false,
+        };
+        name_matches || uuid_matches
+    }
+}
+
+/// A list of players kept in a JSON file, ex. the whitelist or the ban list
+///
+/// The file is an array of objects, each with a `name`, a `uuid`, or both, and optionally a
+/// `reason`
+#[derive(Default)]
+pub struct PlayerList {
+    pub entries: Vec<PlayerListEntry>,
+}
+
+impl PlayerList {
+    /// Reads a player list, which is empty if the file doesn't exist
+    pub fn load(filename: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let filename = filename.as_ref();
+        let data = match fs::read_to_string(filename) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read \"{}\"", filename.display()))
+            }
+        };
+        let parsed = json::parse(&data)
+            .with_context(|| format!("Failed to parse \"{}\"", filename.display()))?;
+        if !parsed.is_array() {
+            bail!("\"{}\" is not an array of players", filename.display());
+        }
+        let mut entries = Vec::new();
+        for (i, entry) in parsed.members().enumerate() {
+            let field = |key: &str| entry[key].as_str().map(str::to_owned);
+            let entry = PlayerListEntry {
+                name: field("name"),
+                uuid: field("uuid"),
+                reason: field("reason"),
+            };
+            if entry.name.is_none() && entry.uuid.is_none() {
+                bail!(
+                    "Player {} in \"{}\" has neither a name nor a uuid",
+                    i,
+                    filename.display()
+                );
+            }
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+    pub fn save(&self, filename: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut players = json::JsonValue::new_array();
+        for entry in self.entries.iter() {
+            let mut player = json::JsonValue::new_object();
+            let fields = [
+                ("name", &entry.name),
+                ("uuid", &entry.uuid),
+                ("reason", &entry.reason),
+            ];
+            for (key, value) in fields.iter() {
+                if let Some(value) = value {
+                    player[*key] = value.as_str().into();
+                }
+            }
+            players.push(player)?;
+        }
+        write_file_atomic(json::stringify_pretty(players, 4).as_bytes(), filename)
+    }
+    pub fn find(&self, name: &str, uuid: UUID4) -> Option<&PlayerListEntry> {
+        self.entries.iter().find(|entry| entry.matches(name, uuid))
+    }
+    /// Adds an entry, replacing any entries for the same player
+    pub fn add(&mut self, name: &str, uuid: UUID4, reason: Option<String>) {
+        self.remove(name, uuid);
+        self.entries.push(PlayerListEntry {
+            name: Some(name.to_owned()),
+            uuid: Some(uuid.to_string()),
+            reason,
+        });
+    }
+    /// Removes every entry for the player, returning whether there were any
+    pub fn remove(&mut self, name: &str, uuid: UUID4) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !entry.matches(name, uuid));
+        self.entries.len() != len
+    }
+}
+
+fn normalize_uuid(uuid: &str) -> String {
+    uuid.chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: u128 = 0x0123456789abcdef0123456789abcdef;
+
+    fn entry(name: Option<&str>, uuid: Option<&str>) -> PlayerListEntry {
+        PlayerListEntry {
+            name: name.map(str::to_owned),
+            uuid: uuid.map(str::to_owned),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn matches_name_ignoring_case() {
+        let entry = entry(Some("Alice"), None);
+        assert!(entry.matches("alice", UUID4::from(UUID)));
+        assert!(!entry.matches("bob", UUID4::from(UUID)));
+    }
+
+    #[test]
+    fn matches_uuid_in_any_format() {
+        let hyphenated = entry(None, Some("01234567-89AB-CDEF-0123-456789ABCDEF"));
+        let plain = entry(None, Some("0123456789abcdef0123456789abcdef"));
+        assert!(hyphenated.matches("someone", UUID4::from(UUID)));
+        assert!(plain.matches("someone", UUID4::from(UUID)));
+        assert!(!plain.matches("someone", UUID4::from(UUID + 1)));
+    }
+
+    #[test]
+    fn add_replaces_existing_entries() {
+        let mut list = PlayerList::default();
+        list.entries.push(entry(Some("alice"), None));
+        list.add("Alice", UUID4::from(UUID), Some("spam".to_owned()));
+        assert_eq!(list.entries.len(), 1);
+        assert_eq!(
+            list.find("ALICE", UUID4::from(0u128))
+                .and_then(|entry| entry.reason.as_deref()),
+            Some("spam")
+        );
+        assert!(list.remove("someone", UUID4::from(UUID)));
+        assert!(list.entries.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use futures_lite::{future, AsyncRead, AsyncReadExt};
+use smol::{Async, Timer};
+
+use crate::proxy::SplinterProxy;
+
+/// Bytes every PROXY protocol v2 header starts with
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// How long a new connection has to send its PROXY protocol header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait between looks at a connection that has sent part of a header signature
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Gets the address of the client on the other end of a newly accepted connection
+///
+/// If the PROXY protocol is enabled, connections from trusted sources must start with a v2
+/// header, which is consumed and gives the client's real address. Headers from anywhere else are
+/// refused, so that a client can't spoof its address. No source is trusted if none are listed.
+pub async fn resolve_client_address(
+    proxy: &SplinterProxy,
+    stream: &Async<TcpStream>,
+    peer_addr: SocketAddr,
+) -> anyhow::Result<SocketAddr> {
+    if !proxy.config().proxy_protocol {
+        return Ok(peer_addr);
+    }
+    let trusted = !proxy.proxy_protocol_trusted.is_empty()
+        && proxy.proxy_protocol_trusted.allows(peer_addr.ip());
+    future::or(read_client_address(stream, peer_addr, trusted), async {
+        Timer::after(HEADER_TIMEOUT).await;
+        Err(anyhow!(
+            "Timed out waiting for PROXY protocol header from {}",
+            peer_addr
+        ))
+    })
+    .await
+}
+
+async fn read_client_address(
+    stream: &Async<TcpStream>,
+    peer_addr: SocketAddr,
+    trusted: bool,
+) -> anyhow::Result<SocketAddr> {
+    if trusted {
+        read_header(stream, peer_addr).await
+    } else {
+        if starts_with_signature(stream).await? {
+            bail!("PROXY protocol header from untrusted source {}", peer_addr);
+        }
+        Ok(peer_addr)
+    }
+}
+
+/// Whether the connection starts with the PROXY protocol signature, without consuming anything.
+/// A peek can return less than the whole signature, so this looks again for as long as what has
+/// arrived could still be the start of one
+async fn starts_with_signature(stream: &Async<TcpStream>) -> anyhow::Result<bool> {
+    let mut buf = [0u8; SIGNATURE.len()];
+    loop {
+        let len = stream.peek(&mut buf).await?;
+        if len == 0 || buf[..len] != SIGNATURE[..len] {
+            // closed, or not a header
+            return Ok(false);
+        }
+        if len == SIGNATURE.len() {
+            return Ok(true);
+        }
+        Timer::after(PEEK_INTERVAL).await;
+    }
+}
+
+/// Reads a PROXY protocol v2 header, returning the source address it carries
+async fn read_header(
+    mut stream: impl AsyncRead + Unpin,
+    peer_addr: SocketAddr,
+) -> anyhow::Result<SocketAddr> {
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await?;
+    if header[..12] != SIGNATURE {
+        bail!("Expected a PROXY protocol header from {}", peer_addr);
+    }
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0F;
+    if version != 2 {
+        bail!("Unsupported PROXY protocol version {}", version);
+    }
+    let family = header[13] >> 4;
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+    match command {
+        // LOCAL: the balancer's own connection, ex. a health check
+        0x0 => Ok(peer_addr),
+        0x1 => match family {
+            // AF_INET
+            0x1 if body.len() >= 12 => Ok(SocketAddr::new(
+                Ipv4Addr::from(<[u8; 4]>::try_from(&body[0..4])?).into(),
+                u16::from_be_bytes([body[8], body[9]]),
+            )),
+            // AF_INET6
+            0x2 if body.len() >= 36 => Ok(SocketAddr::new(
+                Ipv6Addr::from(<[u8; 16]>::try_from(&body[0..16])?).into(),
+                u16::from_be_bytes([body[32], body[33]]),
+            )),
+            // AF_UNSPEC or AF_UNIX have no address we can use
+            _ => Ok(peer_addr),
+        },
+        other => bail!("Unknown PROXY protocol command {}", other),
+    }
+}
+
+/// Makes a PROXY protocol v2 header for a connection to a backend server
+///
+/// With the addresses of the client and the server, the header tells the server the connection
+/// is on behalf of the client. Without them, it is a LOCAL header for the proxy's own connections,
+/// ex. status queries
+pub fn encode_header(addresses: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+    let (source, destination) = match addresses {
+        Some(addresses) => addresses,
+        None => {
+            // LOCAL, AF_UNSPEC, no addresses
+            header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+            return header;
+        }
+    };
+    // PROXY
+    header.push(0x21);
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+            // TCP over IPv4
+            header.push(0x11);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&source_ip.octets());
+            header.extend_from_slice(&destination_ip.octets());
+        }
+        (source_ip, destination_ip) => {
+            // TCP over IPv6. both addresses must be the same family, so an IPv4 address is sent
+            // IPv4-mapped
+            header.push(0x21);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&ipv6(source_ip).octets());
+            header.extend_from_slice(&ipv6(destination_ip).octets());
+        }
+    }
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header.extend_from_slice(&destination.port().to_be_bytes());
+    header
+}
+
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:40000".parse().unwrap()
+    }
+
+    fn read(header: &[u8]) -> anyhow::Result<SocketAddr> {
+        smol::block_on(read_header(header, peer()))
+    }
+
+    #[test]
+    fn ipv4_header_round_trips() {
+        let client = "203.0.113.7:51234".parse().unwrap();
+        let server = "192.168.1.2:25565".parse().unwrap();
+        assert_eq!(
+            read(&encode_header(Some((client, server)))).unwrap(),
+            client
+        );
+    }
+
+    #[test]
+    fn ipv6_header_round_trips() {
+        let client = "[2001:db8::7]:51234".parse().unwrap();
+        let server = "[2001:db8::2]:25565".parse().unwrap();
+        assert_eq!(
+            read(&encode_header(Some((client, server)))).unwrap(),
+            client
+        );
+    }
+
+    #[test]
+    fn mixed_families_are_sent_as_ipv6() {
+        let client: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let server = "[2001:db8::2]:25565".parse().unwrap();
+        let header = encode_header(Some((client, server)));
+        assert_eq!(header[13], 0x21);
+        assert_eq!(
+            read(&header).unwrap(),
+            SocketAddr::new(ipv6(client.ip()).into(), client.port())
+        );
+    }
+
+    #[test]
+    fn local_header_keeps_peer_address() {
+        assert_eq!(read(&encode_header(None)).unwrap(), peer());
+    }
+
+    #[test]
+    fn bad_headers_are_refused() {
+        let mut header = encode_header(None);
+        header[0] = b'X';
+        assert!(read(&header).is_err());
+        let mut header = encode_header(None);
+        // version 1
+        header[12] = 0x10;
+        assert!(read(&header).is_err());
+        // cut off in the middle of the addresses
+        let client = "203.0.113.7:51234".parse().unwrap();
+        let server = "192.168.1.2:25565".parse().unwrap();
+        let header = encode_header(Some((client, server)));
+        assert!(read(&header[..20]).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Instant;
+
+use crate::{
+    protocol::current::PacketLatestKind,
+    proxy::config::{RateLimit, RateLimitConfig},
+};
+
+/// Seconds over which a client's dropped packets are counted towards `kick_after`
+pub const ABUSE_WINDOW_SECS: u64 = 10;
+
+/// Tokens refill continuously at the limit's rate up to its burst, and each packet takes one
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.last_refill = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// One client's packet rate limits. Buckets are made the first time the client sends a packet
+/// of each kind, and the client only ever sends a few kinds, so they are kept in a list
+#[derive(Default)]
+pub struct RateLimiter {
+    /// `None` for kinds that aren't limited
+    buckets: Vec<(PacketLatestKind, Option<TokenBucket>)>,
+    /// Start of the current abuse window in unix seconds, and packets dropped in it
+    drops: (u64, u32),
+}
+
+impl RateLimiter {
+    /// Takes a packet of the given kind out of its bucket, returning whether it may be relayed
+    pub fn allow(&mut self, config: &RateLimitConfig, kind: PacketLatestKind) -> bool {
+        let index = match self.buckets.iter().position(|(k, _)| *k == kind) {
+            Some(index) => index,
+            None => {
+                let bucket = config.limit_for(kind).map(TokenBucket::new);
+                self.buckets.push((kind, bucket));
+                self.buckets.len() - 1
+            }
+        };
+        match &mut self.buckets[index].1 {
+            Some(bucket) => bucket.take(),
+            None => true,
+        }
+    }
+    /// Counts a dropped packet, returning how many have been dropped in the current abuse window
+    pub fn record_drop(&mut self, unix_secs: u64) -> u32 {
+        let window = unix_secs - unix_secs % ABUSE_WINDOW_SECS;
+        if self.drops.0 != window {
+            self.drops = (window, 0);
+        }
+        self.drops.1 += 1;
+        self.drops.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(default: Option<RateLimit>) -> RateLimitConfig {
+        RateLimitConfig {
+            default,
+            kinds: vec![(
+                "PlayClientChatMessage".to_owned(),
+                RateLimit {
+                    per_second: 0.,
+                    burst: 2.,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            kick_after: None,
+        }
+    }
+
+    #[test]
+    fn burst_is_used_up() {
+        let config = config(None);
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.allow(&config, PacketLatestKind::PlayClientChatMessage));
+        assert!(limiter.allow(&config, PacketLatestKind::PlayClientChatMessage));
+        assert!(!limiter.allow(&config, PacketLatestKind::PlayClientChatMessage));
+    }
+
+    #[test]
+    fn unlisted_kinds_use_default() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..100 {
+            assert!(limiter.allow(&config(None), PacketLatestKind::PlayAnimation));
+        }
+        let config = config(Some(RateLimit {
+            per_second: 0.,
+            burst: 1.,
+        }));
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.allow(&config, PacketLatestKind::PlayAnimation));
+        assert!(!limiter.allow(&config, PacketLatestKind::PlayAnimation));
+        // each kind has its own bucket
+        assert!(limiter.allow(&config, PacketLatestKind::PlayClientKeepAlive));
+    }
+
+    #[test]
+    fn tokens_refill_up_to_burst() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            per_second: 1000.,
+            burst: 1.,
+        });
+        assert!(bucket.take());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(bucket.take());
+        assert!(bucket.tokens <= 1.);
+    }
+
+    #[test]
+    fn drops_are_counted_per_window() {
+        let mut limiter = RateLimiter::default();
+        assert_eq!(limiter.record_drop(100), 1);
+        assert_eq!(limiter.record_drop(109), 2);
+        assert_eq!(limiter.record_drop(110), 1);
+    }
+}
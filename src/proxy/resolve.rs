@@ -0,0 +1,385 @@
+use std::{
+    convert::TryFrom,
+    fs,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use futures_lite::{future, AsyncReadExt, AsyncWriteExt};
+use smol::{
+    net::{TcpStream, UdpSocket},
+    Timer,
+};
+
+/// Port servers are assumed to be on when an address has neither a port nor an SRV record
+const DEFAULT_PORT: u16 = 25565;
+/// How long to wait for the nameserver to answer an SRV query
+const SRV_TIMEOUT: Duration = Duration::from_secs(3);
+const SRV_RECORD_TYPE: u16 = 33;
+
+/// Resolves a simulation server address from the config
+///
+/// Addresses are tried as a literal socket address first. Otherwise a hostname with a port is
+/// looked up as is, and a hostname without one is looked up through its `_minecraft._tcp` SRV
+/// record, falling back to port 25565 the same way the vanilla client does.
+pub async fn resolve_server_address(address: &str) -> anyhow::Result<SocketAddr> {
+    if let Ok(addr) = SocketAddr::from_str(address) {
+        return Ok(addr);
+    }
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            Some(
+                port.parse::<u16>()
+                    .map_err(|_| anyhow!("Invalid port in \"{}\"", address))?,
+            ),
+        ),
+        None => (address, None),
+    };
+    let (host, port) = match port {
+        Some(port) => (host.to_owned(), port),
+        None => match lookup_srv(host).await {
+            Ok(Some(target)) => target,
+            Ok(None) => (host.to_owned(), DEFAULT_PORT),
+            Err(e) => {
+                warn!("SRV lookup for \"{}\" failed: {:?}", host, e);
+                (host.to_owned(), DEFAULT_PORT)
+            }
+        },
+    };
+    smol::net::resolve((host.as_str(), port))
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("\"{}\" did not resolve to any address", host))
+}
+
+/// Looks up the `_minecraft._tcp` SRV records of a host, returning the target host and port of
+/// the record to connect to
+async fn lookup_srv(host: &str) -> anyhow::Result<Option<(String, u16)>> {
+    let nameserver = match system_nameserver() {
+        Some(nameserver) => nameserver,
+        None => {
+            debug!(
+                "No system nameserver to look up SRV records of \"{}\" with",
+                host
+            );
+            return Ok(None);
+        }
+    };
+    let id = rand::random::<u16>();
+    let query = build_query(id, &format!("_minecraft._tcp.{}", host))?;
+    let response = future::or(query_nameserver(nameserver, &query), async {
+        Timer::after(SRV_TIMEOUT).await;
+        Err(anyhow!("Nameserver {} did not answer", nameserver))
+    })
+    .await?;
+    let records = parse_srv_response(id, &response)?;
+    Ok(choose_srv(&records, rand::random::<u32>())
+        .map(|record| (record.target.clone(), record.port)))
+}
+
+/// Sends a query to a nameserver over UDP, and again over TCP if the answer didn't fit in a
+/// datagram
+async fn query_nameserver(nameserver: IpAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let socket = UdpSocket::bind(match nameserver {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    })
+    .await?;
+    socket.connect((nameserver, 53)).await?;
+    socket.send(query).await?;
+    let mut buf = [0u8; 1500];
+    let len = socket.recv(&mut buf).await?;
+    if !is_truncated(&buf[..len]) {
+        return Ok(buf[..len].to_vec());
+    }
+    // over TCP, each message is prefixed with its length
+    let mut stream = TcpStream::connect((nameserver, 53)).await?;
+    let query_len = u16::try_from(query.len())?;
+    stream.write_all(&query_len.to_be_bytes()).await?;
+    stream.write_all(query).await?;
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf).await?;
+    let mut response = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Gets the first nameserver in `/etc/resolv.conf`, if there is one. Other systems keep their
+/// nameservers elsewhere, so addresses there are only ever looked up without SRV records
+fn system_nameserver() -> Option<IpAddr> {
+    fs::read_to_string("/etc/resolv.conf")
+        .ok()?
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|addr| IpAddr::from_str(addr.trim()).ok())
+}
+
+/// Builds a recursive DNS query for the SRV records of a name
+fn build_query(id: u16, name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend(id.to_be_bytes());
+    query.extend([0x01, 0x00]); // recursion desired
+    query.extend([0, 1, 0, 0, 0, 0, 0, 0]); // one question
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let len = u8::try_from(label.len())
+            .ok()
+            .filter(|len| *len < 64)
+            .ok_or_else(|| anyhow!("DNS label \"{}\" is too long", label))?;
+        query.push(len);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(SRV_RECORD_TYPE.to_be_bytes());
+    query.extend(1u16.to_be_bytes()); // internet class
+    Ok(query)
+}
+
+/// Whether a DNS response had to be cut short to fit in a datagram
+fn is_truncated(msg: &[u8]) -> bool {
+    msg.len() > 2 && msg[2] & 0x02 != 0
+}
+
+/// An SRV record's target
+#[derive(Clone, Debug, PartialEq)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+/// Reads the SRV records from the answer to the query with the given id. A name that does not
+/// exist has no records
+fn parse_srv_response(id: u16, msg: &[u8]) -> anyhow::Result<Vec<SrvRecord>> {
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        bail!("Malformed DNS response");
+    }
+    match msg[3] & 0x0F {
+        0 => {}
+        3 => return Ok(vec![]),
+        rcode => bail!("DNS query failed with response code {}", rcode),
+    }
+    let question_count = u16::from_be_bytes([msg[4], msg[5]]);
+    let answer_count = u16::from_be_bytes([msg[6], msg[7]]);
+    let mut ind = 12;
+    for _ in 0..question_count {
+        ind = skip_name(msg, ind)? + 4;
+    }
+    let mut records = vec![];
+    for _ in 0..answer_count {
+        ind = skip_name(msg, ind)?;
+        let fixed = msg
+            .get(ind..ind + 10)
+            .ok_or_else(|| anyhow!("Truncated DNS answer"))?;
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let data_len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        ind += 10;
+        let data = msg
+            .get(ind..ind + data_len)
+            .ok_or_else(|| anyhow!("Truncated DNS answer"))?;
+        if record_type == SRV_RECORD_TYPE && data.len() >= 7 {
+            records.push(SrvRecord {
+                priority: u16::from_be_bytes([data[0], data[1]]),
+                weight: u16::from_be_bytes([data[2], data[3]]),
+                port: u16::from_be_bytes([data[4], data[5]]),
+                target: read_name(msg, ind + 6)?,
+            });
+        }
+        ind += data_len;
+    }
+    Ok(records)
+}
+
+/// Picks the record to connect to as RFC 2782 describes: one with the lowest priority, chosen
+/// at random in proportion to the weights given `roll`, a random number
+fn choose_srv(records: &[SrvRecord], roll: u32) -> Option<&SrvRecord> {
+    let priority = records.iter().map(|record| record.priority).min()?;
+    let candidates = records
+        .iter()
+        .filter(|record| record.priority == priority)
+        .collect::<Vec<_>>();
+    let total_weight = candidates
+        .iter()
+        .map(|record| record.weight as u32)
+        .sum::<u32>();
+    if total_weight == 0 {
+        return candidates.first().copied();
+    }
+    let mut roll = roll % total_weight;
+    for record in candidates {
+        if roll < record.weight as u32 {
+            return Some(record);
+        }
+        roll -= record.weight as u32;
+    }
+    unreachable!()
+}
+
+/// Gets the index just past a possibly compressed name
+fn skip_name(msg: &[u8], mut ind: usize) -> anyhow::Result<usize> {
+    loop {
+        let len = *msg.get(ind).ok_or_else(|| anyhow!("Truncated DNS name"))?;
+        match len {
+            0 => return Ok(ind + 1),
+            // a pointer ends the name
+            len if len & 0xC0 == 0xC0 => return Ok(ind + 2),
+            len => ind += 1 + len as usize,
+        }
+    }
+}
+
+/// Reads a possibly compressed name
+fn read_name(msg: &[u8], mut ind: usize) -> anyhow::Result<String> {
+    let mut labels = vec![];
+    // bounds the pointers followed, so a pointer loop can't hang
+    for _ in 0..128 {
+        let len = *msg.get(ind).ok_or_else(|| anyhow!("Truncated DNS name"))?;
+        if len == 0 {
+            return Ok(labels.join("."));
+        }
+        if len & 0xC0 == 0xC0 {
+            let low = *msg
+                .get(ind + 1)
+                .ok_or_else(|| anyhow!("Truncated DNS name"))?;
+            ind = (((len & 0x3F) as usize) << 8) | low as usize;
+            continue;
+        }
+        let label = msg
+            .get(ind + 1..ind + 1 + len as usize)
+            .ok_or_else(|| anyhow!("Truncated DNS name"))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        ind += 1 + len as usize;
+    }
+    bail!("DNS name has too many labels or a pointer loop")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "_minecraft._tcp.example.com";
+
+    fn srv_answer(priority: u16, weight: u16, port: u16, target: &str) -> Vec<u8> {
+        let mut target_name = build_query(0, target).unwrap()[12..].to_vec();
+        target_name.truncate(target_name.len() - 4);
+        // a pointer to the question's name
+        let mut answer = vec![0xC0, 12];
+        answer.extend(SRV_RECORD_TYPE.to_be_bytes());
+        answer.extend([0, 1, 0, 0, 0, 60]);
+        answer.extend((6 + target_name.len() as u16).to_be_bytes());
+        answer.extend(priority.to_be_bytes());
+        answer.extend(weight.to_be_bytes());
+        answer.extend(port.to_be_bytes());
+        answer.extend(target_name);
+        answer
+    }
+
+    fn response(id: u16, rcode: u8, answers: &[Vec<u8>]) -> Vec<u8> {
+        let mut msg = build_query(id, NAME).unwrap();
+        msg[2] = 0x81;
+        msg[3] = 0x80 | rcode;
+        msg[7] = answers.len() as u8;
+        for answer in answers {
+            msg.extend(answer);
+        }
+        msg
+    }
+
+    fn record(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.into(),
+        }
+    }
+
+    #[test]
+    fn reads_srv_records() {
+        let msg = response(
+            7,
+            0,
+            &[
+                srv_answer(10, 5, 25566, "a.example.com"),
+                srv_answer(20, 0, 25567, "b.example.com"),
+            ],
+        );
+        assert_eq!(
+            parse_srv_response(7, &msg).unwrap(),
+            vec![
+                record(10, 5, 25566, "a.example.com"),
+                record(20, 0, 25567, "b.example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_name_has_no_records() {
+        assert!(parse_srv_response(7, &response(7, 3, &[]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_other_query_ids_and_failures() {
+        assert!(parse_srv_response(8, &response(7, 0, &[])).is_err());
+        assert!(parse_srv_response(7, &response(7, 2, &[])).is_err());
+        assert!(parse_srv_response(7, &[0, 7, 0x81]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_answer() {
+        let mut msg = response(7, 0, &[srv_answer(10, 5, 25566, "a.example.com")]);
+        msg.truncate(msg.len() - 3);
+        assert!(parse_srv_response(7, &msg).is_err());
+    }
+
+    #[test]
+    fn detects_truncated_response() {
+        let mut msg = response(7, 0, &[]);
+        assert!(!is_truncated(&msg));
+        msg[2] |= 0x02;
+        assert!(is_truncated(&msg));
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        let msg = response(7, 0, &[]);
+        assert_eq!(read_name(&msg, 12).unwrap(), NAME);
+        let mut msg = msg;
+        let pointer = msg.len();
+        msg.extend([4, b'm', b'a', b'i', b'n', 0xC0, 12 + 11]);
+        assert_eq!(read_name(&msg, pointer).unwrap(), "main._tcp.example.com");
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let mut msg = response(7, 0, &[]);
+        let pointer = msg.len();
+        msg.extend([0xC0, pointer as u8]);
+        assert!(read_name(&msg, pointer).is_err());
+    }
+
+    #[test]
+    fn chooses_lowest_priority_by_weight() {
+        let records = vec![
+            record(20, 100, 1, "backup"),
+            record(10, 1, 2, "light"),
+            record(10, 3, 3, "heavy"),
+        ];
+        assert_eq!(choose_srv(&records, 0).unwrap().target, "light");
+        assert_eq!(choose_srv(&records, 1).unwrap().target, "heavy");
+        assert_eq!(choose_srv(&records, 3).unwrap().target, "heavy");
+        assert_eq!(choose_srv(&records, 4).unwrap().target, "light");
+        assert!(choose_srv(&[], 0).is_none());
+    }
+
+    #[test]
+    fn chooses_first_of_weightless_records() {
+        let records = vec![record(10, 0, 1, "first"), record(10, 0, 2, "second")];
+        assert_eq!(choose_srv(&records, 5).unwrap().target, "first");
+    }
+}
@@ -1,52 +1,325 @@
 use std::{
-    collections::HashSet,
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
     net::{
+        Shutdown,
         SocketAddr,
         TcpStream,
     },
-    sync::atomic::AtomicBool,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use async_compat::CompatExt;
 use async_dup::Arc as AsyncArc;
-use craftio_rs::CraftConnection;
-use mcproto_rs::protocol::PacketDirection;
+use craftio_rs::{
+    CraftAsyncReader,
+    CraftAsyncWriter,
+    CraftConnection,
+    CraftIo,
+};
+use futures_lite::AsyncWriteExt;
+use mcproto_rs::protocol::{
+    PacketDirection,
+    State,
+};
 use smol::{
     lock::Mutex,
     Async,
 };
 
-use crate::protocol::{
-    current::uuid::UUID4,
-    AsyncCraftConnection,
-    AsyncCraftReader,
-    AsyncCraftWriter,
+use crate::{
+    protocol::{
+        current::{
+            proto::{
+                HandshakeNextState,
+                HandshakeSpec,
+                PlayDeclareCommandsSpec,
+                StatusRequestSpec,
+            },
+            protocol::HasPacketKind,
+            status::StatusSpec,
+            uuid::UUID4,
+            PacketLatest,
+            PacketLatestKind,
+            RawPacketLatest,
+        },
+        AsyncCraftConnection,
+        AsyncCraftReader,
+        AsyncCraftWriter,
+        v_cur::{self, BossBars, ServerDimension, WorldBorderState},
+    },
+    proxy::{logging::LogSpan, proxyprotocol},
+    systems::keepalive::unix_time_millis,
 };
 
 #[derive(Clone)]
 pub struct SplinterServer {
     pub id: u64,
     pub address: SocketAddr,
+    /// Number of connected sockets kept ready for new connections to this server
+    pub pool_size: usize,
+    /// Sockets connected ahead of time, with when they were connected
+    pub pool: Arc<Mutex<VecDeque<(Instant, Async<TcpStream>)>>>,
+    /// Whether sockets to this server send each write immediately rather than coalescing them
+    pub nodelay: bool,
+    /// Whether connections to this server start with a PROXY protocol v2 header
+    pub send_proxy_protocol: bool,
+    /// Largest packet in bytes read from this server
+    pub max_packet_size: usize,
+    /// Entered while handling packets from this server, so their log lines name it
+    pub log_span: LogSpan,
 }
+
+/// How long a pooled socket is kept before it is assumed stale. Servers drop connections that
+/// go too long without a handshake, so this is kept well below that
+const POOLED_SOCKET_LIFETIME: Duration = Duration::from_secs(10);
+
 impl SplinterServer {
+    pub fn new(
+        id: u64,
+        address: SocketAddr,
+        pool_size: usize,
+        nodelay: bool,
+        send_proxy_protocol: bool,
+        max_packet_size: usize,
+    ) -> Self {
+        Self {
+            id,
+            address,
+            pool_size,
+            pool: Arc::new(Mutex::new(VecDeque::with_capacity(pool_size))),
+            nodelay,
+            send_proxy_protocol,
+            max_packet_size,
+            log_span: LogSpan::server(id),
+        }
+    }
+    /// Opens a new socket to this server
+    async fn open_socket(&self) -> anyhow::Result<Async<TcpStream>> {
+        let stream = Async::<TcpStream>::connect(self.address).await?;
+        stream.get_ref().set_nodelay(self.nodelay)?;
+        Ok(stream)
+    }
+    /// Takes a fresh socket from the pool, or opens a new one if there is none
+    async fn take_socket(&self) -> anyhow::Result<Async<TcpStream>> {
+        let mut pool = self.pool.lock().await;
+        while let Some((connected_at, stream)) = pool.pop_front() {
+            if connected_at.elapsed() < POOLED_SOCKET_LIFETIME {
+                return Ok(stream);
+            }
+        }
+        drop(pool);
+        self.open_socket().await
+    }
+    /// Drops stale sockets from the pool and connects new ones until it is full
+    pub async fn refill_pool(&self) -> anyhow::Result<()> {
+        self.pool
+            .lock()
+            .await
+            .retain(|(connected_at, _)| connected_at.elapsed() < POOLED_SOCKET_LIFETIME);
+        while self.pool.lock().await.len() < self.pool_size {
+            let stream = self.open_socket().await?;
+            self.pool.lock().await.push_back((Instant::now(), stream));
+        }
+        Ok(())
+    }
+    /// Connects to this server for the proxy itself rather than for a client
     pub async fn connect(&self) -> anyhow::Result<AsyncCraftConnection> {
-        let arc_stream = AsyncArc::new(Async::<TcpStream>::connect(self.address).await?);
+        Ok(self.connect_stream(None).await?.0)
+    }
+    /// Connects to this server on behalf of the client at the given address, or the proxy itself
+    /// if `None`, also returning the underlying stream so that it can be shut down separately
+    /// from the reader and writer
+    pub async fn connect_stream(
+        &self,
+        client_addr: Option<SocketAddr>,
+    ) -> anyhow::Result<(AsyncCraftConnection, AsyncArc<Async<TcpStream>>)> {
+        let mut stream = self.take_socket().await?;
+        if self.send_proxy_protocol {
+            // nothing has been written to the socket yet, pooled or not, so the header comes
+            // first
+            let header = proxyprotocol::encode_header(client_addr.map(|addr| (addr, self.address)));
+            stream.write_all(&header).await?;
+        }
+        let arc_stream = AsyncArc::new(stream);
         let (reader, writer) = (
             AsyncArc::clone(&arc_stream).compat(),
             AsyncArc::clone(&arc_stream).compat(),
         );
-        let conn = CraftConnection::from_async((reader, writer), PacketDirection::ClientBound);
-        Ok(conn)
+        let mut conn = CraftConnection::from_async((reader, writer), PacketDirection::ClientBound);
+        conn.set_max_packet_size(self.max_packet_size);
+        Ok((conn, arc_stream))
+    }
+    /// Connects to this server and wraps the connection up for use by the client at the given
+    /// address
+    pub async fn open_connection(
+        &self,
+        client_addr: SocketAddr,
+    ) -> anyhow::Result<SplinterServerConnection> {
+        let (conn, stream) = self.connect_stream(Some(client_addr)).await?;
+        let (reader, writer) = conn.into_split();
+        Ok(SplinterServerConnection {
+            writer: Mutex::new(writer),
+            reader: Mutex::new(reader),
+            stream,
+            server: self.clone(),
+            alive: AtomicBool::new(true),
+            last_activity: Mutex::new(unix_time_millis()),
+            eid: -1,
+            uuid: UUID4::from(0u128),
+            known_chunks: Mutex::new(HashSet::new()),
+            replayed_state: Mutex::new(ReplayedState::default()),
+            dimension: Mutex::new(None),
+            boss_bars: Mutex::new(HashMap::new()),
+            objectives: Mutex::new(HashMap::new()),
+            declared_commands: Mutex::new(None),
+            disconnect_relayed: AtomicBool::new(false),
+        })
+    }
+    /// Pings this server for its status, as a client's server list would
+    pub async fn query_status(&self, protocol: i32) -> anyhow::Result<StatusSpec> {
+        let mut conn = self.connect().await?;
+        conn.write_packet_async(PacketLatest::Handshake(HandshakeSpec {
+            version: protocol.into(),
+            server_address: format!("{}", self.address.ip()),
+            server_port: self.address.port(),
+            next_state: HandshakeNextState::Status,
+        }))
+        .await?;
+        conn.set_state(State::Status);
+        conn.write_packet_async(PacketLatest::StatusRequest(StatusRequestSpec))
+            .await?;
+        match conn.read_packet_async::<RawPacketLatest>().await? {
+            Some(PacketLatest::StatusResponse(body)) => Ok(body.response),
+            Some(other) => bail!("Expected a status response; instead got: {:?}", other),
+            None => bail!("Server {} closed connection during status query", self.id),
+        }
     }
 }
 
 pub struct SplinterServerConnection {
     pub writer: Mutex<AsyncCraftWriter>,
     pub reader: Mutex<AsyncCraftReader>,
+    pub stream: AsyncArc<Async<TcpStream>>,
     pub server: SplinterServer,
     pub alive: AtomicBool,
+    /// Unix time in milliseconds of the last packet read from or written to this connection
+    pub last_activity: Mutex<u128>,
 
     pub eid: i32,
     pub uuid: UUID4,
     pub known_chunks: Mutex<HashSet<(i32, i32)>>,
+    pub replayed_state: Mutex<ReplayedState>,
+    /// World the server has placed the player in, once the server has said
+    pub dimension: Mutex<Option<ServerDimension>>,
+    pub boss_bars: Mutex<BossBars>,
+    /// Proxy-side names of the scoreboard objectives the server has created, by server-side name
+    pub objectives: Mutex<HashMap<String, String>>,
+    /// Command tree the server last declared, before the proxy's commands were added to it
+    pub declared_commands: Mutex<Option<PlayDeclareCommandsSpec>>,
+    /// Whether a disconnect from the server has been relayed to the client, in which case the
+    /// server closing the connection is a kick rather than a failure
+    pub disconnect_relayed: AtomicBool,
+}
+impl SplinterServerConnection {
+    /// Marks this connection as no longer alive and shuts down its socket, waking up anything
+    /// waiting to read from it
+    pub fn close(&self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Err(e) = self.stream.get_ref().shutdown(Shutdown::Both) {
+            debug!("Failed to shut down connection to server {}: {}", self.server.id, e);
+        }
+    }
+    pub async fn mark_activity(&self) {
+        *self.last_activity.lock().await = unix_time_millis();
+    }
+    /// Remembers the packet as part of the state replayed to the client on a server switch
+    pub async fn record_state(&self, packet: &PacketLatest) {
+        self.replayed_state.lock().await.record(packet);
+    }
+}
+
+/// Packet kinds that can be replayed to the client on a server switch, by name. Each is state
+/// that a newer packet of the same kind entirely replaces
+pub const REPLAYABLE_KINDS: &[(&str, PacketLatestKind)] = &[
+    ("PlaySetExperience", PacketLatestKind::PlaySetExperience),
+    ("PlayUpdateHealth", PacketLatestKind::PlayUpdateHealth),
+    ("PlayServerHeldItemChange", PacketLatestKind::PlayServerHeldItemChange),
+    ("PlayServerDifficulty", PacketLatestKind::PlayServerDifficulty),
+    ("PlayServerPlayerAbilities", PacketLatestKind::PlayServerPlayerAbilities),
+    ("PlaySpawnPosition", PacketLatestKind::PlaySpawnPosition),
+    ("PlayTimeUpdate", PacketLatestKind::PlayTimeUpdate),
+];
+
+/// Looks up the packet kind with the given name in a table of kinds by name
+pub fn kind_by_name(kinds: &[(&str, PacketLatestKind)], name: &str) -> Option<PacketLatestKind> {
+    kinds
+        .iter()
+        .find(|(kind_name, _)| *kind_name == name)
+        .map(|(_, kind)| *kind)
+}
+
+/// Looks up the replayable packet kinds with the given names, skipping unknown names
+pub fn replayed_kinds(names: &[String]) -> Vec<PacketLatestKind> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let kind = kind_by_name(REPLAYABLE_KINDS, name);
+            if kind.is_none() {
+                warn!("\"{}\" is not a packet kind that can be replayed", name);
+            }
+            kind
+        })
+        .collect()
+}
+
+/// The latest packet of each replayed kind a server has sent. Since only the active connection's
+/// packets reach the client, these are sent to the client when a connection becomes active so it
+/// doesn't keep showing the previous server's state, ex. health or whether the player may fly
+#[derive(Default)]
+pub struct ReplayedState {
+    packets: Vec<PacketLatest>,
+    /// The world border's packets each change part of it, so they are merged instead
+    world_border: Option<WorldBorderState>,
+}
+impl ReplayedState {
+    /// Keeps the packet in place of the last packet of its kind
+    pub fn record(&mut self, packet: &PacketLatest) {
+        let kind = packet.kind();
+        if let PacketLatest::PlayInitializeWorldBorder(body) = packet {
+            self.world_border = Some(WorldBorderState::new(body.clone()));
+            return;
+        }
+        if v_cur::WORLD_BORDER_KINDS.contains(&kind) {
+            // changes before the border is initialized have nothing to apply to
+            if let Some(world_border) = &mut self.world_border {
+                world_border.update(packet);
+            }
+            return;
+        }
+        match self.packets.iter_mut().find(|existing| existing.kind() == kind) {
+            Some(existing) => *existing = packet.clone(),
+            None => self.packets.push(packet.clone()),
+        }
+    }
+    pub fn packets(&self) -> Vec<PacketLatest> {
+        let mut packets = self.packets.clone();
+        if let Some(world_border) = &self.world_border {
+            packets.push(world_border.initialize_packet());
+        }
+        packets
+    }
 }
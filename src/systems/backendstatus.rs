@@ -0,0 +1,93 @@
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use futures_lite::future;
+use smol::Timer;
+
+use crate::{
+    proxy::{server::SplinterServer, SplinterProxy},
+    systems::SplinterSystem,
+};
+
+/// How long a server has to answer a status query before it is counted as unreachable
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Backend Status",
+        init: Box::new(|proxy| {
+            Box::pin(async move {
+                smol::spawn(async move {
+                    if let Err(e) = backend_status_loop(proxy).await {
+                        error!("Backend Status encountered an error: {:?}", e);
+                    }
+                }).detach();
+                Ok(())
+            })
+        }),
+    }
+}
+
+pub async fn backend_status_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    loop {
+        if !proxy.alive.load(Ordering::Relaxed) {
+            break;
+        }
+        let servers = proxy
+            .servers
+            .read()
+            .await
+            .iter()
+            .map(|(_, server)| Arc::clone(server))
+            .collect::<Vec<_>>();
+        let server_ids = servers
+            .iter()
+            .map(|server| server.id)
+            .collect::<HashSet<u64>>();
+        // queried all at once, so that one slow server doesn't hold up the others' counts
+        let queries = servers
+            .into_iter()
+            .map(|server| smol::spawn(update_player_count(Arc::clone(&proxy), server)))
+            .collect::<Vec<_>>();
+        for query in queries {
+            query.await;
+        }
+        // servers removed since the last pass have no players to count
+        proxy
+            .backend_player_counts
+            .lock()
+            .await
+            .retain(|server_id, _| server_ids.contains(server_id));
+        Timer::after(Duration::from_secs(proxy.config().backend_status_interval)).await;
+    }
+    Ok(())
+}
+
+async fn update_player_count(proxy: Arc<SplinterProxy>, server: Arc<SplinterServer>) {
+    let status = future::or(
+        async { Some(server.query_status(proxy.config().protocol).await) },
+        async {
+            Timer::after(STATUS_TIMEOUT).await;
+            None
+        },
+    )
+    .await
+    .unwrap_or_else(|| Err(anyhow!("Timed out")));
+    match status {
+        Ok(status) => {
+            proxy
+                .backend_player_counts
+                .lock()
+                .await
+                .insert(server.id, status.players.online);
+        }
+        Err(e) => {
+            // an unreachable server has no players to count
+            proxy.backend_player_counts.lock().await.remove(&server.id);
+            debug!("Failed to query status of server {}: {:?}", server.id, e);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::{
+        mapping::uuid_from_name, playerlist::BANLIST_FILENAME, ClientKickReason, SplinterProxy,
+    },
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "ban",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let (name, reason) = match args.split_first() {
+                Some((name, [])) => (*name, format!("Banned by {}", sender.name())),
+                Some((name, reason)) => (*name, reason.join(" ")),
+                None => bail!("Expected a player name"),
+            };
+            {
+                let banlist = &mut *smol::block_on(proxy.banlist.write());
+                banlist.add(name, uuid_from_name(name), Some(reason.clone()));
+                banlist.save(BANLIST_FILENAME)?;
+            }
+            info!("{} banned \"{}\": {}", sender.name(), name, &reason);
+            if smol::block_on(proxy.players.read()).contains_key(name) {
+                smol::block_on(proxy.kick_client(name, ClientKickReason::Banned(reason)))?;
+            }
+            if let Err(e) = sender.respond_sync(format!("Banned \"{}\"", name)) {
+                error!("Failed to send ban response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
+
+inventory::submit! {
+    SplinterCommand {
+        name: "pardon",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let name = match args {
+                [name] => *name,
+                _ => bail!("Expected a player name"),
+            };
+            {
+                let banlist = &mut *smol::block_on(proxy.banlist.write());
+                if !banlist.remove(name, uuid_from_name(name)) {
+                    bail!("\"{}\" is not banned", name);
+                }
+                banlist.save(BANLIST_FILENAME)?;
+            }
+            info!("{} pardoned \"{}\"", sender.name(), name);
+            if let Err(e) = sender.respond_sync(format!("Pardoned \"{}\"", name)) {
+                error!("Failed to send pardon response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
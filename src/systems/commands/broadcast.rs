@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use crate::{
+    protocol::current::proto::ChatPosition,
+    proxy::{chat::format_chat_message, SplinterProxy},
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "broadcast",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            // `broadcast --actionbar <message>` shows the message above the hotbar instead
+            let (position, words) = match args.split_first() {
+                Some((&"--actionbar", rest)) => (ChatPosition::GameInfo, rest),
+                _ => (ChatPosition::ChatBox, args),
+            };
+            if words.is_empty() {
+                bail!("Expected a message");
+            }
+            let message = format_chat_message(sender, words.join(" "));
+            let clients = smol::block_on(proxy.players.read())
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut count = 0;
+            for client in clients {
+                match smol::block_on(client.write_chat(message.clone(), position, sender.uuid())) {
+                    Ok(()) => count += 1,
+                    Err(e) => error!("Failed to send broadcast to \"{}\": {}", &client.name, e),
+                }
+            }
+            if let Err(e) = sender.respond_sync(format!("Broadcast to {} players", count)) {
+                error!("Failed to send broadcast response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::{resolve::resolve_server_address, SplinterProxy},
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "connect",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            if !proxy.config().allow_adhoc_servers {
+                bail!("Connecting to servers outside of the config is disabled");
+            }
+            if args.len() != 2 {
+                bail!("Expected a player name and an address");
+            }
+            let client = smol::block_on(proxy.players.read())
+                .get(args[0])
+                .map(Arc::clone)
+                .ok_or_else(|| anyhow!("Failed to find player \"{}\"", args[0]))?;
+            let address = smol::block_on(resolve_server_address(args[1]))
+                .map_err(|e| anyhow!("Failed to resolve \"{}\": {}", args[1], e))?;
+            let server_id = smol::block_on(proxy.add_adhoc_server(address));
+            // keep zoning from moving the player back off of the new server
+            client.pinned_server.store(Arc::new(Some(server_id)));
+            if let Err(e) = smol::block_on(client.switch_server(server_id)) {
+                client.pinned_server.store(Arc::new(None));
+                return Err(e);
+            }
+            if let Err(e) = sender.respond_sync(format!(
+                "Connected \"{}\" to {} as server {}",
+                &client.name, address, server_id
+            )) {
+                error!("Failed to send connect response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
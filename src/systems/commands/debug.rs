@@ -0,0 +1,107 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::{
+    proxy::{client::SplinterClient, SplinterProxy},
+    systems::{
+        commands::{CommandSender, SplinterCommand},
+        keepalive::unix_time_millis,
+    },
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "debug",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let msg = match args.get(0) {
+                Some(&"packets") => {
+                    let client = find_player(proxy, args.get(1))?;
+                    let enabled = match args.get(2) {
+                        Some(&"on") => true,
+                        Some(&"off") => false,
+                        _ => bail!("Expected \"on\" or \"off\""),
+                    };
+                    client.trace_packets.store(enabled, Ordering::Relaxed);
+                    format!(
+                        "Packet tracing for \"{}\" is {}",
+                        &client.name,
+                        if enabled { "on" } else { "off" }
+                    )
+                }
+                Some(&"conns") => {
+                    let client = find_player(proxy, args.get(1))?;
+                    match args.get(2) {
+                        Some(&"drop") => {
+                            let target_id = args
+                                .get(3)
+                                .ok_or_else(|| anyhow!("Expected a server id"))?
+                                .parse::<u64>()
+                                .map_err(|_| anyhow!("Server id must be a number"))?;
+                            smol::block_on(client.drop_connection(target_id))?;
+                            format!(
+                                "Dropped connection from \"{}\" to server {}, active server is now {}",
+                                &client.name,
+                                target_id,
+                                client.server_id()
+                            )
+                        }
+                        Some(other) => bail!("Unknown conns subcommand \"{}\"", other),
+                        None => {
+                            let now = unix_time_millis();
+                            let active = client.active_server.load_full();
+                            let mut conns = vec![("active", active)];
+                            for (_, conn) in client.dummy_servers.load().iter() {
+                                conns.push(("dummy", Arc::clone(conn)));
+                            }
+                            let mut msg = format!("Connections of \"{}\":", &client.name);
+                            for (kind, conn) in conns {
+                                let last_activity = *smol::block_on(conn.last_activity.lock());
+                                msg.push_str(&format!(
+                                    "\n  server {} ({}): {}, last activity {}ms ago",
+                                    conn.server.id,
+                                    kind,
+                                    if conn.alive.load(Ordering::Relaxed) {
+                                        "alive"
+                                    } else {
+                                        "dead"
+                                    },
+                                    now.saturating_sub(last_activity)
+                                ));
+                            }
+                            msg
+                        }
+                    }
+                }
+                Some(&"limbo") => {
+                    let client = find_player(proxy, args.get(1))?;
+                    match args.get(2) {
+                        Some(target_id) => {
+                            let target_id = target_id
+                                .parse::<u64>()
+                                .map_err(|_| anyhow!("Server id must be a number"))?;
+                            smol::block_on(client.leave_limbo(target_id))?;
+                            format!("Moved \"{}\" out of limbo to server {}", &client.name, target_id)
+                        }
+                        None => {
+                            smol::block_on(client.send_to_limbo())?;
+                            format!("Sent \"{}\" to limbo", &client.name)
+                        }
+                    }
+                }
+                Some(other) => bail!("Unknown debug subcommand \"{}\"", other),
+                None => bail!("Expected a debug subcommand"),
+            };
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send debug response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
+
+fn find_player(proxy: &SplinterProxy, name: Option<&&str>) -> anyhow::Result<Arc<SplinterClient>> {
+    let name = name.ok_or_else(|| anyhow!("Expected a player name"))?;
+    smol::block_on(proxy.players.read())
+        .get(*name)
+        .map(Arc::clone)
+        .ok_or_else(|| anyhow!("Failed to find player \"{}\"", name))
+}
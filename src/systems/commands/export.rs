@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::{
+        commands::{CommandSender, SplinterCommand},
+        playersave::export_player_data,
+    },
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "export",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            if args.is_empty() {
+                bail!("Expected a path to export player data to");
+            }
+            let path = args.join(" ");
+            smol::block_on(async {
+                // include where connected players are right now
+                let players = proxy
+                    .players
+                    .read()
+                    .await
+                    .values()
+                    .map(Arc::clone)
+                    .collect::<Vec<_>>();
+                for client in players {
+                    client.store_player_info().await;
+                }
+                let count = export_player_data(&*proxy.player_data.lock().await, &path)?;
+                if let Err(e) = sender.respond(format!("Exported {} players to \"{}\"", count, &path)).await {
+                    error!("Failed to send export response to {}: {}", sender.name(), e);
+                }
+                Ok(())
+            })
+        }),
+    }
+}
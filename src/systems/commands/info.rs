@@ -0,0 +1,55 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "info",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, _args: &[&str], sender: &CommandSender| {
+            // one line of key=value pairs, so scripts reading it over rcon can split it up
+            let server_ids = smol::block_on(proxy.servers.read())
+                .keys()
+                .copied()
+                .collect::<Vec<u64>>();
+            // servers answering status queries are the ones with a player count
+            let reachable = {
+                let counts = smol::block_on(proxy.backend_player_counts.lock());
+                server_ids.iter().filter(|id| counts.contains_key(id)).count()
+            };
+            let players = smol::block_on(proxy.players.read()).len();
+            let config = proxy.config();
+            let allowed_versions = if server_ids
+                .iter()
+                .all(|id| config.server_allowed_versions.contains_key(id))
+            {
+                server_ids
+                    .iter()
+                    .flat_map(|id| config.server_allowed_versions[id].iter().copied())
+                    .collect::<BTreeSet<i32>>()
+                    .iter()
+                    .map(i32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            } else {
+                "any".to_owned()
+            };
+            let msg = format!(
+                "version={} uptime={} servers={} reachable={} players={} protocol={} allowed_versions={}",
+                env!("CARGO_PKG_VERSION"),
+                proxy.uptime().as_secs(),
+                server_ids.len(),
+                reachable,
+                players,
+                proxy.config().protocol,
+                allowed_versions,
+            );
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send info response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
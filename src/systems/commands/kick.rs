@@ -17,6 +17,28 @@ inventory::submit! {
             if args.is_empty() {
                 bail!("Expected at least one argument");
             }
+            // `kick server <id> [reason]` kicks everyone on a server
+            if let (Some(&"server"), Some(Ok(server_id))) = (args.get(0), args.get(1).map(|id| id.parse::<u64>())) {
+                let message = if args.len() > 2 { Some(args[2..].join(" ")) } else { None };
+                let names = smol::block_on(proxy.players.read())
+                    .values()
+                    .filter(|client| client.server_id() == server_id)
+                    // don't kick whoever is running the command
+                    .filter(|client| client.name != sender.name() || !matches!(sender, CommandSender::Player(_)))
+                    .map(|client| client.name.clone())
+                    .collect::<Vec<String>>();
+                let mut count = 0;
+                for name in names {
+                    match smol::block_on(proxy.kick_client(&name, ClientKickReason::Kicked(sender.name(), message.clone()))) {
+                        Ok(()) => count += 1,
+                        Err(e) => error!("Failed to kick \"{}\" from server {}: {:?}", &name, server_id, e),
+                    }
+                }
+                if let Err(e) = sender.respond_sync(format!("Kicked {} players from server {}", count, server_id)) {
+                    error!("Failed to send kick response to {}: {}", sender.name(), e);
+                }
+                return Ok(());
+            }
             let mut arg_iter = args.iter();
             let name = arg_iter.next().unwrap();
             let message = if args.len() > 1 {
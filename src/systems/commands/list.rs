@@ -11,20 +11,29 @@ inventory::submit! {
     SplinterCommand {
         name: "list",
         action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, _args: &[&str], sender: &CommandSender| {
-            let players = smol::block_on(proxy.players.read());
-            let msg = format!(
-                "{}/{} players: {}",
-                players.len(),
-                match proxy.config.max_players {
+            // copy the clients out so the player list isn't held while responding
+            let mut clients = smol::block_on(proxy.players.read())
+                .values()
+                .map(Arc::clone)
+                .collect::<Vec<_>>();
+            clients.sort_by(|a, b| a.name.cmp(&b.name));
+            let mut msg = format!(
+                "{}/{} players",
+                clients.len(),
+                match proxy.config().max_players {
                     Some(players) => players.to_string(),
                     None => "--".into(),
                 },
-                players
-                    .iter()
-                    .map(|(name, _)| name.to_owned())
-                    .reduce(|a, b| format!("{}, {}", a, b))
-                    .unwrap_or_else(String::new),
             );
+            for client in clients.iter() {
+                msg.push_str(&format!(
+                    "\n{} ({}): server {}, {} connections",
+                    client.name,
+                    client.uuid,
+                    client.server_id(),
+                    client.connected_servers().len(),
+                ));
+            }
             if let Err(e) = sender.respond_sync(msg) {
                 error!(
                     "Failed to send player list response to {}: {}",
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "maintenance",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let enabled = match args.get(0) {
+                Some(&"on") => true,
+                Some(&"off") => false,
+                Some(other) => bail!("Expected \"on\" or \"off\", got \"{}\"", other),
+                None => {
+                    let msg = format!(
+                        "Maintenance mode is {}",
+                        if proxy.in_maintenance() { "on" } else { "off" }
+                    );
+                    if let Err(e) = sender.respond_sync(msg) {
+                        error!("Failed to send maintenance response to {}: {}", sender.name(), e);
+                    }
+                    return Ok(());
+                }
+            };
+            smol::block_on(proxy.set_maintenance(enabled))?;
+            info!(
+                "{} turned maintenance mode {}",
+                sender.name(),
+                if enabled { "on" } else { "off" }
+            );
+            Ok(())
+        }),
+    }
+}
@@ -1,6 +1,12 @@
 use std::{
+    future::Future,
     io,
+    net::SocketAddr,
     sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use blocking::{
@@ -8,6 +14,7 @@ use blocking::{
     Unblock,
 };
 use mcproto_rs::uuid::UUID4;
+use smol::lock::Mutex;
 
 use crate::{
     proxy::{
@@ -18,14 +25,31 @@ use crate::{
     systems::SplinterSystem,
 };
 
+mod ban;
+mod broadcast;
+mod connect;
+mod debug;
+mod export;
+mod info;
 mod kick;
 mod list;
+mod maintenance;
+mod reconnect;
+mod reload;
+mod server;
+mod servers;
 mod stop;
 mod switch;
+mod tpserver;
+mod uptime;
+mod whitelist;
+mod whois;
 
 pub enum CommandSender {
     Player(Arc<SplinterClient>),
     Console,
+    /// A remote console client by address, with the output of the command it is running
+    Rcon(SocketAddr, Arc<Mutex<String>>),
 }
 
 impl CommandSender {
@@ -36,6 +60,14 @@ impl CommandSender {
                 info!("{}", msg.to_string());
                 Ok(())
             }
+            CommandSender::Rcon(_, output) => {
+                let output = &mut *output.lock().await;
+                if !output.is_empty() {
+                    output.push('\n');
+                }
+                output.push_str(&msg.to_string());
+                Ok(())
+            }
         }
     }
     pub fn respond_sync(&self, msg: impl ToChat + ToString) -> anyhow::Result<()> {
@@ -45,12 +77,13 @@ impl CommandSender {
         match self {
             CommandSender::Player(client) => client.name.to_owned(),
             CommandSender::Console => "console".into(),
+            CommandSender::Rcon(addr, _) => format!("rcon ({})", addr),
         }
     }
     pub fn uuid(&self) -> UUID4 {
         match self {
             CommandSender::Player(client) => client.uuid,
-            CommandSender::Console => UUID4::from(0u128),
+            CommandSender::Console | CommandSender::Rcon(..) => UUID4::from(0u128),
         }
     }
 }
@@ -60,10 +93,34 @@ impl Clone for CommandSender {
         match self {
             Self::Console => Self::Console,
             Self::Player(client) => Self::Player(Arc::clone(client)),
+            Self::Rcon(addr, output) => Self::Rcon(*addr, Arc::clone(output)),
         }
     }
 }
 
+/// Runs a command's work in the background, so a command that waits on the network doesn't hold
+/// up whatever ran it, then sends the sender what the work returned or why it failed. A remote
+/// console client has been answered by then, so it is only told about it in the log
+pub fn spawn_command(
+    sender: &CommandSender,
+    work: impl Future<Output = anyhow::Result<String>> + Send + 'static,
+) {
+    let sender = sender.clone();
+    smol::spawn(async move {
+        let msg = match work.await {
+            Ok(msg) => msg,
+            Err(e) => format!("Command failed: {:?}", e),
+        };
+        if let CommandSender::Rcon(addr, _) = &sender {
+            info!("Background command from rcon ({}): {}", addr, &msg);
+        }
+        if let Err(e) = sender.respond(msg).await {
+            error!("Failed to send command response to {}: {}", sender.name(), e);
+        }
+    })
+    .detach();
+}
+
 pub type CommandFn = Box<
     dyn Send + Sync + Fn(&Arc<SplinterProxy>, &str, &[&str], &CommandSender) -> anyhow::Result<()>,
 >;
@@ -84,13 +141,66 @@ pub async fn process_command(
         .into_iter()
         .find(|cmd_data| cmd_data.name.eq(cmd))
     {
+        if let Some(remaining) = check_cooldown(proxy, cmd, sender).await {
+            sender
+                .respond(format!(
+                    "Please wait {} seconds before using /{} again",
+                    remaining.as_secs() + 1,
+                    cmd
+                ))
+                .await?;
+            return Ok(());
+        }
         (cmd_data.action)(proxy, cmd, args, sender)?;
+        // a command that failed doesn't count as a use
+        record_command_use(proxy, cmd, sender).await;
     } else {
         bail!("Unknown command \"{}\"", cmd);
     }
     Ok(())
 }
 
+/// Checks whether the sender may use the command yet, returning how long they have left to wait
+/// if not. Uses are only tracked for players, so the consoles never wait
+async fn check_cooldown(
+    proxy: &SplinterProxy,
+    cmd: &str,
+    sender: &CommandSender,
+) -> Option<Duration> {
+    let cooldown = Duration::from_secs(*proxy.config().command_cooldowns.get(cmd)?);
+    let client = match sender {
+        CommandSender::Player(client) => client,
+        CommandSender::Console | CommandSender::Rcon(..) => return None,
+    };
+    let now = Instant::now();
+    let mut last_used = proxy.command_last_used.lock().await;
+    // forget uses that no longer hold anyone back, so this only grows with recent use
+    last_used.retain(|(_, cmd), used| {
+        proxy
+            .config()
+            .command_cooldowns
+            .get(cmd)
+            .map(|secs| now.duration_since(*used) < Duration::from_secs(*secs))
+            .unwrap_or(false)
+    });
+    let used = last_used.get(&(client.uuid, cmd.to_owned()))?;
+    Some(cooldown.saturating_sub(now.duration_since(*used)))
+}
+
+/// Starts the sender's cooldown for the command, if it has one
+async fn record_command_use(proxy: &SplinterProxy, cmd: &str, sender: &CommandSender) {
+    if !proxy.config().command_cooldowns.contains_key(cmd) {
+        return;
+    }
+    if let CommandSender::Player(client) = sender {
+        proxy
+            .command_last_used
+            .lock()
+            .await
+            .insert((client.uuid, cmd.to_owned()), Instant::now());
+    }
+}
+
 inventory::submit! {
     SplinterSystem {
         name: "Console Command Listener",
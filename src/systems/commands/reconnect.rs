@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{spawn_command, CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "reconnect",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            if args.len() != 1 {
+                bail!("Expected a player name");
+            }
+            let client = smol::block_on(proxy.players.read())
+                .get(args[0])
+                .map(Arc::clone)
+                .ok_or_else(|| anyhow!("Failed to find player \"{}\"", args[0]))?;
+            spawn_command(sender, async move {
+                client.reconnect().await?;
+                Ok(format!(
+                    "Reconnected \"{}\" to server {}",
+                    &client.name,
+                    client.server_id()
+                ))
+            });
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::{
+        config::{SplinterConfig, CONFIG_FILENAME},
+        SplinterProxy,
+    },
+    systems::commands::{spawn_command, CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "reload",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, _args: &[&str], sender: &CommandSender| {
+            let config = SplinterConfig::from_file(CONFIG_FILENAME)
+                // keep the whole chain so the offending key reaches the sender
+                .map_err(|e| anyhow!("{:#}", e))?;
+            let proxy = Arc::clone(proxy);
+            spawn_command(sender, async move {
+                let (added, removed) = proxy.reload_config(config).await;
+                info!("Reloaded config and simulation servers: {} added, {} removed", added, removed);
+                let (whitelisted, banned) = proxy.reload_player_lists().await?;
+                info!("Reloaded player lists: {} whitelisted, {} banned", whitelisted, banned);
+                Ok(format!(
+                    "Reloaded simulation servers: {} added, {} removed. Reloaded player lists: {} whitelisted, {} banned. Listen addresses, allowlists, zoning and replayed packets apply on restart",
+                    added, removed, whitelisted, banned
+                ))
+            });
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::{resolve::resolve_server_address, SplinterProxy},
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "server",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let msg = match args {
+                ["add", id, address] => {
+                    let server_id = id
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("Expected a server id, got \"{}\"", id))?;
+                    let address = smol::block_on(resolve_server_address(address))
+                        .map_err(|e| anyhow!("Failed to resolve \"{}\": {}", address, e))?;
+                    smol::block_on(proxy.add_server(server_id, address))?;
+                    format!("Added server {} at {}", server_id, address)
+                }
+                ["remove", id] => {
+                    let server_id = id
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("Expected a server id, got \"{}\"", id))?;
+                    smol::block_on(proxy.remove_server(server_id))?;
+                    format!("Removed server {}", server_id)
+                }
+                _ => bail!("Expected \"add <id> <address>\" or \"remove <id>\""),
+            };
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send server response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
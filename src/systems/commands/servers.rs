@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "servers",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let client = match (args.get(0), sender) {
+                (Some(name), _) => smol::block_on(proxy.players.read())
+                    .get(*name)
+                    .map(Arc::clone)
+                    .ok_or_else(|| anyhow!("Failed to find player \"{}\"", name))?,
+                (None, CommandSender::Player(client)) => Arc::clone(client),
+                (None, _) => bail!("Expected a player name"),
+            };
+            smol::block_on(client.open_server_selector())
+        }),
+    }
+}
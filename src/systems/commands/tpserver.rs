@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{spawn_command, CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "tp-server",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            if args.len() != 2 {
+                bail!("Expected a player name and a server id");
+            }
+            let client = smol::block_on(proxy.players.read())
+                .get(args[0])
+                .map(Arc::clone)
+                .ok_or_else(|| anyhow!("Failed to find player \"{}\"", args[0]))?;
+            let target_id = args[1]
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Server id must be a number"))?;
+            if !smol::block_on(proxy.servers.read()).contains_key(&target_id) {
+                bail!("There is no server with id {}", target_id);
+            }
+            spawn_command(sender, async move {
+                // keep zoning from moving the player back off of the server
+                let previous_pin = client.pinned_server.swap(Arc::new(Some(target_id)));
+                if let Err(e) = client.switch_server(target_id).await {
+                    client.pinned_server.store(previous_pin);
+                    return Err(e);
+                }
+                Ok(format!("Moved \"{}\" to server {}", &client.name, target_id))
+            });
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,28 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::{
+        commands::{CommandSender, SplinterCommand},
+        restart::format_duration,
+    },
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "uptime",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, _args: &[&str], sender: &CommandSender| {
+            let mut msg = format!("Uptime: {}", format_duration(proxy.uptime()));
+            if let Some(interval) = proxy.config().restart_interval {
+                msg.push_str(&format!(
+                    ", restarting in {}",
+                    format_duration(Duration::from_secs(interval).saturating_sub(proxy.uptime()))
+                ));
+            }
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send uptime response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
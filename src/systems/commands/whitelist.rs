@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use crate::{
+    proxy::{mapping::uuid_from_name, playerlist::WHITELIST_FILENAME, SplinterProxy},
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "whitelist",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            let msg = {
+                let whitelist = &mut *smol::block_on(proxy.whitelist.write());
+                let msg = match args {
+                    ["add", name] => {
+                        whitelist.add(name, uuid_from_name(name), None);
+                        format!("Added \"{}\" to the whitelist", name)
+                    }
+                    ["remove", name] => {
+                        if !whitelist.remove(name, uuid_from_name(name)) {
+                            bail!("\"{}\" is not whitelisted", name);
+                        }
+                        format!("Removed \"{}\" from the whitelist", name)
+                    }
+                    _ => bail!("Expected \"add <name>\" or \"remove <name>\""),
+                };
+                whitelist.save(WHITELIST_FILENAME)?;
+                msg
+            };
+            info!("{}: {}", sender.name(), &msg);
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send whitelist response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::commands::{CommandSender, SplinterCommand},
+};
+
+inventory::submit! {
+    SplinterCommand {
+        name: "whois",
+        action: Box::new(|proxy: &Arc<SplinterProxy>, _cmd: &str, args: &[&str], sender: &CommandSender| {
+            if args.len() != 1 {
+                bail!("Expected a player name");
+            }
+            let client = smol::block_on(proxy.players.read())
+                .get(args[0])
+                .map(Arc::clone)
+                .ok_or_else(|| anyhow!("Failed to find player \"{}\"", args[0]))?;
+            let pos = &**client.position.load();
+            let mut msg = format!(
+                "{} ({}, protocol {}): active server {}, connected to [{}], at ({:.1}, {:.1}, {:.1})",
+                &client.name,
+                client.uuid,
+                client.protocol_version,
+                client.server_id(),
+                client
+                    .connected_servers()
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                pos.x,
+                pos.y,
+                pos.z,
+            );
+            let entity_cap_hits = client.entity_cap_hits.load(Ordering::Relaxed);
+            if entity_cap_hits > 0 {
+                msg.push_str(&format!(", {} entity spawns dropped", entity_cap_hits));
+            }
+            if let Err(e) = sender.respond_sync(msg) {
+                error!("Failed to send whois response to {}: {}", sender.name(), e);
+            }
+            Ok(())
+        }),
+    }
+}
@@ -0,0 +1,48 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use smol::Timer;
+
+use crate::{proxy::SplinterProxy, systems::SplinterSystem};
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Connection Pool",
+        init: Box::new(|proxy| {
+            Box::pin(async move {
+                smol::spawn(async move {
+                    if let Err(e) = connection_pool_loop(proxy).await {
+                        error!("Connection Pool encountered an error: {:?}", e);
+                    }
+                }).detach();
+                Ok(())
+            })
+        }),
+    }
+}
+
+/// Keeps each server's pool of connected sockets full and fresh
+pub async fn connection_pool_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    loop {
+        if !proxy.alive.load(Ordering::Relaxed) {
+            break;
+        }
+        let servers = proxy
+            .servers
+            .read()
+            .await
+            .values()
+            .filter(|server| server.pool_size > 0)
+            .map(Arc::clone)
+            .collect::<Vec<_>>();
+        for server in servers {
+            if let Err(e) = server.refill_pool().await {
+                debug!("Failed to refill connection pool for server {}: {:?}", server.id, e);
+            }
+        }
+        Timer::after(Duration::from_secs(2)).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use smol::Timer;
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::{keepalive::unix_time_millis, SplinterSystem},
+};
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Idle Connection Reaper",
+        init: Box::new(|proxy| {
+            Box::pin(async move {
+                smol::spawn(async move {
+                    if let Err(e) = connection_reaper_loop(proxy).await {
+                        error!("Idle Connection Reaper encountered an error: {:?}", e);
+                    }
+                }).detach();
+                Ok(())
+            })
+        }),
+    }
+}
+
+pub async fn connection_reaper_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    let timeout = proxy.config().idle_connection_timeout as u128 * 1000;
+    loop {
+        if !proxy.alive.load(Ordering::Relaxed) {
+            break;
+        }
+        Timer::after(Duration::from_secs(15)).await;
+        let players = proxy
+            .players
+            .read()
+            .await
+            .iter()
+            .map(|(_, client)| Arc::clone(client))
+            .collect::<Vec<_>>();
+        let now = unix_time_millis();
+        for client in players.iter() {
+            // servers send keep alives regularly, so a dummy connection that has gone quiet is
+            // most likely half-open
+            for (server_id, conn) in client.dummy_servers.load().iter() {
+                if now.saturating_sub(*conn.last_activity.lock().await) > timeout {
+                    info!(
+                        "Closing idle connection between \"{}\" and server {}",
+                        &client.name, server_id
+                    );
+                    if let Err(e) = client.disconnect_dummy(*server_id).await {
+                        error!(
+                            "Failed to disconnect idle dummy {}-{}: {:?}",
+                            &client.name, server_id, e
+                        );
+                    }
+                }
+            }
+        }
+        // nobody sees the player info of servers nobody is connected to, and it would go stale
+        let connected = players
+            .iter()
+            .flat_map(|client| client.connected_servers())
+            .collect::<HashSet<u64>>();
+        proxy
+            .mapping
+            .lock()
+            .await
+            .player_info
+            .retain(|server_id, _| connected.contains(server_id));
+        // servers added by `connect` go away once nobody is on them
+        proxy.remove_unused_adhoc_servers().await;
+    }
+    Ok(())
+}
@@ -41,14 +41,7 @@ async fn eid_auto_removal_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()>
             // if there is no reference among any client to an eid, then we dont need that
             // mapping
             for proxy_eid in eids_for_removal {
-                if let Some((_, (server_id, server_eid))) = map.eids.remove_by_left(&proxy_eid) {
-                    debug!(
-                        "destroying map s->p ({}, {}) to {}",
-                        server_id, server_eid, proxy_eid
-                    );
-                    map.entity_data.remove(&proxy_eid);
-                    map.eid_gen.return_id(proxy_eid as u64);
-                }
+                map.free_eid(proxy_eid);
             }
         }
     })
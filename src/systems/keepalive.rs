@@ -17,11 +17,17 @@ use crate::{
             PacketLatest, PacketLatestKind, RawPacketLatest,
         },
         events::LazyDeserializedPacket,
-        v_cur::{has_eids, map_eid, send_packet, send_position_set},
+        v_cur::{
+            has_eids, map_eid, observe_packet, send_packet, send_position_set, ServerDimension,
+            EFFECT_KINDS,
+        },
         PacketDestination,
     },
     proxy::{
-        client::SplinterClient, mapping::SplinterMappingResult, server::SplinterServerConnection,
+        client::SplinterClient,
+        logging::Instrument,
+        mapping::{map_boss_bar_uuid, SplinterMappingResult},
+        server::SplinterServerConnection,
         ClientKickReason, SplinterProxy,
     },
     systems::SplinterSystem,
@@ -37,8 +43,9 @@ inventory::submit! {
 
 async fn keep_alive_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
     smol::spawn(async move {
+        let timeout = proxy.config().keep_alive_timeout as u128 * 1000;
         loop {
-            Timer::after(Duration::from_secs(15)).await;
+            Timer::after(Duration::from_secs(proxy.config().keep_alive_interval)).await;
             let players = proxy
                 .players
                 .read()
@@ -48,7 +55,9 @@ async fn keep_alive_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
                 .collect::<Vec<_>>();
             let keep_alive_millis = unix_time_millis();
             for client in players.iter() {
-                if keep_alive_millis - *client.last_keep_alive.lock().await > 30 * 1000 {
+                if keep_alive_millis.saturating_sub(*client.last_keep_alive.lock().await)
+                    > timeout
+                {
                     // client connection time out
                     if let Err(e) = proxy
                         .kick_client(&client.name, ClientKickReason::TimedOut)
@@ -96,6 +105,8 @@ pub fn unix_time_millis() -> u128 {
 }
 
 pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterServerConnection>) {
+    let client_span = client.log_span.clone();
+    let server_span = dummy_conn.server.log_span.clone();
     smol::spawn(async move {
         debug!("Starting dummy watch on {} for server {}", &client.name, dummy_conn.server.id);
         loop {
@@ -120,16 +131,30 @@ pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterSe
                     continue;
                 },
             };
+            dummy_conn.mark_activity().await;
             let mut lazy_packet = LazyDeserializedPacket::from_raw_packet(raw_packet);
             let packet_kind = lazy_packet.kind();
             let mut pass_through = false;
+            if client.proxy.is_replayed(packet_kind) {
+                if let Ok(packet) = lazy_packet.packet() {
+                    dummy_conn.record_state(packet).await;
+                }
+            }
+            if EFFECT_KINDS.contains(&packet_kind) {
+                if let Ok(packet) = lazy_packet.packet() {
+                    pass_through = client.wants_background_effect(packet);
+                }
+            }
             if matches!(packet_kind,
                 PacketLatestKind::PlayServerKeepAlive
                 | PacketLatestKind::PlayChunkData
                 | PacketLatestKind::PlayUpdateLight
                 | PacketLatestKind::PlayUnloadChunk
                 | PacketLatestKind::PlayServerPlayerPositionAndLook
-                | PacketLatestKind::PlayServerPluginMessage) {
+                | PacketLatestKind::PlayServerPluginMessage
+                | PacketLatestKind::PlayPlayerInfo
+                | PacketLatestKind::PlayRespawn
+                | PacketLatestKind::PlayBossBar) {
                 match lazy_packet.packet() {
                     Ok(packet) => match packet {
                         PacketLatest::PlayServerKeepAlive(body) => {
@@ -143,11 +168,11 @@ pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterSe
                         }
                         PacketLatest::PlayChunkData(body) => {
                             let chunk = (body.x, body.z);
-                            pass_through = pass_through || dummy_conn.update_chunk(&*client, true, chunk).await;
+                            pass_through = pass_through || dummy_conn.update_background_chunk(&*client, true, chunk).await;
                         },
                         PacketLatest::PlayUpdateLight(body) => {
                             let chunk = (*body.chunk.x, *body.chunk.z);
-                            pass_through = pass_through || dummy_conn.update_chunk(&*client, false, chunk).await;
+                            pass_through = pass_through || dummy_conn.update_background_chunk(&*client, false, chunk).await;
                         },
                         PacketLatest::PlayUnloadChunk(body) => {
                             let chunk = (body.position.x, body.position.z);
@@ -179,6 +204,20 @@ pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterSe
                                 }
                             }
                         },
+                        PacketLatest::PlayPlayerInfo(body) => {
+                            pass_through = client.relay_player_info(dummy_conn.server.id, &mut body.actions).await || pass_through;
+                        },
+                        PacketLatest::PlayRespawn(body) => {
+                            // the client is moved into this world if it switches to this server
+                            *dummy_conn.dimension.lock().await = Some(ServerDimension::from(&*body));
+                        },
+                        PacketLatest::PlayBossBar(body) => {
+                            dummy_conn.record_boss_bar(body).await;
+                            if client.shows_background_boss_bars() {
+                                body.uuid = map_boss_bar_uuid(dummy_conn.server.id, body.uuid);
+                                pass_through = true;
+                            }
+                        },
                         PacketLatest::PlayServerPluginMessage(_body) => {
                             // if body.channel == "splinter:splinter" {
                             //     match body.data.data[0] {
@@ -213,6 +252,12 @@ pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterSe
                     pass_through = pass_through || SplinterMappingResult::Client == map_eid(&*client, map, packet, &PacketDirection::ClientBound, &dummy_conn.server);
                 }
             }
+            let destination = if pass_through {
+                PacketDestination::Client
+            } else {
+                PacketDestination::None
+            };
+            observe_packet(&client.proxy, &dummy_conn, &client, &PacketDirection::ClientBound, &lazy_packet, &destination);
             if pass_through {
                 if let Err(e) = send_packet(&client, &PacketDestination::Client, lazy_packet)
                     .await
@@ -227,7 +272,22 @@ pub async fn watch_dummy(client: Arc<SplinterClient>, dummy_conn: Arc<SplinterSe
             }
         }
         client.grab_dummy(dummy_conn.server.id).ok();
+        // take away the entries only this connection's server gave the client
+        if client.alive.load(Ordering::Relaxed) {
+            if let Err(e) = client.sync_player_info(&[]).await {
+                error!("Failed to send player info to \"{}\": {:?}", &client.name, e);
+            }
+            // the boss bars of a server the client is no longer connected to would never go away
+            let is_active = Arc::ptr_eq(&*client.active_server.load(), &dummy_conn);
+            if client.shows_background_boss_bars() && !is_active {
+                if let Err(e) = client.hide_boss_bars(&dummy_conn).await {
+                    error!("Failed to take boss bars from \"{}\": {:?}", &client.name, e);
+                }
+            }
+        }
         debug!("Closing dummy watch on {} for server {}", &client.name, dummy_conn.server.id);
-    })
+    }
+    .instrument(server_span)
+    .instrument(client_span))
     .detach()
 }
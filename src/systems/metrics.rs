@@ -0,0 +1,104 @@
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use futures_lite::future;
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    Timer,
+};
+
+use crate::{proxy::SplinterProxy, systems::SplinterSystem};
+
+/// Largest request head read before the request is refused
+const MAX_REQUEST_LEN: usize = 8192;
+/// How long a scraper has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Metrics Endpoint",
+        init: Box::new(|proxy| {
+            Box::pin(init(proxy))
+        }),
+    }
+}
+
+async fn init(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    let address = match &proxy.config().metrics_address {
+        Some(address) => SocketAddr::from_str(address)?,
+        None => return Ok(()),
+    };
+    let listener = TcpListener::bind(address).await?;
+    info!("Serving metrics on http://{}/metrics", address);
+    smol::spawn(async move {
+        loop {
+            // wake up every so often to see if we should stop listening
+            let accepted = future::or(async { Some(listener.accept().await) }, async {
+                Timer::after(Duration::from_secs(1)).await;
+                None
+            })
+            .await;
+            if !proxy.is_alive() {
+                break;
+            }
+            let (stream, addr) = match accepted {
+                Some(Ok(s)) => s,
+                Some(Err(e)) => {
+                    error!("Failed to accept a metrics request: {}", e);
+                    continue;
+                }
+                None => continue,
+            };
+            let proxy = Arc::clone(&proxy);
+            smol::spawn(async move {
+                if let Err(e) = handle(&proxy, stream).await {
+                    debug!("Metrics request from {} failed: {:?}", addr, e);
+                }
+            })
+            .detach();
+        }
+    })
+    .detach();
+    Ok(())
+}
+
+/// Answers a single HTTP request, then closes the connection
+async fn handle(proxy: &SplinterProxy, mut stream: TcpStream) -> anyhow::Result<()> {
+    let head = future::or(read_request_head(&mut stream), async {
+        Timer::after(REQUEST_TIMEOUT).await;
+        Err(anyhow!("Timed out reading request"))
+    })
+    .await?;
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", proxy.metrics.render(proxy).await),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_owned()),
+        _ => ("405 Method Not Allowed", "Method not allowed\n".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Reads up to the blank line ending the request's headers
+async fn read_request_head(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            bail!("Connection closed before the request was complete");
+        }
+        head.extend_from_slice(&buf[..len]);
+        if head.len() > MAX_REQUEST_LEN {
+            bail!("Request is too large");
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
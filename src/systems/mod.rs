@@ -7,10 +7,17 @@ use smol::prelude::Future;
 
 use crate::proxy::SplinterProxy;
 
+pub mod backendstatus;
 pub mod commands;
+pub mod connectionpool;
+pub mod connectionreaper;
 pub mod eidautoremoval;
 pub mod keepalive;
+pub mod metrics;
 pub mod playersave;
+pub mod rcon;
+pub mod restart;
+pub mod tablist;
 pub mod zoning;
 
 pub type SystemInitFn = Box<
@@ -1,16 +1,23 @@
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::Write,
+    io::{Read, Write},
+    path::Path,
     sync::{atomic::Ordering, Arc},
     time::Duration,
 };
 
+use anyhow::Context;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
 use smol::Timer;
 
-use crate::{protocol::current::uuid::UUID4, proxy::SplinterProxy, systems::SplinterSystem};
+use crate::{
+    protocol::current::{types::Vec3, uuid::UUID4},
+    proxy::SplinterProxy,
+    systems::SplinterSystem,
+};
 
 inventory::submit! {
     SplinterSystem {
@@ -30,6 +37,8 @@ inventory::submit! {
 
 pub const PLAYER_DATA_FILENAME: &str = "./playerdata.ron";
 pub const DEFAULT_SPAWN_POSITION: (f64, f64, f64) = (0., 8., 0.);
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PlInfoPlayer {
@@ -37,6 +46,48 @@ pub struct PlInfoPlayer {
     pub y: f64,
     pub z: f64,
     pub name: String,
+    /// Selected hotbar slot, from 0 to 8
+    #[serde(default)]
+    pub held_slot: i8,
+    /// Items in the player's inventory window, as their server last sent them. Backend servers
+    /// keep their own inventories, so this is a record for tools reading the player data and is
+    /// not restored on join
+    #[serde(default)]
+    pub inventory: Vec<PlInfoItem>,
+}
+impl PlInfoPlayer {
+    /// Record of a player at the given position, with their inventory's items sorted by slot
+    pub fn new(
+        name: &str,
+        position: &Vec3<f64>,
+        held_slot: i8,
+        inventory: &HashMap<i16, Vec<u8>>,
+    ) -> Self {
+        let mut inventory = inventory
+            .iter()
+            .map(|(slot, data)| PlInfoItem {
+                slot: *slot,
+                data: data.clone(),
+            })
+            .collect::<Vec<PlInfoItem>>();
+        inventory.sort_by_key(|item| item.slot);
+        Self {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            name: name.to_owned(),
+            held_slot,
+            inventory,
+        }
+    }
+}
+/// An item stack in a player's inventory window
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlInfoItem {
+    /// Slot of the inventory window the item is in
+    pub slot: i16,
+    /// Item stack as serialized in slot data by protocol 756 (1.17.1)
+    pub data: Vec<u8>,
 }
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PlInfo {
@@ -56,20 +107,169 @@ pub async fn player_save_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
             break;
         }
         Timer::after(Duration::from_secs(30)).await;
-        if let Err(e) = save_player_data(&*proxy.player_data.lock().await, PLAYER_DATA_FILENAME) {
-            error!("Player Saver error when reading file: {:?}", e);
+        if let Err(e) = save_player_data(
+            &*proxy.player_data.lock().await,
+            PLAYER_DATA_FILENAME,
+            proxy.config().compress_player_data,
+        ) {
+            error!("Player Saver error when writing file: {:?}", e);
         }
     }
     Ok(())
 }
+/// Reads player data, decompressing it first if it was saved gzipped
 pub fn load_player_data(filename: impl AsRef<str>) -> anyhow::Result<PlInfo> {
-    let existing_file = fs::read_to_string(filename.as_ref())?;
+    let bytes = fs::read(filename.as_ref())?;
+    let existing_file = if bytes.starts_with(&GZIP_MAGIC) {
+        let mut data = String::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut data)
+            .context("Failed to decompress player data")?;
+        data
+    } else {
+        String::from_utf8(bytes)?
+    };
     let existing_plinfo: PlInfo = ron::de::from_str(&existing_file)?;
     Ok(existing_plinfo)
 }
-pub fn save_player_data(info: &PlInfo, filename: impl AsRef<str>) -> anyhow::Result<()> {
+pub fn save_player_data(
+    info: &PlInfo,
+    filename: impl AsRef<str>,
+    compress: bool,
+) -> anyhow::Result<()> {
     debug!("saving player data...");
-    File::create(filename.as_ref())?
-        .write_all(ron::ser::to_string_pretty(info, PrettyConfig::default())?.as_bytes())
-        .map_err(anyhow::Error::new)
+    let data = ron::ser::to_string_pretty(info, PrettyConfig::default())?;
+    if compress {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data.as_bytes())?;
+        write_file_atomic(&encoder.finish()?, filename.as_ref())
+    } else {
+        write_file_atomic(data.as_bytes(), filename.as_ref())
+    }
+}
+/// Writes player data as JSON to a path other than the main save file, returning the number of
+/// players written
+pub fn export_player_data(info: &PlInfo, filename: impl AsRef<str>) -> anyhow::Result<usize> {
+    let path = Path::new(filename.as_ref());
+    if filename.as_ref().is_empty() || path.is_dir() {
+        bail!("\"{}\" is not a file path", filename.as_ref());
+    }
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            bail!("Directory \"{}\" does not exist", parent.display());
+        }
+    }
+    if let (Ok(save_path), Ok(export_path)) = (
+        fs::canonicalize(PLAYER_DATA_FILENAME),
+        fs::canonicalize(path),
+    ) {
+        if save_path == export_path {
+            bail!("Cannot export over the main player data file");
+        }
+    }
+    let mut players = json::JsonValue::new_object();
+    for (uuid, player) in info.players.iter() {
+        players[uuid.to_string()] = json::object! {
+            name: player.name.as_str(),
+            x: player.x,
+            y: player.y,
+            z: player.z,
+            held_slot: player.held_slot,
+        };
+    }
+    write_file_atomic(
+        json::stringify_pretty(json::object! { players: players }, 4).as_bytes(),
+        path,
+    )?;
+    Ok(info.players.len())
+}
+/// Writes the data to a temporary file next to the target and renames it over the target, so
+/// that the target holds either the old data or the new data even if the proxy dies mid-write
+pub fn write_file_atomic(data: &[u8], filename: impl AsRef<Path>) -> anyhow::Result<()> {
+    let filename = filename.as_ref();
+    let mut temp_filename = filename.as_os_str().to_owned();
+    temp_filename.push(".tmp");
+    let temp_filename = Path::new(&temp_filename);
+    let mut file = File::create(temp_filename)
+        .with_context(|| format!("Failed to create \"{}\"", temp_filename.display()))?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(temp_filename, filename).with_context(|| {
+        format!(
+            "Failed to move \"{}\" to \"{}\"",
+            temp_filename.display(),
+            filename.display()
+        )
+    })?;
+    // the rename itself is only durable once the directory is synced
+    let dir = match filename.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync directory \"{}\"", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of a file in the system temp directory, unique to the test
+    fn temp_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("splinter-{}-{}.ron", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn player_data(name: &str) -> PlInfo {
+        let mut info = PlInfo::default();
+        info.players.insert(
+            UUID4::from(1u128),
+            PlInfoPlayer {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+                name: name.to_owned(),
+                held_slot: 4,
+                inventory: vec![],
+            },
+        );
+        info
+    }
+
+    #[test]
+    fn interrupted_save_keeps_old_data() {
+        let path = temp_path("interrupted");
+        save_player_data(&player_data("old"), &path, false).unwrap();
+        // a save that died mid-write leaves a truncated temporary file behind
+        let new_data =
+            ron::ser::to_string_pretty(&player_data("new"), PrettyConfig::default()).unwrap();
+        fs::write(format!("{}.tmp", path), &new_data[..new_data.len() / 2]).unwrap();
+        let loaded = load_player_data(&path).unwrap();
+        assert_eq!(loaded.players[&UUID4::from(1u128)].name, "old");
+        // the next save replaces the leftover
+        save_player_data(&player_data("new"), &path, true).unwrap();
+        let loaded = load_player_data(&path).unwrap();
+        assert_eq!(loaded.players[&UUID4::from(1u128)].name, "new");
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn records_player_position_and_name() {
+        let inventory = vec![(36, vec![1, 2]), (9, vec![3])].into_iter().collect();
+        let player = PlInfoPlayer::new("alice", &Vec3::from((1.5, 64., -3.)), 2, &inventory);
+        assert_eq!((player.x, player.y, player.z), (1.5, 64., -3.));
+        assert_eq!(player.name, "alice");
+        assert_eq!(player.held_slot, 2);
+        assert_eq!(
+            player
+                .inventory
+                .iter()
+                .map(|item| (item.slot, item.data.clone()))
+                .collect::<Vec<(i16, Vec<u8>)>>(),
+            vec![(9, vec![3]), (36, vec![1, 2])]
+        );
+    }
 }
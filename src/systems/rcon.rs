@@ -0,0 +1,260 @@
+use std::{
+    convert::TryFrom,
+    io::ErrorKind,
+    net::SocketAddr,
+    str::{self, FromStr},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_lite::future;
+use smol::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    lock::Mutex,
+    net::{TcpListener, TcpStream},
+    Timer,
+};
+
+use crate::{
+    proxy::SplinterProxy,
+    systems::{
+        commands::{process_command, CommandSender},
+        SplinterSystem,
+    },
+};
+
+const LOGIN: i32 = 3;
+const EXEC_COMMAND: i32 = 2;
+const AUTH_RESPONSE: i32 = 2;
+const RESPONSE_VALUE: i32 = 0;
+/// Smallest packet length: the id, the type, and the two null bytes ending an empty body
+const MIN_PACKET_LEN: usize = 10;
+/// Largest packet length accepted from clients, the same as the vanilla server's
+const MAX_PACKET_LEN: usize = 1460;
+/// Largest body sent in a single response packet. Longer output is split over several packets
+const MAX_RESPONSE_BODY: usize = 4096;
+/// Longest a client may go without sending a packet before its connection is closed
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Remote Console Listener",
+        init: Box::new(|proxy| {
+            Box::pin(init(proxy))
+        }),
+    }
+}
+
+async fn init(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    let config = &proxy.config().rcon;
+    if !config.enabled {
+        return Ok(());
+    }
+    if config.password.is_empty() {
+        warn!("Not starting the remote console listener since no password is set");
+        return Ok(());
+    }
+    let listener = TcpListener::bind(SocketAddr::from_str(&config.address)?).await?;
+    info!(
+        "Listening for remote console connections on {}",
+        &config.address
+    );
+    smol::spawn(async move {
+        loop {
+            // wake up every so often to see if we should stop listening
+            let accepted = future::or(async { Some(listener.accept().await) }, async {
+                Timer::after(Duration::from_secs(1)).await;
+                None
+            })
+            .await;
+            if !proxy.is_alive() {
+                break;
+            }
+            let (stream, addr) = match accepted {
+                Some(Ok(s)) => s,
+                Some(Err(e)) => {
+                    error!("Failed to accept a remote console client: {}", e);
+                    continue;
+                }
+                None => continue,
+            };
+            let proxy = Arc::clone(&proxy);
+            smol::spawn(async move {
+                if let Err(e) = handle(&proxy, stream, addr).await {
+                    warn!("Remote console connection from {} failed: {:?}", addr, e);
+                }
+            })
+            .detach();
+        }
+    })
+    .detach();
+    Ok(())
+}
+
+struct RconPacket {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+/// Runs commands for a remote console client until it disconnects, goes quiet for too long, or
+/// fails to log in
+async fn handle(
+    proxy: &Arc<SplinterProxy>,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+) -> anyhow::Result<()> {
+    let mut authenticated = false;
+    while let Some(packet) = future::or(read_packet(&mut stream), async {
+        Timer::after(READ_TIMEOUT).await;
+        Err(anyhow!("No packet in {} seconds", READ_TIMEOUT.as_secs()))
+    })
+    .await?
+    {
+        match packet.kind {
+            LOGIN => {
+                authenticated = passwords_match(&packet.body, &proxy.config().rcon.password);
+                if authenticated {
+                    info!("Remote console client {} logged in", addr);
+                    write_packet(&mut stream, packet.id, AUTH_RESPONSE, "").await?;
+                } else {
+                    // one attempt per connection, so guessing costs a reconnect each time
+                    warn!("Remote console client {} used the wrong password", addr);
+                    write_packet(&mut stream, -1, AUTH_RESPONSE, "").await?;
+                    break;
+                }
+            }
+            EXEC_COMMAND if authenticated => {
+                info!("Remote console client {} ran \"{}\"", addr, &packet.body);
+                let output = run_command(proxy, addr, &packet.body).await;
+                for chunk in split_body(&output) {
+                    write_packet(&mut stream, packet.id, RESPONSE_VALUE, chunk).await?;
+                }
+            }
+            EXEC_COMMAND => write_packet(&mut stream, -1, AUTH_RESPONSE, "").await?,
+            kind => bail!("Unknown packet type {}", kind),
+        }
+    }
+    Ok(())
+}
+
+/// Compares a given password to the configured one in time that doesn't depend on where they
+/// differ
+fn passwords_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Runs a command line as the remote console client, returning everything it responded with
+async fn run_command(proxy: &Arc<SplinterProxy>, addr: SocketAddr, line: &str) -> String {
+    let mut split = line.split_whitespace();
+    let cmd = match split.next() {
+        Some(cmd) => cmd,
+        None => return String::new(),
+    };
+    let args = split.collect::<Vec<&str>>();
+    let output = Arc::new(Mutex::new(String::new()));
+    let sender = CommandSender::Rcon(addr, Arc::clone(&output));
+    if let Err(e) = process_command(proxy, cmd, args.as_slice(), &sender).await {
+        if let Err(e) = sender.respond(format!("Command failed: {:?}", e)).await {
+            error!(
+                "Failed to send command failure message to {}: {}",
+                sender.name(),
+                e
+            );
+        }
+    }
+    let output = output.lock().await;
+    output.clone()
+}
+
+/// Reads a packet, or `None` if the client closed the connection. Packets with a length out of
+/// bounds or a body that isn't null terminated UTF-8 are errors
+async fn read_packet(stream: &mut TcpStream) -> anyhow::Result<Option<RconPacket>> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = i32::from_le_bytes(len_buf);
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| (MIN_PACKET_LEN..=MAX_PACKET_LEN).contains(len))
+        .ok_or_else(|| anyhow!("Invalid packet length {}", len))?;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    if buf[len - 2..] != [0, 0] {
+        bail!("Packet body is not null terminated");
+    }
+    Ok(Some(RconPacket {
+        id: i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        kind: i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        body: str::from_utf8(&buf[8..len - 2])?.to_owned(),
+    }))
+}
+
+async fn write_packet(
+    stream: &mut TcpStream,
+    id: i32,
+    kind: i32,
+    body: &str,
+) -> anyhow::Result<()> {
+    let len = i32::try_from(MIN_PACKET_LEN + body.len())?;
+    let mut buf = Vec::with_capacity(4 + MIN_PACKET_LEN + body.len());
+    buf.extend(len.to_le_bytes());
+    buf.extend(id.to_le_bytes());
+    buf.extend(kind.to_le_bytes());
+    buf.extend(body.as_bytes());
+    buf.extend([0, 0]);
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Splits a response body into pieces that each fit in a packet, without splitting characters.
+/// An empty body is still a single, empty piece
+fn split_body(mut body: &str) -> Vec<&str> {
+    let mut chunks = vec![];
+    while body.len() > MAX_RESPONSE_BODY {
+        let mut end = MAX_RESPONSE_BODY;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, rest) = body.split_at(end);
+        chunks.push(chunk);
+        body = rest;
+    }
+    chunks.push(body);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_passwords() {
+        assert!(passwords_match("hunter2", "hunter2"));
+        assert!(passwords_match("", ""));
+    }
+
+    #[test]
+    fn mismatched_passwords() {
+        assert!(!passwords_match("hunter3", "hunter2"));
+        assert!(!passwords_match("hunter", "hunter2"));
+        assert!(!passwords_match("hunter22", "hunter2"));
+    }
+
+    #[test]
+    fn long_body_splits_on_char_boundaries() {
+        let body = "é".repeat(MAX_RESPONSE_BODY);
+        let chunks = split_body(&body);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= MAX_RESPONSE_BODY));
+        assert_eq!(chunks.concat(), body);
+    }
+}
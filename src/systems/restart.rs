@@ -0,0 +1,74 @@
+use std::{sync::Arc, time::Duration};
+
+use smol::Timer;
+
+use crate::{
+    proxy::{chat::broadcast_message, ClientKickReason, SplinterProxy},
+    systems::{commands::CommandSender, SplinterSystem},
+};
+
+/// Seconds before a scheduled restart at which players are warned
+const RESTART_WARNINGS: [u64; 9] = [600, 300, 120, 60, 30, 10, 3, 2, 1];
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Scheduled Restart",
+        init: Box::new(|proxy| {
+            Box::pin(async move {
+                if let Some(interval) = proxy.config().restart_interval {
+                    smol::spawn(restart_loop(proxy, Duration::from_secs(interval))).detach();
+                }
+                Ok(())
+            })
+        }),
+    }
+}
+
+async fn restart_loop(proxy: Arc<SplinterProxy>, interval: Duration) {
+    info!("Scheduled restart in {}", format_duration(interval));
+    for warning in RESTART_WARNINGS.iter() {
+        let warning = Duration::from_secs(*warning);
+        let until_restart = interval.saturating_sub(proxy.uptime());
+        if until_restart < warning {
+            continue;
+        }
+        Timer::after(until_restart - warning).await;
+        if !proxy.is_alive() {
+            return;
+        }
+        broadcast_message(
+            &proxy,
+            &CommandSender::Console,
+            format!("Proxy restarting in {}", format_duration(warning)),
+        )
+        .await;
+    }
+    Timer::after(interval.saturating_sub(proxy.uptime())).await;
+    if proxy.is_alive() {
+        info!("Restarting on schedule");
+        proxy.shutdown(ClientKickReason::Restart).await;
+    }
+}
+
+/// Formats a duration as days, hours, minutes, and seconds, leaving out leading zero units
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let (days, hours, minutes, seconds) = (
+        total / 86400,
+        total / 3600 % 24,
+        total / 60 % 60,
+        total % 60,
+    );
+    let mut parts = vec![];
+    if days > 0 {
+        parts.push(format!("{}d", days));
+    }
+    if days > 0 || hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if days > 0 || hours > 0 || minutes > 0 {
+        parts.push(format!("{}m", minutes));
+    }
+    parts.push(format!("{}s", seconds));
+    parts.join(" ")
+}
@@ -0,0 +1,47 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use smol::Timer;
+
+use crate::{proxy::SplinterProxy, systems::SplinterSystem};
+
+inventory::submit! {
+    SplinterSystem {
+        name: "Tab List",
+        init: Box::new(|proxy| {
+            Box::pin(async move {
+                smol::spawn(async move {
+                    if let Err(e) = tab_list_loop(proxy).await {
+                        error!("Tab List encountered an error: {:?}", e);
+                    }
+                }).detach();
+                Ok(())
+            })
+        }),
+    }
+}
+
+/// Periodically resends every client's tab list so that its placeholders stay up to date
+pub async fn tab_list_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
+    loop {
+        Timer::after(Duration::from_secs(proxy.config().tab_list.update_interval)).await;
+        if !proxy.alive.load(Ordering::Relaxed) {
+            break;
+        }
+        let players = proxy
+            .players
+            .read()
+            .await
+            .iter()
+            .map(|(_, client)| Arc::clone(client))
+            .collect::<Vec<_>>();
+        for client in players {
+            if let Err(e) = client.send_tab_list().await {
+                error!("Failed to send tab list to \"{}\": {:?}", &client.name, e);
+            }
+        }
+    }
+    Ok(())
+}
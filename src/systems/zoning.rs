@@ -1,9 +1,15 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 use smallvec::SmallVec;
 use smol::Timer;
 
-use crate::{proxy::SplinterProxy, systems::SplinterSystem};
+use crate::{
+    proxy::{client::SplinterClient, SplinterProxy},
+    systems::{keepalive::unix_time_millis, SplinterSystem},
+};
 
 pub enum Zone {
     Rectangle { x1: i32, z1: i32, x2: i32, z2: i32 },
@@ -27,9 +33,24 @@ impl Zone {
 
 pub struct Zoner {
     pub zones: Vec<(u64, Zone)>,
+    /// Server that owns chunks no zone covers
+    pub default_server: u64,
 }
 
 impl Zoner {
+    /// Gets the id of the server that owns the given chunk position: the first zone containing
+    /// it, or the default server if there is none
+    pub fn server_for(&self, x: i32, z: i32) -> u64 {
+        self.zones
+            .iter()
+            .find(|(_, zone)| zone.point_in_zone(x, z))
+            .map(|(server_id, _)| *server_id)
+            .unwrap_or(self.default_server)
+    }
+    /// Whether zoning can send players to the server, as the default server or a zone's server
+    pub fn uses_server(&self, server_id: u64) -> bool {
+        self.default_server == server_id || self.zones.iter().any(|(id, _)| *id == server_id)
+    }
     pub fn zones_in_point(&self, (x, z): (i32, i32)) -> SmallVec<[u64; 2]> {
         let mut ids = SmallVec::new();
         for (server_id, zone) in self.zones.iter() {
@@ -39,6 +60,31 @@ impl Zoner {
         }
         return ids;
     }
+    /// Whether the server owns a chunk within `margin` blocks of the position along either axis
+    pub fn server_near(&self, server_id: u64, (x, z): (f64, f64), margin: f64) -> bool {
+        // sample at most a chunk apart so that no chunk in the margin is skipped
+        let steps = (margin / 16.).ceil().max(0.) as i32;
+        for i in -steps..=steps {
+            for j in -steps..=steps {
+                let (dx, dz) = if steps == 0 {
+                    (0., 0.)
+                } else {
+                    (
+                        margin * i as f64 / steps as f64,
+                        margin * j as f64 / steps as f64,
+                    )
+                };
+                let chunk = world_to_chunk_position((x + dx, z + dz));
+                let owners = self.zones_in_point(chunk);
+                if owners.contains(&server_id)
+                    || (owners.is_empty() && self.default_server == server_id)
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
 }
 
 inventory::submit! {
@@ -61,13 +107,26 @@ pub async fn zoner_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
     loop {
         Timer::after(Duration::from_secs(1)).await;
         for (_, cl) in proxy.players.read().await.iter() {
+            if cl.in_limbo.load(Ordering::Relaxed) {
+                continue;
+            }
             let pl_pos = &**cl.position.load();
+            let servers = match **cl.pinned_server.load() {
+                Some(server_id) => SmallVec::from_slice(&[server_id]),
+                None => {
+                    let (x, z) = world_to_chunk_position((pl_pos.x, pl_pos.z));
+                    let mut servers = proxy.zoner.zones_in_point((x, z));
+                    if servers.is_empty() {
+                        servers.push(proxy.zoner.server_for(x, z));
+                    }
+                    if should_stay(&proxy, cl, (pl_pos.x, pl_pos.z), &servers).await {
+                        servers.insert(0, cl.server_id());
+                    }
+                    servers
+                }
+            };
             if let Err(e) = cl
-                .update_touching_servers(
-                    proxy
-                        .zoner
-                        .zones_in_point(world_to_chunk_position((pl_pos.x, pl_pos.z))),
-                )
+                .update_touching_servers(servers)
                 .await
             {
                 error!(
@@ -78,3 +137,123 @@ pub async fn zoner_loop(proxy: Arc<SplinterProxy>) -> anyhow::Result<()> {
         }
     }
 }
+
+/// Whether zoning should keep the client on its active server even though the server doesn't own
+/// the client's position: the client is still within the switch margin of the server's zones, or
+/// it was moved to the server too recently
+async fn should_stay(
+    proxy: &SplinterProxy,
+    client: &SplinterClient,
+    position: (f64, f64),
+    servers: &[u64],
+) -> bool {
+    let active_id = client.server_id();
+    if servers.contains(&active_id) {
+        return false;
+    }
+    if proxy
+        .zoner
+        .server_near(active_id, position, proxy.config().zone_switch_margin)
+    {
+        return true;
+    }
+    let since_switch = unix_time_millis().saturating_sub(*client.last_server_switch.lock().await);
+    since_switch < proxy.config().zone_switch_cooldown as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zoner() -> Zoner {
+        Zoner {
+            zones: vec![
+                (
+                    1,
+                    Zone::Rectangle {
+                        x1: 0,
+                        z1: 0,
+                        x2: 10,
+                        z2: 10,
+                    },
+                ),
+                (
+                    2,
+                    Zone::Rectangle {
+                        x1: 5,
+                        z1: 5,
+                        x2: 20,
+                        z2: 20,
+                    },
+                ),
+            ],
+            default_server: 7,
+        }
+    }
+
+    #[test]
+    fn matched_point_gets_its_zone_server() {
+        assert_eq!(zoner().server_for(3, 3), 1);
+        assert_eq!(zoner().server_for(15, 15), 2);
+    }
+
+    #[test]
+    fn overlapping_zones_go_to_the_first() {
+        assert_eq!(zoner().server_for(7, 7), 1);
+    }
+
+    #[test]
+    fn unmatched_point_gets_default_server() {
+        assert_eq!(zoner().server_for(-1, 3), 7);
+        assert_eq!(zoner().server_for(100, 100), 7);
+    }
+
+    #[test]
+    fn zone_boundaries_include_start_and_exclude_end() {
+        let zoner = zoner();
+        assert_eq!(zoner.server_for(0, 0), 1);
+        assert_eq!(zoner.server_for(9, 0), 1);
+        assert_eq!(zoner.server_for(10, 0), 7);
+        assert_eq!(zoner.server_for(0, 10), 7);
+        assert_eq!(zoner.server_for(19, 19), 2);
+        assert_eq!(zoner.server_for(20, 19), 7);
+    }
+
+    #[test]
+    fn inverted_zone_matches_outside() {
+        let zoner = Zoner {
+            zones: vec![(
+                3,
+                Zone::InvertedRectangle {
+                    x1: 0,
+                    z1: 0,
+                    x2: 10,
+                    z2: 10,
+                },
+            )],
+            default_server: 7,
+        };
+        assert_eq!(zoner.server_for(10, 10), 3);
+        assert_eq!(zoner.server_for(9, 9), 7);
+    }
+
+    #[test]
+    fn uses_zone_and_default_servers() {
+        let zoner = zoner();
+        assert!(zoner.uses_server(1));
+        assert!(zoner.uses_server(2));
+        assert!(zoner.uses_server(7));
+        assert!(!zoner.uses_server(3));
+    }
+
+    #[test]
+    fn server_near_checks_chunks_within_margin() {
+        let zoner = zoner();
+        // chunk 0..10 belongs to server 1, so its blocks end at x = 160
+        assert!(zoner.server_near(1, (150., 8.), 0.));
+        assert!(!zoner.server_near(1, (-20., 8.), 0.));
+        assert!(zoner.server_near(1, (-20., 8.), 24.));
+        assert!(!zoner.server_near(2, (8., 8.), 16.));
+        assert!(zoner.server_near(7, (-20., 8.), 0.));
+    }
+}